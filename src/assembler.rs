@@ -0,0 +1,535 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::data_section::{parse_number, strip_comment};
+use crate::disassembler::REGISTER_NAMES;
+
+// Source line and the words it was assembled to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListingEntry
+{
+    pub line: usize, // 1-based
+    pub address: u32,
+    pub words: Vec<u32>,
+    pub text: String,
+}
+
+pub struct Assembly
+{
+    pub base: u32,
+    pub words: Vec<u32>,
+    pub labels: BTreeMap<String, u32>,
+    pub listing: Vec<ListingEntry>,
+}
+
+impl Assembly
+{
+    pub fn to_bytes(&self) -> Vec<u8>
+    {
+        self.words.iter().flat_map(|word| word.to_be_bytes()).collect()
+    }
+
+    // Address of the first word generated by the source line.
+    pub fn line_address(&self, line: usize) -> Option<u32>
+    {
+        self.listing.iter()
+            .find(|entry| entry.line == line)
+            .map(|entry| entry.address)
+    }
+}
+
+/*
+    Assembles MIPS32 source placed at the base address, one instruction per line.
+    Labels end with a colon, comments start with #. The pseudo instructions nop, move, li, la,
+    b, beqz, bnez, not and neg are expanded like MARS does, li takes one or two words
+    depending on the value, la always takes two.
+ */
+pub fn assemble(source: &str, base: u32) -> Result<Assembly, String>
+{
+    let mut lines = Vec::new(); // (line number, label-free text)
+    let mut labels = BTreeMap::new();
+    let mut address = base;
+
+    // First pass: addresses of the labels.
+    for (index, line) in source.lines().enumerate()
+    {
+        let number = index + 1;
+        let mut text = strip_comment(line).trim();
+
+        while let Some((label, rest)) = text.split_once(':')
+        {
+            let label = label.trim();
+            if !is_identifier(label)
+            {
+                break;
+            }
+            if labels.insert(label.to_string(), address).is_some()
+            {
+                return Err(format!("Line {}: Duplicate label: {}", number, label));
+            }
+            text = rest.trim();
+        }
+
+        if text.is_empty()
+        {
+            continue;
+        }
+
+        let size = instruction_size(text)
+            .map_err(|error| format!("Line {}: {}", number, error))?;
+        lines.push((number, text, address));
+        address = address.wrapping_add(4 * size);
+    }
+
+    // Second pass: encoding.
+    let mut assembly = Assembly
+    {
+        base,
+        words: Vec::new(),
+        labels,
+        listing: Vec::new(),
+    };
+
+    for (number, text, address) in lines
+    {
+        let words = encode(text, address, &assembly.labels)
+            .map_err(|error| format!("Line {}: {}", number, error))?;
+        assembly.words.extend(&words);
+        assembly.listing.push(ListingEntry
+        {
+            line: number,
+            address,
+            words,
+            text: text.to_string(),
+        });
+    }
+
+    Ok(assembly)
+}
+
+fn is_identifier(text: &str) -> bool
+{
+    let mut chars = text.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '.') &&
+        chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+fn split_instruction(text: &str) -> (&str, Vec<&str>)
+{
+    let (mnemonic, operands) = text.split_once(char::is_whitespace)
+        .unwrap_or((text, ""));
+    let operands = operands.split(',')
+        .map(str::trim)
+        .filter(|operand| !operand.is_empty())
+        .collect();
+
+    (mnemonic, operands)
+}
+
+fn instruction_size(text: &str) -> Result<u32, String>
+{
+    let (mnemonic, operands) = split_instruction(text);
+    match mnemonic
+    {
+        "la" => Ok(2),
+        "li" =>
+        {
+            let value = operands.get(1).ok_or("Missing immediate")?;
+            Ok(li_words(parse_number(value)?).len() as u32)
+        },
+        _ => Ok(1),
+    }
+}
+
+// The immediates li is split into: one addiu/ori or a lui and ori pair.
+fn li_words(value: u32) -> Vec<(u32, u32)> // (opcode, immediate)
+{
+    let signed = value as i32;
+    if (-0x8000..0x8000).contains(&signed)
+    {
+        vec![(9, value & 0xFFFF)] // addiu
+    }
+    else if value <= 0xFFFF
+    {
+        vec![(13, value)] // ori
+    }
+    else
+    {
+        vec![(15, value >> 16), (13, value & 0xFFFF)] // lui, ori
+    }
+}
+
+fn register(operand: &str) -> Result<u32, String>
+{
+    let name = operand.trim();
+    if let Some(number) = REGISTER_NAMES.iter().position(|&register| register == name)
+    {
+        return Ok(number as u32);
+    }
+
+    match name.strip_prefix('$').map(str::parse::<u32>)
+    {
+        Some(Ok(number)) if number < 32 => Ok(number),
+        _ => match name
+        {
+            "$s8" => Ok(30),
+            _ => Err(format!("Bad register: {}", operand)),
+        },
+    }
+}
+
+fn fp_register(operand: &str) -> Result<u32, String>
+{
+    match operand.trim().strip_prefix("$f").map(str::parse::<u32>)
+    {
+        Some(Ok(number)) if number < 32 => Ok(number),
+        _ => Err(format!("Bad FP register: {}", operand)),
+    }
+}
+
+// offset(base) memory operand
+fn memory_operand(operand: &str) -> Result<(u32, u32), String>
+{
+    let (offset, base) = operand.strip_suffix(')')
+        .and_then(|operand| operand.split_once('('))
+        .ok_or(format!("Bad memory operand: {}", operand))?;
+
+    let offset = match offset.trim()
+    {
+        "" => 0,
+        offset => parse_number(offset)?,
+    };
+
+    Ok((offset, register(base)?))
+}
+
+fn address_of(operand: &str, labels: &BTreeMap<String, u32>) -> Result<u32, String>
+{
+    match labels.get(operand.trim())
+    {
+        Some(&address) => Ok(address),
+        None => parse_number(operand).map_err(|_| format!("Unknown label: {}", operand)),
+    }
+}
+
+fn branch_offset(operand: &str, address: u32, labels: &BTreeMap<String, u32>) -> Result<u32, String>
+{
+    let target = address_of(operand, labels)?;
+    let offset = (target.wrapping_sub(address.wrapping_add(4)) as i32) >> 2;
+    if !(-0x8000..0x8000).contains(&offset)
+    {
+        return Err(format!("Branch target out of range: {}", operand));
+    }
+
+    Ok(offset as u32 & 0xFFFF)
+}
+
+fn r_type(funct: u32, rs: u32, rt: u32, rd: u32, shamt: u32) -> u32
+{
+    (rs << 21) | (rt << 16) | (rd << 11) | (shamt << 6) | funct
+}
+
+fn i_type(opcode: u32, rs: u32, rt: u32, imm: u32) -> u32
+{
+    (opcode << 26) | (rs << 21) | (rt << 16) | (imm & 0xFFFF)
+}
+
+fn cp1_type(format: u32, ft: u32, fs: u32, fd: u32, funct: u32) -> u32
+{
+    (0x11 << 26) | (format << 21) | (ft << 16) | (fs << 11) | (fd << 6) | funct
+}
+
+fn encode(text: &str, address: u32, labels: &BTreeMap<String, u32>) -> Result<Vec<u32>, String>
+{
+    let (mnemonic, operands) = split_instruction(text);
+    let operand = |index: usize| -> Result<&str, String>
+    {
+        operands.get(index).copied()
+            .ok_or(format!("Missing operand {} of {}", index + 1, mnemonic))
+    };
+    let reg = |index: usize| register(operand(index)?);
+    let fp_reg = |index: usize| fp_register(operand(index)?);
+    let number = |index: usize| parse_number(operand(index)?);
+    let branch = |index: usize| branch_offset(operand(index)?, address, labels);
+
+    let word = match mnemonic
+    {
+        // pseudo instructions
+        "nop" => 0,
+        "move" => r_type(33, reg(1)?, 0, reg(0)?, 0), // addu
+        "not" => r_type(39, reg(1)?, 0, reg(0)?, 0), // nor
+        "neg" => r_type(34, 0, reg(1)?, reg(0)?, 0), // sub
+        "b" => i_type(4, 0, 0, branch(0)?),
+        "beqz" => i_type(4, reg(0)?, 0, branch(1)?),
+        "bnez" => i_type(5, reg(0)?, 0, branch(1)?),
+        "li" =>
+        {
+            let rt = reg(0)?;
+            let words = li_words(number(1)?).into_iter().enumerate()
+                .map(|(index, (opcode, imm))|
+                {
+                    let rs = if index == 0 {0} else {rt}; // the ori of a pair completes the lui
+                    i_type(opcode, rs, rt, imm)
+                })
+                .collect();
+            return Ok(words);
+        },
+        "la" =>
+        {
+            let rt = reg(0)?;
+            let target = address_of(operand(1)?, labels)?;
+            return Ok(vec![i_type(15, 0, rt, target >> 16), i_type(13, rt, rt, target)]);
+        },
+
+        // SPECIAL
+        "sll" => r_type(0, 0, reg(1)?, reg(0)?, number(2)? & 0b11111),
+        "srl" => r_type(2, 0, reg(1)?, reg(0)?, number(2)? & 0b11111),
+        "sra" => r_type(3, 0, reg(1)?, reg(0)?, number(2)? & 0b11111),
+        "sllv" => r_type(4, reg(2)?, reg(1)?, reg(0)?, 0),
+        "srlv" => r_type(6, reg(2)?, reg(1)?, reg(0)?, 0),
+        "srav" => r_type(7, reg(2)?, reg(1)?, reg(0)?, 0),
+        "movf" => r_type(1, reg(1)?, (number(2)? & 0b111) << 2, reg(0)?, 0),
+        "movt" => r_type(1, reg(1)?, ((number(2)? & 0b111) << 2) | 1, reg(0)?, 0),
+        "jr" => r_type(8, reg(0)?, 0, 0, 0),
+        "jalr" if operands.len() == 1 => r_type(9, reg(0)?, 0, 31, 0),
+        "jalr" => r_type(9, reg(1)?, 0, reg(0)?, 0),
+        "syscall" if operands.is_empty() => 12,
+        "syscall" => ((number(0)? & 0xF_FFFF) << 6) | 12,
+        "mfhi" => r_type(16, 0, 0, reg(0)?, 0),
+        "mthi" => r_type(17, reg(0)?, 0, 0, 0),
+        "mflo" => r_type(18, 0, 0, reg(0)?, 0),
+        "mtlo" => r_type(19, reg(0)?, 0, 0, 0),
+        "mult" => r_type(24, reg(0)?, reg(1)?, 0, 0),
+        "multu" => r_type(25, reg(0)?, reg(1)?, 0, 0),
+        "div" => r_type(26, reg(0)?, reg(1)?, 0, 0),
+        "divu" => r_type(27, reg(0)?, reg(1)?, 0, 0),
+        "add" => r_type(32, reg(1)?, reg(2)?, reg(0)?, 0),
+        "addu" => r_type(33, reg(1)?, reg(2)?, reg(0)?, 0),
+        "sub" => r_type(34, reg(1)?, reg(2)?, reg(0)?, 0),
+        "subu" => r_type(35, reg(1)?, reg(2)?, reg(0)?, 0),
+        "and" => r_type(36, reg(1)?, reg(2)?, reg(0)?, 0),
+        "or" => r_type(37, reg(1)?, reg(2)?, reg(0)?, 0),
+        "xor" => r_type(38, reg(1)?, reg(2)?, reg(0)?, 0),
+        "nor" => r_type(39, reg(1)?, reg(2)?, reg(0)?, 0),
+        "slt" => r_type(42, reg(1)?, reg(2)?, reg(0)?, 0),
+        "sltu" => r_type(43, reg(1)?, reg(2)?, reg(0)?, 0),
+        "tge" | "tgeu" | "tlt" | "tltu" | "teq" | "tne" =>
+        {
+            let funct = match mnemonic
+            {
+                "tge" => 0x30,
+                "tgeu" => 0x31,
+                "tlt" => 0x32,
+                "tltu" => 0x33,
+                "teq" => 0x34,
+                _ => 0x36, // tne
+            };
+            let code = match operands.len()
+            {
+                2 => 0,
+                _ => number(2)? & 0x3FF,
+            };
+            (code << 6) | r_type(funct, reg(0)?, reg(1)?, 0, 0)
+        },
+
+        // REGIMM
+        "tgei" => i_type(1, reg(0)?, 8, number(1)?),
+        "tgeiu" => i_type(1, reg(0)?, 9, number(1)?),
+        "tlti" => i_type(1, reg(0)?, 0xA, number(1)?),
+        "tltiu" => i_type(1, reg(0)?, 0xB, number(1)?),
+        "teqi" => i_type(1, reg(0)?, 0xC, number(1)?),
+        "tnei" => i_type(1, reg(0)?, 0xE, number(1)?),
+
+        "j" | "jal" =>
+        {
+            let target = address_of(operand(0)?, labels)?;
+            if (target ^ address.wrapping_add(4)) & 0xF000_0000 != 0
+            {
+                return Err(format!("Jump target out of the current region: {}", operand(0)?));
+            }
+            let opcode = if mnemonic == "j" {2} else {3};
+            (opcode << 26) | ((target >> 2) & 0x3FF_FFFF)
+        },
+        "beq" => i_type(4, reg(0)?, reg(1)?, branch(2)?),
+        "bne" => i_type(5, reg(0)?, reg(1)?, branch(2)?),
+        "blez" => i_type(6, reg(0)?, 0, branch(1)?),
+        "bgtz" => i_type(7, reg(0)?, 0, branch(1)?),
+
+        "addi" => i_type(8, reg(1)?, reg(0)?, number(2)?),
+        "addiu" => i_type(9, reg(1)?, reg(0)?, number(2)?),
+        "slti" => i_type(10, reg(1)?, reg(0)?, number(2)?),
+        "sltiu" => i_type(11, reg(1)?, reg(0)?, number(2)?),
+        "andi" => i_type(12, reg(1)?, reg(0)?, number(2)?),
+        "ori" => i_type(13, reg(1)?, reg(0)?, number(2)?),
+        "xori" => i_type(14, reg(1)?, reg(0)?, number(2)?),
+        "lui" => i_type(15, 0, reg(0)?, number(1)?),
+
+        "lb" | "lh" | "lwl" | "lw" | "lbu" | "lhu" | "lwr" | "sb" | "sh" | "sw" | "ll" | "sc" =>
+        {
+            let opcode = match mnemonic
+            {
+                "lb" => 32,
+                "lh" => 33,
+                "lwl" => 34,
+                "lw" => 35,
+                "lbu" => 36,
+                "lhu" => 37,
+                "lwr" => 38,
+                "sb" => 40,
+                "sh" => 41,
+                "sw" => 43,
+                "ll" => 0x30,
+                _ => 0x38, // sc
+            };
+            let (offset, base) = memory_operand(operand(1)?)?;
+            i_type(opcode, base, reg(0)?, offset)
+        },
+        "cache" | "pref" =>
+        {
+            let opcode = match mnemonic
+            {
+                "cache" => 0x2F,
+                _ => 0x33, // pref
+            };
+            let (offset, base) = memory_operand(operand(1)?)?;
+            i_type(opcode, base, number(0)? & 0b11111, offset)
+        },
+        "lwc1" | "swc1" | "ldc1" | "sdc1" =>
+        {
+            let opcode = match mnemonic
+            {
+                "lwc1" => 0x31,
+                "swc1" => 0x39,
+                "ldc1" => 0x35,
+                _ => 0x3D, // sdc1
+            };
+            let (offset, base) = memory_operand(operand(1)?)?;
+            i_type(opcode, base, fp_reg(0)?, offset)
+        },
+
+        // SPECIAL2
+        "mul" => (0x1C << 26) | r_type(0x02, reg(1)?, reg(2)?, reg(0)?, 0),
+        "clz" => (0x1C << 26) | r_type(0x20, reg(1)?, reg(0)?, reg(0)?, 0),
+        "clo" => (0x1C << 26) | r_type(0x21, reg(1)?, reg(0)?, reg(0)?, 0),
+
+        // COP0
+        "mfc0" if operands.len() == 3 => (0x10 << 26) | (reg(0)? << 16) | (reg(1)? << 11) | (number(2)? & 0b111),
+        "mfc0" => (0x10 << 26) | (reg(0)? << 16) | (reg(1)? << 11),
+        "mtc0" if operands.len() == 3 => (0x10 << 26) | (4 << 21) | (reg(0)? << 16) | (reg(1)? << 11) | (number(2)? & 0b111),
+        "mtc0" => (0x10 << 26) | (4 << 21) | (reg(0)? << 16) | (reg(1)? << 11),
+        "rfe" => (0x10 << 26) | (1 << 25) | 0x10,
+        "eret" => (0x10 << 26) | (1 << 25) | 0x18,
+        "wait" => (0x10 << 26) | (1 << 25) | 0x20,
+
+        // COP2, moves only
+        "mfc2" => (0x12 << 26) | (reg(0)? << 16) | (reg(1)? << 11),
+        "cfc2" => (0x12 << 26) | (2 << 21) | (reg(0)? << 16) | (reg(1)? << 11),
+        "mtc2" => (0x12 << 26) | (4 << 21) | (reg(0)? << 16) | (reg(1)? << 11),
+        "ctc2" => (0x12 << 26) | (6 << 21) | (reg(0)? << 16) | (reg(1)? << 11),
+
+        // COP1
+        "mfc1" => cp1_type(0, reg(0)?, fp_reg(1)?, 0, 0),
+        "mtc1" => cp1_type(4, reg(0)?, fp_reg(1)?, 0, 0),
+
+        _ => return encode_cp1_arithmetic(mnemonic, &operands).map(|word| vec![word]),
+    };
+
+    Ok(vec![word])
+}
+
+fn encode_cp1_arithmetic(mnemonic: &str, operands: &[&str]) -> Result<u32, String>
+{
+    let (operation, suffix) = mnemonic.rsplit_once('.')
+        .ok_or(format!("Unknown instruction: {}", mnemonic))?;
+    let format = match suffix
+    {
+        "s" => 0x10,
+        "d" => 0x11,
+        "w" => 0x14,
+        _ => return Err(format!("Unknown instruction: {}", mnemonic)),
+    };
+
+    let operand = |index: usize| -> Result<&str, String>
+    {
+        operands.get(index).copied()
+            .ok_or(format!("Missing operand {} of {}", index + 1, mnemonic))
+    };
+    let fp_reg = |index: usize| fp_register(operand(index)?);
+
+    let three = |funct: u32| -> Result<u32, String>
+    {
+        Ok(cp1_type(format, fp_reg(2)?, fp_reg(1)?, fp_reg(0)?, funct))
+    };
+    let two = |funct: u32| -> Result<u32, String>
+    {
+        Ok(cp1_type(format, 0, fp_reg(1)?, fp_reg(0)?, funct))
+    };
+    let compare = |funct: u32| -> Result<u32, String>
+    {
+        let (cc, first) = match operands.len()
+        {
+            3 => (parse_number(operand(0)?)? & 0b111, 1),
+            _ => (0, 0),
+        };
+        Ok(cp1_type(format, fp_reg(first + 1)?, fp_reg(first)?, cc << 2, funct))
+    };
+
+    match (operation, format)
+    {
+        ("add", 0x10 | 0x11) => three(0),
+        ("sub", 0x10 | 0x11) => three(1),
+        ("mul", 0x10 | 0x11) => three(2),
+        ("div", 0x10 | 0x11) => three(3),
+        ("sqrt", 0x10 | 0x11) => two(4),
+        ("abs", 0x10 | 0x11) => two(5),
+        ("mov", 0x10 | 0x11) => two(6),
+        ("neg", 0x10 | 0x11) => two(7),
+        ("round.w", 0x10 | 0x11) => two(0xC),
+        ("trunc.w", 0x10 | 0x11) => two(0xD),
+        ("ceil.w", 0x10 | 0x11) => two(0xE),
+        ("floor.w", 0x10 | 0x11) => two(0xF),
+        ("movf" | "movt", 0x10 | 0x11) =>
+        {
+            let cc = parse_number(operand(2)?)? & 0b111;
+            let tf = (operation == "movt") as u32;
+            Ok(cp1_type(format, (cc << 2) | tf, fp_reg(1)?, fp_reg(0)?, 0x11))
+        },
+        ("movz" | "movn", 0x10 | 0x11) =>
+        {
+            let funct = if operation == "movz" {0x12} else {0x13};
+            Ok(cp1_type(format, register(operand(2)?)?, fp_reg(1)?, fp_reg(0)?, funct))
+        },
+        ("cvt.s", 0x11 | 0x14) => two(0x20),
+        ("cvt.d", 0x10 | 0x14) => two(0x21),
+        ("cvt.w", 0x10 | 0x11) => two(0x24),
+        ("c.eq", 0x10 | 0x11) => compare(0x32),
+        ("c.lt", 0x10 | 0x11) => compare(0x3C),
+        ("c.le", 0x10 | 0x11) => compare(0x3E),
+        _ => Err(format!("Unknown instruction: {}", mnemonic)),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use alloc::vec::Vec;
+    use super::assemble;
+
+    #[test]
+    fn listing_maps_lines_to_addresses_after_expansion()
+    {
+        let assembly = assemble("addiu $t0, $zero, 1
+            li $t1, 0x12345678
+            nop", 0x400).unwrap();
+
+        let listing = &assembly.listing;
+        assert_eq!(listing.len(), 3);
+        assert_eq!(listing.iter().map(|entry| entry.line).collect::<Vec<_>>(), [1, 2, 3]);
+        assert_eq!(listing.iter().map(|entry| entry.address).collect::<Vec<_>>(), [0x400, 0x404, 0x40C]);
+        assert_eq!(listing[1].words.len(), 2); // lui and ori
+        assert_eq!(listing[2].words, [0]);
+        assert_eq!(listing[1].text, "li $t1, 0x12345678");
+    }
+}
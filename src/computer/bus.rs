@@ -0,0 +1,286 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Range;
+use crate::computer::console::ConsoleInput;
+use crate::computer::dma::DmaController;
+use crate::computer::memory::Memory;
+use crate::computer::timer::IntervalTimer;
+
+/*
+    Routes the CPU memory requests to the RAM or the memory mapped devices.
+    VRAM is a buffer of its own mapped over its address range, the RAM under it is never touched.
+ */
+pub(super) struct Bus
+{
+    pub(super) ram: Memory,
+    pub(super) vram: Memory,
+    vram_base: u32,
+    timers: Vec<IntervalTimer>,
+    dma_controllers: Vec<DmaController>,
+    console: Option<ConsoleInput>,
+    halt_register: Option<u32>, // address of the word the guest writes its exit code to
+    exit_code: Option<i32>, // written to the halt register, the machine stops
+}
+
+impl Bus
+{
+    pub(super) fn new(ram: Memory, vram: Range<u32>) -> Bus
+    {
+        Bus
+        {
+            ram,
+            vram: Memory::new(vram.len()),
+            vram_base: vram.start,
+            timers: Vec::new(),
+            dma_controllers: Vec::new(),
+            console: None,
+            halt_register: None,
+            exit_code: None,
+        }
+    }
+
+    // Offset of the access into VRAM if it lies there.
+    fn vram_offset(&self, address: u32, size: u8) -> Option<u32>
+    {
+        address.checked_sub(self.vram_base)
+            .filter(|&offset| self.vram.contains(offset, size))
+    }
+
+    // The bytes of a range that lies wholly in VRAM or in the RAM.
+    pub(super) fn slice(&self, start: u32, length: usize) -> Option<&[u8]>
+    {
+        let vram_start = start.checked_sub(self.vram_base)
+            .filter(|&offset| offset as usize + length <= self.vram.len());
+        let (memory, start) = match vram_start
+        {
+            Some(offset) => (&self.vram, offset as usize),
+            None => (&self.ram, start as usize),
+        };
+
+        memory.as_slice().get(start..start.checked_add(length)?)
+    }
+
+    // Offset of the range into VRAM if it lies wholly there, otherwise the range is in the RAM.
+    fn vram_range_offset(&self, start: u32, length: u32) -> Option<u32>
+    {
+        start.checked_sub(self.vram_base)
+            .filter(|&offset| offset as usize + length as usize <= self.vram.len())
+    }
+
+    fn memory_mut(&mut self, in_vram: bool) -> &mut Memory
+    {
+        match in_vram
+        {
+            true => &mut self.vram,
+            false => &mut self.ram,
+        }
+    }
+
+    pub(super) fn fill(&mut self, start: u32, length: u32, byte: u8) -> Result<(), String>
+    {
+        let vram_offset = self.vram_range_offset(start, length);
+        self.memory_mut(vram_offset.is_some()).fill(vram_offset.unwrap_or(start), length, byte)
+    }
+
+    // Copies within the RAM or VRAM, or between them, the ranges may overlap.
+    pub(super) fn copy(&mut self, source: u32, destination: u32, length: u32) -> Result<(), String>
+    {
+        let source_offset = self.vram_range_offset(source, length);
+        let destination_offset = self.vram_range_offset(destination, length);
+        let (source, destination) = (source_offset.unwrap_or(source), destination_offset.unwrap_or(destination));
+
+        if source_offset.is_some() == destination_offset.is_some()
+        {
+            return self.memory_mut(source_offset.is_some()).copy_within(source, destination, length);
+        }
+
+        let bytes = self.memory_mut(source_offset.is_some()).read_bytes(source, length)?.to_vec();
+        self.memory_mut(destination_offset.is_some()).write_bytes(destination, &bytes)
+    }
+
+    pub(super) fn add_timer(&mut self, timer: IntervalTimer)
+    {
+        self.timers.push(timer);
+    }
+
+    pub(super) fn add_dma_controller(&mut self, controller: DmaController)
+    {
+        self.dma_controllers.push(controller);
+    }
+
+    pub(super) fn set_console(&mut self, console: ConsoleInput)
+    {
+        self.console = Some(console);
+    }
+
+    pub(super) fn console_mut(&mut self) -> Option<&mut ConsoleInput>
+    {
+        self.console.as_mut()
+    }
+
+    fn console_at(&self, address: u32) -> Option<&ConsoleInput>
+    {
+        self.console.as_ref().filter(|console| console.contains(address))
+    }
+
+    pub(super) fn set_halt_register(&mut self, address: Option<u32>)
+    {
+        self.halt_register = address;
+    }
+
+    pub(super) fn take_exit_code(&mut self) -> Option<i32>
+    {
+        self.exit_code.take()
+    }
+
+    fn is_halt_register(&self, address: u32) -> bool
+    {
+        self.halt_register.is_some_and(|register| address.wrapping_sub(register) < 4)
+    }
+
+    pub(super) fn contains(&self, address: u32, size: u8) -> bool
+    {
+        self.is_halt_register(address) ||
+            self.console_at(address).is_some() ||
+            self.timers.iter().any(|timer| timer.contains(address)) ||
+            self.dma_controllers.iter().any(|controller| controller.contains(address)) ||
+            self.vram_offset(address, size).is_some() ||
+            self.ram.contains(address, size)
+    }
+
+    pub(super) fn read_data(&self, address: u32, size: u8) -> u32
+    {
+        if self.is_halt_register(address)
+        {
+            return 0;
+        }
+
+        if let Some(console) = self.console_at(address)
+        {
+            return console.read(address);
+        }
+
+        if let Some(controller) = self.dma_controllers.iter().find(|controller| controller.contains(address))
+        {
+            return controller.read(address);
+        }
+
+        match self.timers.iter().find(|timer| timer.contains(address))
+        {
+            Some(timer) => timer.read(address),
+            None => match self.vram_offset(address, size)
+            {
+                Some(offset) => self.vram.read_data(offset, size),
+                None => self.ram.read_data(address, size),
+            },
+        }
+    }
+
+    pub(super) fn write_data(&mut self, address: u32, data: u32, size: u8)
+    {
+        if self.is_halt_register(address)
+        {
+            self.exit_code = Some(data as i32);
+            return;
+        }
+
+        if let Some(console) = self.console.as_mut().filter(|console| console.contains(address))
+        {
+            console.write(address, data);
+            return;
+        }
+
+        if let Some(controller) = self.dma_controllers.iter_mut().find(|controller| controller.contains(address))
+        {
+            controller.write(address, data);
+            return;
+        }
+
+        match self.timers.iter_mut().find(|timer| timer.contains(address))
+        {
+            Some(timer) => timer.write(address, data),
+            None => match self.vram_offset(address, size)
+            {
+                Some(offset) => self.vram.write_data(offset, data, size),
+                None => self.ram.write_data(address, data, size),
+            },
+        }
+    }
+
+    // A load by the CPU, unlike read_data it takes the data of a device that hands it out once.
+    pub(super) fn load(&mut self, address: u32, size: u8) -> u32
+    {
+        match self.console.as_mut().filter(|console| console.contains(address))
+        {
+            Some(console) => console.load(address),
+            None => self.read_data(address, size),
+        }
+    }
+
+    // Advances the devices by one step and collects their interrupt requests.
+    pub(super) fn step(&mut self) -> u8
+    {
+        let timer_requests = self.timers.iter_mut().fold(0, |requests, timer|
+        {
+            timer.step();
+            requests | timer.interrupt_requests()
+        });
+
+        let mut dma_requests = 0;
+        for index in 0..self.dma_controllers.len()
+        {
+            if let Some(transfer) = self.dma_controllers[index].take_transfer()
+            {
+                let copied = self.copy(transfer.source, transfer.destination, transfer.length).is_ok();
+                self.dma_controllers[index].finish(copied);
+            }
+            dma_requests |= self.dma_controllers[index].interrupt_requests();
+        }
+
+        let console_requests = self.console.as_ref().map_or(0, |console| console.interrupt_requests());
+
+        timer_requests | dma_requests | console_requests
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use crate::computer::{test_computer, HaltReason};
+
+    #[test]
+    fn vram_store_goes_to_the_video_buffer_only()
+    {
+        let mut computer = test_computer("
+            addi $t0, $zero, 0x1000
+            li $t1, 0x00FF8040
+            sw $t1, 0x20($t0)
+            lw $t2, 0x20($t0)
+            done: b done");
+        computer.add_breakpoint_symbol("done").unwrap();
+        computer.run();
+
+        assert_eq!(computer.cpu.register(10), 0x00FF8040); // read back from VRAM
+        assert_eq!(computer.bus.vram.read_data(0x20, 4), 0x00FF8040);
+        assert_eq!(computer.bus.ram.read_data(0x1020, 4), 0);
+        assert_eq!(computer.framebuffer()[0], 0x00FF8040);
+    }
+
+    #[test]
+    fn guest_writing_the_halt_register_exits_with_the_value()
+    {
+        let mut computer = test_computer("
+            li $t1, 0x3FF0
+            lw $s0, 0($t1)
+            li $t0, 7
+            sw $t0, 0($t1)
+            li $s1, 1");
+        computer.bus.ram.write_data(0x3FF0, 0x12345678, 4);
+        computer.set_halt_register(Some(0x3FF0));
+
+        assert_eq!(computer.run(), HaltReason::Exited(7));
+        assert_eq!(computer.registers()[16], 0); // reads give 0, not the RAM under the register
+        assert_eq!(computer.registers()[17], 0); // stopped right after the store
+        assert_eq!(computer.bus.ram.read_data(0x3FF0, 4), 0x12345678);
+    }
+}
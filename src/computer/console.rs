@@ -0,0 +1,135 @@
+use alloc::collections::VecDeque;
+
+/*
+    Console input, mapped at its base address:
+        word 0: control, bit 0 interrupt enable, bit 1 input ready (read only)
+        word 1: data, a load takes the next queued byte (0 if there is none)
+    The host queues the input (see Computer::queue_console_input). With interrupts enabled
+    the console requests its interrupt line as long as input is ready, so the guest
+    acknowledges the interrupt by reading the data until the queue is drained.
+ */
+const CONTROL_INTERRUPT_ENABLE: u32 = 1 << 0;
+const CONTROL_INPUT_READY: u32 = 1 << 1;
+
+pub(super) const CONSOLE_SIZE: u32 = 8;
+
+pub(super) struct ConsoleInput
+{
+    base: u32,
+    interrupt_line: u8,
+    control: u32,
+    input: VecDeque<u8>,
+}
+
+impl ConsoleInput
+{
+    pub(super) fn new(base: u32, interrupt_line: u8) -> ConsoleInput
+    {
+        if interrupt_line > 7
+        {
+            panic!("Bad interrupt line: {}", interrupt_line);
+        }
+
+        ConsoleInput
+        {
+            base,
+            interrupt_line,
+            control: 0,
+            input: VecDeque::new(),
+        }
+    }
+
+    pub(super) fn contains(&self, address: u32) -> bool
+    {
+        address.wrapping_sub(self.base) < CONSOLE_SIZE
+    }
+
+    // Reading has no side effects here, see load.
+    pub(super) fn read(&self, address: u32) -> u32
+    {
+        match (address - self.base) / 4
+        {
+            0 if self.input.is_empty() => self.control,
+            0 => self.control | CONTROL_INPUT_READY,
+            _ => self.input.front().copied().unwrap_or(0) as u32,
+        }
+    }
+
+    // A load by the guest, it consumes the byte it reads from the data word.
+    pub(super) fn load(&mut self, address: u32) -> u32
+    {
+        match (address - self.base) / 4
+        {
+            0 => self.read(address),
+            _ => self.input.pop_front().unwrap_or(0) as u32,
+        }
+    }
+
+    pub(super) fn write(&mut self, address: u32, data: u32)
+    {
+        if (address - self.base) / 4 == 0
+        {
+            self.control = data & CONTROL_INTERRUPT_ENABLE;
+        }
+    }
+
+    pub(super) fn queue(&mut self, bytes: &[u8])
+    {
+        self.input.extend(bytes);
+    }
+
+    pub(super) fn interrupt_requests(&self) -> u8
+    {
+        let requesting = self.control & CONTROL_INTERRUPT_ENABLE != 0 && !self.input.is_empty();
+
+        (requesting as u8) << self.interrupt_line
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use crate::computer::{test_computer, HaltReason};
+    use super::{ConsoleInput, CONTROL_INTERRUPT_ENABLE};
+
+    #[test]
+    fn queued_input_requests_the_line_until_it_is_read()
+    {
+        let mut console = ConsoleInput::new(0x100, 2);
+        console.queue(b"hi");
+        assert_eq!(console.interrupt_requests(), 0); // not enabled yet
+
+        console.write(0x100, CONTROL_INTERRUPT_ENABLE);
+        assert_eq!(console.interrupt_requests(), 1 << 2);
+        assert_eq!(console.read(0x104), b'h' as u32); // a host read doesn't consume it
+        assert_eq!(console.load(0x104), b'h' as u32);
+        assert_eq!(console.interrupt_requests(), 1 << 2);
+        assert_eq!(console.load(0x104), b'i' as u32);
+        assert_eq!(console.interrupt_requests(), 0);
+    }
+
+    #[test]
+    fn guest_polls_for_the_queued_input()
+    {
+        let mut computer = test_computer("
+            li $t0, 0x3F00
+        poll:
+            lw $t1, 0($t0)
+            andi $t1, $t1, 2
+            beq $t1, $zero, poll
+            lw $s0, 4($t0)
+            lw $s1, 4($t0)
+        done:
+            b done");
+        computer.add_breakpoint_symbol("done").unwrap();
+        assert!(computer.queue_console_input(b"A").is_err()); // nothing mapped yet
+        computer.add_console_input(0x3F00, 2);
+
+        assert_eq!(computer.run_for(20), HaltReason::StepLimit); // no input, still polling
+
+        computer.queue_console_input(b"A").unwrap();
+        assert!(matches!(computer.run(), HaltReason::Breakpoint(_)));
+        assert_eq!(computer.registers()[16], b'A' as u32);
+        assert_eq!(computer.registers()[17], 0); // the first load drained the queue
+    }
+}
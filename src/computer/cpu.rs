@@ -1,9 +1,18 @@
-use std::mem;
+use alloc::collections::BTreeSet;
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::computer::fault::FaultKind;
+use crate::computer::Phase;
+use crate::computer::bus::Bus;
+use crate::disassembler::mnemonic;
+#[cfg(not(any(feature = "std", test)))]
+use crate::computer::float::FloatMath;
 
 enum CPUPhase
 {
     Fetch,
     DecodeAndExecute,
+    MemoryWait, // a load or store in progress, see memory_latency
     WriteBack,
     InterruptCheck,
 }
@@ -18,11 +27,35 @@ pub(super) struct MemoryBuffer
     pub(super) write_back_register: u8,
     pub(super) sign_extended: bool,
     partial_write: Option<(usize, usize)>,
+    second_word: Option<(u32, u8)>, // data and write back register of the next word of a doubleword
 }
 
-const EXCEPTION_HANDLER_ADDRESS: u32 = 0x8000_0180; // 0x8000_0080 ?
+pub(super) const EXCEPTION_HANDLER_ADDRESS: u32 = 0x8000_0180; // the default exception vector
+
+/* Status register fields (MIPS32) */
+const STATUS_IE: u32 = 1 << 0; // interrupt enable
+const STATUS_EXL: u32 = 1 << 1; // exception level
+const STATUS_ERL: u32 = 1 << 2; // error level
+const STATUS_KSU: u32 = 0b11 << 3; // 00 kernel, 10 user
+const STATUS_CU1: u32 = 1 << 29; // coprocessor 1 usable
+
+/* Cause register fields (MIPS32) */
+const CAUSE_EXC_CODE: u32 = 0b11111 << 2;
+const CAUSE_CE: u32 = 0b11 << 28; // coprocessor of a Coprocessor Unusable exception
+const CAUSE_BD: u32 = 1 << 31; // the exception happened in a branch delay slot
+const CAUSE_IP_SOFTWARE: u32 = 0b11 << 8; // IP1 and IP0, the only part of Cause mtc0 writes
+
+/*
+    Config (register 16, select 0): Config1 follows (M), big endian (BE), MIPS32 release 1,
+    no TLB (MT = 0) and uncached kseg0 (K0 = 2).
+    Config1 (select 1) is made on reading, see Cpu::config1.
+ */
+const CONFIG: u32 = (1 << 31) | (1 << 15) | 2;
+const CONFIG1_C2: u32 = 1 << 6; // coprocessor 2 present
+const CONFIG1_FP: u32 = 1 << 0; // FPU present
 
 #[allow(unused)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 #[repr(u8)]
 enum ExceptionCode
 {
@@ -34,11 +67,46 @@ enum ExceptionCode
     Syscall = 8,
     Break = 9,
     ReservedInstruction = 10,
+    CoprocessorUnusable = 11, // also privileged instructions in user mode
     Overflow = 12,
     CalledTrap = 13, // https://faculty.kfupm.edu.sa/COE/aimane/coe301/lab/COE301_Lab_8_MIPS_Exceptions_and_IO.pdf
 }
 
-pub(super) struct CPU
+// An odd register number used for a double-precision value.
+#[derive(Debug)]
+struct FpRegError;
+
+/*
+    The default NaNs of the legacy MIPS encoding, where a clear most significant fraction bit
+    marks a quiet NaN. Arithmetic results that are NaN are replaced with them, so the register
+    bits don't depend on the NaN the host produced.
+ */
+const CANONICAL_NAN_S: u32 = 0x7FBF_FFFF;
+const CANONICAL_NAN_D: u64 = 0x7FF7_FFFF_FFFF_FFFF;
+
+// abs and neg only clear or flip these bits, even for NaNs and zeros.
+const SIGN_BIT_S: u32 = 1 << 31;
+const SIGN_BIT_D: u64 = 1 << 63;
+
+fn canonical_nan_s(value: f32) -> f32
+{
+    match value.is_nan()
+    {
+        true => f32::from_bits(CANONICAL_NAN_S),
+        false => value,
+    }
+}
+
+fn canonical_nan_d(value: f64) -> f64
+{
+    match value.is_nan()
+    {
+        true => f64::from_bits(CANONICAL_NAN_D),
+        false => value,
+    }
+}
+
+pub(super) struct Cpu
 {
     int_reg: [u32; 32],
     cp0_reg: [u32; 32],
@@ -48,14 +116,54 @@ pub(super) struct CPU
     cp1_reg: [f32; 32],
     cc: [bool; 8],
 
+    cp2_enabled: bool, // a stub without operations, only its registers can be moved
+    cp2_reg: [u32; 32],
+    cp2_control: [u32; 32],
+
     pc: u32,
+    exception_vector: u32, // where every exception enters the guest handler
+    instruction_address: u32, // address of the instruction in progress
+    delay_slots: bool, // jumps and taken branches run the next instruction first
+    delayed_jump: Option<u32>, // target of the jump waiting for its delay slot
+    delay_slot_branch: Option<u32>, // the branch whose delay slot is the instruction in progress
     memory_buffer: MemoryBuffer,
     phase: CPUPhase,
+
+    track_calls: bool,
+    call_stack: Vec<u32>, // return addresses of the calls made while tracking
+
+    host_syscalls: bool,
+    pending_syscall: bool,
+    syscall_code: u32, // code field of the last syscall instruction
+    host_exceptions: u32, // bit n set: ExcCode n goes to a host handler
+    host_exception: Option<u8>, // raised and waiting for the host handler
+    halt_on_exception: bool,
+    halted_exception: Option<u8>, // raised with halt_on_exception, the machine stops
+    allowed_instructions: Option<BTreeSet<String>>, // mnemonics, None for the whole instruction set
+
+    ll_bit: bool, // reservation taken by ll, sc only stores while it holds
+    waiting: bool, // idle after wait until an interrupt is requested
+    interrupt_priority: [u8; 8], // interrupt lines, highest priority first
+    software_interrupts: u8, // lines 0 and 1, requested by the guest writing Cause
+
+    trap_on_overflow: bool, // add, addi and sub wrap silently when cleared
+    overflow: bool, // set by the last add, addi or sub
+
+    hilo_latency: u32, // cycles until a mult or div result can be read, 0 = no hazard
+    hilo_busy: u32, // cycles left until HI and LO are ready
+    hilo_hazards: u64, // reads of HI or LO that had to wait
+    exceptions_taken: u64, // entries to the guest exception vector
+    stall_cycles: u64,
+    memory_latency: u32, // extra cycles of a load or store in the phased model
+    memory_wait: u32, // cycles left until the memory access completes
+    cycles: u64,
+
+    fault: Option<FaultKind>, // unrecoverable condition found while decoding
 }
 
-impl CPU
+impl Cpu
 {
-    pub fn new() -> CPU
+    pub fn new() -> Cpu
     {
         let memory_buffer = MemoryBuffer
         {
@@ -66,13 +174,15 @@ impl CPU
             write_back_register: 0,
             sign_extended: false,
             partial_write: None,
+            second_word: None,
         };
 
         let mut cp0_reg = [0; 32];
         // status
-        cp0_reg[12] = 0b0000000000000000_11111111_00___00_00_01;
+        cp0_reg[12] = (0xFF << 8) | STATUS_IE; // all interrupt lines unmasked
+        cp0_reg[16] = CONFIG;
 
-        CPU
+        Cpu
         {
             int_reg: [0; 32],
 
@@ -83,15 +193,295 @@ impl CPU
             cp1_reg: [0.0; 32],
             cc: [false; 8],
 
+            cp2_enabled: false,
+            cp2_reg: [0; 32],
+            cp2_control: [0; 32],
+
             pc: 0,
+            exception_vector: EXCEPTION_HANDLER_ADDRESS,
+            instruction_address: 0,
+            delay_slots: false,
+            delayed_jump: None,
+            delay_slot_branch: None,
             memory_buffer,
             phase: CPUPhase::Fetch,
+
+            track_calls: false,
+            call_stack: Vec::new(),
+
+            host_syscalls: false,
+            pending_syscall: false,
+            syscall_code: 0,
+            host_exceptions: 0,
+            host_exception: None,
+            halt_on_exception: false,
+            halted_exception: None,
+            allowed_instructions: None,
+
+            ll_bit: false,
+            waiting: false,
+            interrupt_priority: [7, 6, 5, 4, 3, 2, 1, 0],
+            software_interrupts: 0,
+
+            trap_on_overflow: true,
+            overflow: false,
+
+            hilo_latency: 0,
+            hilo_busy: 0,
+            hilo_hazards: 0,
+            exceptions_taken: 0,
+            stall_cycles: 0,
+            memory_latency: 0,
+            memory_wait: 0,
+            cycles: 0,
+
+            fault: None,
+        }
+    }
+
+    pub(super) fn pc(&self) -> u32
+    {
+        self.pc
+    }
+
+    pub(super) fn set_delay_slots(&mut self, enabled: bool)
+    {
+        self.delay_slots = enabled;
+        self.delayed_jump = None;
+    }
+
+    // Address of the jump or branch if the instruction in progress is in its delay slot.
+    pub(super) fn delay_slot_branch(&self) -> Option<u32>
+    {
+        self.delay_slot_branch
+    }
+
+    // Abandons an instruction left in progress by a fault, the CPU fetches from pc next.
+    pub(super) fn set_pc(&mut self, pc: u32)
+    {
+        self.pc = pc;
+        self.delayed_jump = None;
+        self.memory_buffer.data_size = 0;
+        self.phase = CPUPhase::Fetch;
+    }
+
+    pub(super) fn register(&self, reg_num: u8) -> u32
+    {
+        self.int_reg[reg_num as usize]
+    }
+
+    pub(super) fn registers(&self) -> [u32; 32]
+    {
+        self.int_reg
+    }
+
+    // Architectural state as words, for hashing and comparing machines.
+    pub(super) fn state_words(&self) -> Vec<u32>
+    {
+        let mut words = Vec::new();
+        words.extend(&self.int_reg);
+        words.extend(&self.cp0_reg);
+        words.extend(self.cp1_reg.iter().map(|value| value.to_bits()));
+        words.extend(self.cc.iter().map(|&flag| flag as u32));
+        words.extend(&self.cp2_reg);
+        words.extend(&self.cp2_control);
+        words.extend([self.hi, self.lo, self.pc, self.ll_bit as u32]);
+        if let Some(target) = self.delayed_jump
+        {
+            words.push(target);
+        }
+        words
+    }
+
+    pub(super) fn instruction_address(&self) -> u32
+    {
+        self.instruction_address
+    }
+
+    // ExcCode of the last exception, from Cause.
+    pub(super) fn exception_code(&self) -> u8
+    {
+        ((self.cp0_reg[13] >> 2) & 0b11111) as u8
+    }
+
+    pub(super) fn epc(&self) -> u32
+    {
+        self.cp0_reg[14]
+    }
+
+    pub(super) fn exception_vector(&self) -> u32
+    {
+        self.exception_vector
+    }
+
+    pub(super) fn set_exception_vector(&mut self, address: u32)
+    {
+        self.exception_vector = address;
+    }
+
+    // The memory request of the last tick, the next tick works on its answer.
+    pub(super) fn memory_request(&self) -> MemoryBuffer
+    {
+        self.memory_buffer
+    }
+
+    pub(super) fn take_fault(&mut self) -> Option<FaultKind>
+    {
+        self.fault.take()
+    }
+
+    pub(super) fn set_register(&mut self, reg_num: u8, val: u32)
+    {
+        self.write_to_reg(reg_num, val);
+    }
+
+    pub(super) fn hilo(&self) -> u64
+    {
+        ((self.hi as u64) << 32) | self.lo as u64
+    }
+
+    pub(super) fn set_hilo(&mut self, value: u64)
+    {
+        self.hi = (value >> 32) as u32;
+        self.lo = value as u32;
+    }
+
+    #[cfg(feature = "std")]
+    pub(super) fn set_host_syscalls(&mut self, enabled: bool)
+    {
+        self.host_syscalls = enabled;
+    }
+
+    #[cfg(feature = "std")]
+    pub(super) fn take_pending_syscall(&mut self) -> bool
+    {
+        core::mem::take(&mut self.pending_syscall)
+    }
+
+    // Hands a syscall the host couldn't service over to the guest's exception handler.
+    #[cfg(feature = "std")]
+    pub(super) fn raise_syscall_exception(&mut self)
+    {
+        self.execute_exception(ExceptionCode::Syscall, None);
+    }
+
+    pub(super) fn set_host_exception(&mut self, code: u8, enabled: bool)
+    {
+        let bit = 1u32.checked_shl(code as u32).unwrap_or(0); // there are only 32 codes
+        match enabled
+        {
+            true => self.host_exceptions |= bit,
+            false => self.host_exceptions &= !bit,
+        }
+    }
+
+    pub(super) fn take_host_exception(&mut self) -> Option<u8>
+    {
+        self.host_exception.take()
+    }
+
+    pub(super) fn set_halt_on_exception(&mut self, enabled: bool)
+    {
+        self.halt_on_exception = enabled;
+    }
+
+    pub(super) fn take_halted_exception(&mut self) -> Option<u8>
+    {
+        self.halted_exception.take()
+    }
+
+    pub(super) fn set_allowed_instructions(&mut self, mnemonics: Option<BTreeSet<String>>)
+    {
+        self.allowed_instructions = mnemonics;
+    }
+
+    pub(super) fn set_cp2_enabled(&mut self, enabled: bool)
+    {
+        self.cp2_enabled = enabled;
+    }
+
+    pub(super) fn exceptions_taken(&self) -> u64
+    {
+        self.exceptions_taken
+    }
+
+    pub(super) fn syscall_code(&self) -> u32
+    {
+        self.syscall_code
+    }
+
+    pub(super) fn set_trap_on_overflow(&mut self, enabled: bool)
+    {
+        self.trap_on_overflow = enabled;
+    }
+
+    pub(super) fn overflow(&self) -> bool
+    {
+        self.overflow
+    }
+
+    pub(super) fn hilo_latency(&self) -> u32
+    {
+        self.hilo_latency
+    }
+
+    pub(super) fn set_hilo_latency(&mut self, cycles: u32)
+    {
+        self.hilo_latency = cycles;
+        self.hilo_busy = 0;
+    }
+
+    pub(super) fn hilo_hazards(&self) -> u64
+    {
+        self.hilo_hazards
+    }
+
+    pub(super) fn stall_cycles(&self) -> u64
+    {
+        self.stall_cycles
+    }
+
+    pub(super) fn set_memory_latency(&mut self, cycles: u32)
+    {
+        self.memory_latency = cycles;
+    }
+
+    pub(super) fn cycles(&self) -> u64
+    {
+        self.cycles
+    }
+
+    pub(super) fn phase(&self) -> Phase
+    {
+        match self.phase
+        {
+            CPUPhase::Fetch => Phase::Fetch,
+            CPUPhase::DecodeAndExecute => Phase::DecodeAndExecute,
+            CPUPhase::MemoryWait => Phase::MemoryWait,
+            CPUPhase::WriteBack => Phase::WriteBack,
+            CPUPhase::InterruptCheck => Phase::InterruptCheck,
+        }
+    }
+
+    pub(super) fn set_call_tracking(&mut self, enabled: bool)
+    {
+        self.track_calls = enabled;
+        self.call_stack.clear();
+    }
+
+    pub(super) fn call_stack(&self) -> Option<&[u32]>
+    {
+        match self.track_calls
+        {
+            true => Some(&self.call_stack),
+            false => None,
         }
     }
 
     fn is_kernel_mode(&self) -> bool
     {
-        self.cp0_reg[12] & 0b10 == 0
+        let status = self.cp0_reg[12];
+        status & STATUS_KSU == 0 || status & (STATUS_EXL | STATUS_ERL) != 0
     }
 
     fn write_to_reg(&mut self, reg_num: u8, val: u32)
@@ -107,52 +497,228 @@ impl CPU
 
     pub(super) fn tick(&mut self, data: u32, interrupt_requests: u8) -> MemoryBuffer
     {
+        self.cycles += 1;
         match self.phase
         {
             CPUPhase::Fetch =>
             {
-                self.memory_buffer = MemoryBuffer
-                {
-                    address: self.pc,
-                    data: 0,
-                    data_size: 4,
-                    store: false,
-                    write_back_register: 0,
-                    sign_extended: false,
-                    partial_write: None,
-                };
-                self.pc += 4;
+                self.fetch();
                 self.phase = CPUPhase::DecodeAndExecute;
             }
             CPUPhase::DecodeAndExecute =>
             {
-                self.decode_and_execute(data);
-                if self.memory_buffer.write_back_register == 0
+                self.execute(data);
+                self.memory_wait = self.memory_latency;
+                self.phase = match self.memory_buffer.data_size > 0 && self.memory_wait > 0
                 {
-                    self.memory_buffer.data_size = 0;
+                    true => CPUPhase::MemoryWait,
+                    false => CPUPhase::WriteBack,
+                };
+            }
+            CPUPhase::MemoryWait =>
+            {
+                self.memory_wait -= 1;
+                self.stall_cycles += 1;
+                if self.memory_wait == 0
+                {
+                    self.phase = CPUPhase::WriteBack;
                 }
-                self.phase = CPUPhase::WriteBack;
             }
             CPUPhase::WriteBack =>
             {
-                if self.memory_buffer.data_size > 0
+                if !self.finish_memory_access(data) // the second word of a doubleword needs another tick
                 {
-                    self.memory_buffer.data = data; // save data taken from ram
-                    self.write_back();
+                    self.phase = CPUPhase::InterruptCheck;
                 }
-                self.memory_buffer.data_size = 0; // reset the buffer
-                self.phase = CPUPhase::InterruptCheck;
             }
             CPUPhase::InterruptCheck =>
             {
-                self.set_interrupt_requests(interrupt_requests);
-                self.handle_interrupts(interrupt_requests);
+                self.check_interrupts(interrupt_requests);
                 self.phase = CPUPhase::Fetch;
             }
         }
 
+        self.check_memory_violation();
+        self.memory_buffer
+    }
+
+    /*
+        Runs the fetch, execute and write back phases of one instruction at once,
+        accessing the memory directly instead of exchanging requests through tick.
+        The interrupt check is left to the caller, like the last tick.
+        The cycles are counted as in the phased model, without the memory latency.
+     */
+    pub(super) fn execute_one(&mut self, bus: &mut Bus) -> Result<(), FaultKind>
+    {
+        self.cycles += 4;
+        self.fetch();
+        self.check_memory_violation();
+        let pc = self.memory_buffer.address;
+        if !self.check_fetch(bus, pc)?
+        {
+            self.phase = CPUPhase::Fetch;
+            return Ok(());
+        }
+
+        self.execute(bus.read_data(pc, 4));
+        if let Some(fault) = self.fault.take()
+        {
+            return Err(fault);
+        }
+        self.check_memory_violation();
+
+        loop
+        {
+            let request = self.memory_buffer;
+            let data = match (request.data_size, request.store)
+            {
+                (0, _) => 0,
+                (size, _) if !bus.contains(request.address, size) =>
+                    return Err(FaultKind::BadAddress(request.address)),
+                (size, false) => bus.load(request.address, size),
+                (size, true) =>
+                {
+                    bus.write_data(request.address, request.data, size);
+                    0
+                },
+            };
+
+            if !self.finish_memory_access(data)
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    /*
+        Checks the fetch address before the instruction is read. A misaligned PC raises AdEL,
+        one outside of the memory a bus error, and the instruction is skipped (false is returned).
+        It is a fault if the exception handler itself can't be fetched.
+     */
+    pub(super) fn check_fetch(&mut self, bus: &Bus, pc: u32) -> Result<bool, FaultKind>
+    {
+        if self.memory_buffer.data_size == 0 // refused in user mode, the exception is raised already
+        {
+            self.abort_instruction();
+            return Ok(false);
+        }
+
+        let misaligned = !pc.is_multiple_of(4);
+        if !misaligned && bus.contains(pc, 4)
+        {
+            return Ok(true);
+        }
+
+        if pc == self.exception_vector
+        {
+            return Err(FaultKind::BadAddress(pc));
+        }
+
+        match misaligned
+        {
+            true => self.execute_exception(ExceptionCode::IllegalAddressLoad, Some(pc)),
+            false => self.execute_exception(ExceptionCode::BusErrorOnInstructionFetch, None),
+        }
+        self.abort_instruction();
+        Ok(false)
+    }
+
+    // Skips the rest of the instruction cycle, up to the interrupt check.
+    fn abort_instruction(&mut self)
+    {
+        self.memory_buffer.data_size = 0;
+        self.phase = CPUPhase::InterruptCheck;
+    }
+
+    fn fetch(&mut self)
+    {
+        self.memory_buffer = MemoryBuffer
+        {
+            address: self.pc,
+            data: 0,
+            data_size: 4,
+            store: false,
+            write_back_register: 0,
+            sign_extended: false,
+            partial_write: None,
+            second_word: None,
+        };
+        self.instruction_address = self.pc;
+        self.delay_slot_branch = None;
+        self.pc = match self.delayed_jump.take()
+        {
+            Some(target) =>
+            {
+                self.delay_slot_branch = Some(self.pc.wrapping_sub(4));
+                target
+            },
+            None => self.pc.wrapping_add(4),
+        };
+    }
+
+    fn execute(&mut self, instruction: u32)
+    {
+        self.hilo_busy = self.hilo_busy.saturating_sub(1); // one cycle per instruction
+        self.decode_and_execute(instruction);
+        if !self.memory_buffer.store && self.memory_buffer.write_back_register == 0
+        {
+            self.memory_buffer.data_size = 0; // load to $zero
+        }
+    }
+
+    // Returns true if the buffer was set up for the second word of a doubleword.
+    fn finish_memory_access(&mut self, data: u32) -> bool
+    {
+        if self.memory_buffer.data_size > 0
+        {
+            self.memory_buffer.data = data; // save data taken from ram
+            self.write_back();
+
+            if let Some((data, register)) = self.memory_buffer.second_word.take()
+            {
+                self.memory_buffer.address = self.memory_buffer.address.wrapping_add(4);
+                self.memory_buffer.data = data;
+                self.memory_buffer.write_back_register = register;
+                return true;
+            }
+        }
+        self.memory_buffer.data_size = 0; // reset the buffer
+        false
+    }
+
+    pub(super) fn set_interrupt_priority(&mut self, priority: [u8; 8])
+    {
+        self.interrupt_priority = priority;
+    }
 
-        /* Check memory violation. */
+    pub(super) fn is_waiting(&self) -> bool
+    {
+        self.waiting
+    }
+
+    /*
+        Interrupts are checked at the instruction boundary, after the whole instruction
+        (memory access and a host syscall included) has completed. An instruction that
+        enables interrupts, like eret clearing EXL or mtc0 setting IE, lets a pending request
+        in right here: the interrupt is taken before the next instruction is fetched and
+        EPC points to that instruction, so it runs once the handler returns.
+        The software interrupts stay requested until the guest clears them in Cause.
+     */
+    pub(super) fn check_interrupts(&mut self, interrupt_requests: u8)
+    {
+        let interrupt_requests = interrupt_requests | self.software_interrupts;
+        let mask = ((self.cp0_reg[12] >> 8) & 0xFF) as u8;
+        if interrupt_requests & mask != 0
+        {
+            self.waiting = false;
+        }
+
+        self.set_interrupt_requests(interrupt_requests);
+        self.handle_interrupts(interrupt_requests);
+    }
+
+    fn check_memory_violation(&mut self)
+    {
         let address = self.memory_buffer.address;
         let is_requesting_kernel_space =  self.memory_buffer.data_size > 0 &&
             address & 0x80000000 != 0;
@@ -169,15 +735,37 @@ impl CPU
 
             self.execute_exception(exception_code, Some(address));
         }
+    }
 
-        self.memory_buffer
+    // Raises AdEL or AdES and cancels the access if the address isn't a multiple of the size.
+    fn check_alignment(&mut self, address: u32, size: u32, store: bool) -> bool
+    {
+        if address.is_multiple_of(size)
+        {
+            return true;
+        }
+
+        self.memory_buffer.data_size = 0;
+        let exception_code = match store
+        {
+            true => ExceptionCode::IllegalAddressStore,
+            false => ExceptionCode::IllegalAddressLoad,
+        };
+        self.execute_exception(exception_code, Some(address));
+        false
     }
 
     fn handle_interrupts(&mut self, interrupt_requests: u8)
     {
         let status = &self.cp0_reg[12];
 
-        let interrupts_enabled = (status & 0x01) == 1;
+        // A jump and its delay slot are taken together, EPC can't point between them.
+        if self.delayed_jump.is_some()
+        {
+            return;
+        }
+
+        let interrupts_enabled = status & STATUS_IE != 0 && status & (STATUS_EXL | STATUS_ERL) == 0;
         if !interrupts_enabled
         {
             return;
@@ -185,58 +773,160 @@ impl CPU
 
         let mask = ((status >> 8) & 0xFF) as u8;
         let non_masked_interrupts = interrupt_requests & mask;
-        if non_masked_interrupts != 0
+        let selected_line = self.interrupt_priority.iter()
+            .find(|&&line| non_masked_interrupts & (1 << line) != 0);
+        if let Some(&line) = selected_line
         {
+            // The handler sees only the line it was entered for, the others stay pending.
+            self.set_interrupt_requests(1 << line);
             self.execute_exception(ExceptionCode::Interrupt, None);
         }
     }
 
     fn decode_and_execute(&mut self, instruction: u32)
     {
-        /*
-            RFE encoding
-            https://people.cs.pitt.edu/~don/coe1502/current/Unit4a/Unit4a.html
-         */
-        if instruction == 0b010000_1_0000000000000000000_010000
+        let allowed = self.allowed_instructions.as_ref()
+            .is_none_or(|allowed| allowed.contains(&mnemonic(instruction)));
+        if !allowed
         {
-            self.rfe();
+            self.execute_exception(ExceptionCode::ReservedInstruction, None);
             return;
         }
 
+        let opcode = instruction >> 26;
+        match opcode
+        {
+            0x10 => self.decode_cp0(instruction),
+            0x11 | 0x31 | 0x35 | 0x39 | 0x3D => self.decode_cp1(instruction),
+            0x12 => self.decode_cp2(instruction),
+            _ if self.decode_trap_instruction(instruction) => {},
+            _ => self.decode_int_instruction(instruction),
+        }
+    }
+
+    fn decode_cp2(&mut self, instruction: u32)
+    {
+        if !self.cp2_enabled
+        {
+            self.execute_exception(ExceptionCode::CoprocessorUnusable, None);
+            self.cp0_reg[13] |= 2 << 28; // CE: coprocessor 2
+            return;
+        }
+
+        let rs = (instruction >> 21) & 0b11111;
+        let rt = ((instruction >> 16) & 0b11111) as u8;
+        let rd = ((instruction >> 11) & 0b11111) as usize;
+
+        match rs
+        {
+            0 => self.write_to_reg(rt, self.cp2_reg[rd]), // mfc2
+            2 => self.write_to_reg(rt, self.cp2_control[rd]), // cfc2
+            4 => self.cp2_reg[rd] = self.int_reg[rt as usize], // mtc2
+            6 => self.cp2_control[rd] = self.int_reg[rt as usize], // ctc2
+            _ => self.execute_exception(ExceptionCode::ReservedInstruction, None), // no CP2 operations
+        }
+    }
+
+    fn decode_cp0(&mut self, instruction: u32)
+    {
+        let rs = ((instruction >> 21) & 0b11111) as u8;
+        let rt = ((instruction >> 16) & 0b11111) as u8;
+        let rd = ((instruction >> 11) & 0b11111) as u8;
+        let funct = (instruction & 0b111111) as u8;
+
         /*
+            With the CO bit (25) set the funct field selects an operation and bits 6-24 are
+            implementation defined, otherwise rs selects a move.
             https://www.math.unipd.it/~sperduti/ARCHITETTURE-1/mips32.pdf
+            RFE: https://people.cs.pitt.edu/~don/coe1502/current/Unit4a/Unit4a.html
          */
-        if instruction == 0b010000_1_0000000000000000000_010010
+        let coprocessor_operation = rs & 0x10 != 0;
+        match (coprocessor_operation, rs, funct)
+        {
+            (true, _, 0x10) => self.rfe(),
+            (true, _, 0x18) => self.eret(),
+            (true, _, 0x20) => self.wait(),
+            (false, 0, _) if instruction & 0x7F8 == 0 => self.mfc0(rt, rd, funct & 0b111),
+            (false, 4, _) if instruction & 0x7F8 == 0 => self.mtc0(rt, rd, funct & 0b111),
+            _ => self.execute_exception(ExceptionCode::ReservedInstruction, None),
+        };
+    }
+
+    fn decode_cp1(&mut self, instruction: u32)
+    {
+        let opcode = instruction >> 26;
+        let opcode2 = ((instruction >> 21) & 0b11111) as u8;
+        let ft = ((instruction >> 16) & 0b11111) as u8;
+        let early_cc = ((instruction >> 18) & 0b111) as u8;
+        let after_early_cc = (instruction >> 16) & 0b11;
+        let fs = ((instruction >> 11) & 0b11111) as u8;
+        let fd = ((instruction >> 6) & 0b11111) as u8;
+
+        let late_cc = ((instruction >> 8) & 0b111) as u8;
+        let after_late_cc = (instruction >> 6) & 0b11;
+        let last = instruction & 0b111111;
+
+        let offset = (instruction & 0xFFFF) as u16;
+
+        match opcode
         {
-            self.eret();
-            return;
+            0x39 => return self.swc1(ft, opcode2, offset),
+            0x31 => return self.lwc1(ft, opcode2, offset),
+            _ => {},
         }
 
-        self.decode_cp0(instruction);
-        self.decode_cp1(instruction);
-        self.decode_trap_instruction(instruction);
-        self.decode_int_instruction(instruction);
-    }
+        match (opcode, opcode2, fd, last)
+        {
+            (0x11, 0, 0, 0) => return self.mfc1(ft, fs),
+            (0x11, 4, 0, 0) => return self.mtc1(ft, fs),
+            _ => {},
+        }
+
+        match (opcode, opcode2, ft, last)
+        {
+            (0x11, 0x10, 0, 5) => return self.abs_s(fd, fs),
+            (0x11, 0x10, _, 0) => return self.add_s(fd, fs, ft),
+            (0x11, 0x10, 0, 0xE) => return self.ceil_w_s(fd, fs),
+            (0x11, 0x14, 0, 0x20) => return self.cvt_s_w(fd, fs),
+            (0x11, 0x10, 0, 0x24) => return self.cvt_w_s(fd, fs),
+            (0x11, 0x10, _, 3) => return self.div_s(fd, fs, ft),
+            (0x11, 0x10, 0, 0xF) => return self.floor_w_s(fd, fs),
+            (0x11, 0x10, 0, 6) => return self.mov_s(fd, fs),
+            (0x11, 0x10, rt, 0x13) => return self.movn_s(fd, fs, rt), // rt instead of ft
+            (0x11, 0x10, rt, 0x12) => return self.movz_s(fd, fs, rt), // rt instead of ft
+            (0x11, 0x10, _, 2) => return self.mul_s(fd, fs, ft),
+            (0x11, 0x10, 0, 7) => return self.neg_s(fd, fs),
+            (0x11, 0x10, 0, 0xC) => return self.round_w_s(fd, fs),
+            (0x11, 0x10, 0, 4) => return self.sqrt_s(fd, fs),
+            (0x11, 0x10, _, 1) => return self.sub_s(fd, fs, ft),
+            (0x11, 0x10, 0, 0xD) => return self.trunc_s(fd,fs),
+            _ => {},
+        }
 
-    fn decode_cp0(&mut self, instruction: u32)
-    {
-        let opcode = instruction >> 26;
-        let rs = ((instruction >> 21) & 0b11111) as u8;
-        let rt = ((instruction >> 16) & 0b11111) as u8;
-        let rd = ((instruction >> 11) & 0b11111) as u8;
-        let funct = (instruction & 0b111111) as u8;
+        match (opcode, opcode2, after_late_cc, last)
+        {
+            (0x11, 0x10, 0, 0x32) => return self.c_eq_s(late_cc, fs, ft),
+            (0x11, 0x10, 0, 0x3E) => return self.c_le_s(late_cc, fs, ft),
+            (0x11, 0x10, 0, 0x3C) => return self.c_lt_s(late_cc, fs, ft),
+            _ => {},
+        }
 
-        match (opcode, rs, funct)
+        match (opcode, opcode2, early_cc, after_early_cc, last)
         {
-            (16, 0, 0) => self.mfc0(rt, rd),
-            (16, 4, 0) => self.mtc0(rt, rd),
-            // lwc0?
-            // swc0?
+            (0x11, 0x10, early_cc, 0, 0x11) => return self.movf_s(fd, fs, early_cc),
+            (0x11, 0x10, early_cc, 1, 0x11) => return self.movt_s(fd, fs, early_cc),
             _ => {},
-        };
+        }
+
+        // Not a single precision instruction or a move, an odd double register or reserved.
+        if self.decode_cp1_double(instruction).is_err()
+        {
+            self.execute_exception(ExceptionCode::ReservedInstruction, None);
+        }
     }
 
-    fn decode_cp1(&mut self, instruction: u32)
+    // Instructions on the even/odd register pairs, failing on an odd register number or an unknown encoding.
+    fn decode_cp1_double(&mut self, instruction: u32) -> Result<(), FpRegError>
     {
         let opcode = instruction >> 26;
         let opcode2 = ((instruction >> 21) & 0b11111) as u8;
@@ -254,87 +944,62 @@ impl CPU
 
         match opcode
         {
-            0x39 => self.swc1(ft, opcode2, offset),
-            0x31 => self.lwc1(ft, opcode2, offset),
-            _ => {},
-        }
-
-        match (opcode, opcode2, fd, last)
-        {
-            (0x11, 0, 0, 0) => self.mfc1(ft, fs),
-            (0x11, 4, 0, 0) => self.mtc1(ft, fs),
+            0x35 => return self.ldc1(ft, opcode2, offset),
+            0x3D => return self.sdc1(ft, opcode2, offset),
             _ => {},
         }
 
         match (opcode, opcode2, ft, last)
         {
-            (0x11, 1, 0, 5) => self.abs_d(fd, fs),
-            (0x11, 0, 0, 5) => self.abs_s(fd, fs),
-            (0x11, 0x11, _, 0) => self.add_d(fd, fs, ft),
-            (0x11, 0x10, _, 0) => self.add_s(fd, fs, ft),
-            (0x11, 0x11, 0, 0xE) => self.ceil_w_d(fd, fs),
-            (0x11, 0x10, 0, 0xE) => self.ceil_w_s(fd, fs),
-            (0x11, 0x10, 0, 0x21) => self.cvt_d_s(fd, fs),
-            (0x11, 0x14, 0, 0x21) => self.cvt_d_w(fd, fs),
-            (0x11, 0x11, 0, 0x20) => self.cvt_s_d(fd, fs),
-            (0x11, 0x14, 0, 0x20) => self.cvt_s_w(fd, fs),
-            (0x11, 0x11, 0, 0x24) => self.cvt_w_d(fd, fs),
-            (0x11, 0x10, 0, 0x24) => self.cvt_w_s(fd, fs),
-            (0x11, 0x11, _, 3) => self.div_d(fd, fs, ft),
-            (0x11, 0x10, _, 3) => self.div_s(fd, fs, ft),
-            (0x11, 0x11, 0, 0xF) => self.floor_w_d(fd, fs),
-            (0x11, 0x10, 0, 0xF) => self.floor_w_s(fd, fs),
-            (0x11, 0x11, 0, 6) => self.mov_d(fd, fs),
-            (0x11, 0x10, 0, 6) => self.mov_s(fd, fs),
-            (0x11, 0x11, rt, 0x13) => self.movn_d(fd, fs, rt), // rt instead of ft
-            (0x11, 0x10, rt, 0x13) => self.movn_s(fd, fs, rt), // rt instead of ft
-            (0x11, 0x11, rt, 0x12) => self.movz_d(fd, fs, rt), // rt instead of ft
-            (0x11, 0x10, rt, 0x12) => self.movz_s(fd, fs, rt), // rt instead of ft
-            (0x11, 0x11, _, 2) => self.mul_d(fd, fs, ft),
-            (0x11, 0x10, _, 2) => self.mul_s(fd, fs, ft),
-            (0x11, 0x11, 0, 7) => self.neg_d(fd, fs),
-            (0x11, 0x10, 0, 7) => self.neg_s(fd, fs),
-            (0x11, 0x11, 0, 0xC) => self.round_w_d(fd, fs),
-            (0x11, 0x10, 0, 0xC) => self.round_w_s(fd, fs),
-            (0x11, 0x11, 0, 4) => self.sqrt_d(fd, fs),
-            (0x11, 0x10, 0, 4) => self.sqrt_s(fd, fs),
-            (0x11, 0x11, _, 1) => self.sub_d(fd, fs, ft),
-            (0x11, 0x10, _, 1) => self.sub_s(fd, fs, ft),
-            (0x11, 0x11, 0, 0xD) => self.trunc_d(fd, fs),
-            (0x11, 0x10, 0, 0xD) => self.trunc_s(fd,fs),
+            (0x11, 0x11, 0, 5) => return self.abs_d(fd, fs),
+            (0x11, 0x11, _, 0) => return self.add_d(fd, fs, ft),
+            (0x11, 0x11, 0, 0xE) => return self.ceil_w_d(fd, fs),
+            (0x11, 0x10, 0, 0x21) => return self.cvt_d_s(fd, fs),
+            (0x11, 0x14, 0, 0x21) => return self.cvt_d_w(fd, fs),
+            (0x11, 0x11, 0, 0x20) => return self.cvt_s_d(fd, fs),
+            (0x11, 0x11, 0, 0x24) => return self.cvt_w_d(fd, fs),
+            (0x11, 0x11, _, 3) => return self.div_d(fd, fs, ft),
+            (0x11, 0x11, 0, 0xF) => return self.floor_w_d(fd, fs),
+            (0x11, 0x11, 0, 6) => return self.mov_d(fd, fs),
+            (0x11, 0x11, rt, 0x13) => return self.movn_d(fd, fs, rt), // rt instead of ft
+            (0x11, 0x11, rt, 0x12) => return self.movz_d(fd, fs, rt), // rt instead of ft
+            (0x11, 0x11, _, 2) => return self.mul_d(fd, fs, ft),
+            (0x11, 0x11, 0, 7) => return self.neg_d(fd, fs),
+            (0x11, 0x11, 0, 0xC) => return self.round_w_d(fd, fs),
+            (0x11, 0x11, 0, 4) => return self.sqrt_d(fd, fs),
+            (0x11, 0x11, _, 1) => return self.sub_d(fd, fs, ft),
+            (0x11, 0x11, 0, 0xD) => return self.trunc_d(fd, fs),
             _ => {},
         }
 
         match (opcode, opcode2, after_late_cc, last)
         {
-            (0x11, 0x11, 0, 0x32) => self.c_eq_d(late_cc, fs, ft),
-            (0x11, 0x10, 0, 0x32) => self.c_eq_s(late_cc, fs, ft),
-            (0x11, 0x11, 0, 0x3E) => self.c_le_d(late_cc, fs, ft),
-            (0x11, 0x10, 0, 0x3E) => self.c_le_s(late_cc, fs, ft),
-            (0x11, 0x11, 0, 0x3C) => self.c_lt_d(late_cc, fs, ft),
-            (0x11, 0x10, 0, 0x3C) => self.c_lt_s(late_cc, fs, ft),
+            (0x11, 0x11, 0, 0x32) => return self.c_eq_d(late_cc, fs, ft),
+            (0x11, 0x11, 0, 0x3E) => return self.c_le_d(late_cc, fs, ft),
+            (0x11, 0x11, 0, 0x3C) => return self.c_lt_d(late_cc, fs, ft),
             _ => {},
         }
 
         match (opcode, opcode2, early_cc, after_early_cc, last)
         {
-            (0x11, 0x11, early_cc, 0, 0x11) => self.movf_d(fd, fs, early_cc),
-            (0x11, 0x10, early_cc, 0, 0x11) => self.movf_s(fd, fs, early_cc),
-            (0x11, 0x11, early_cc, 1, 0x11) => self.movt_d(fd, fs, early_cc),
-            (0x11, 0x10, early_cc, 1, 0x11) => self.movt_s(fd, fs, early_cc),
+            (0x11, 0x11, early_cc, 0, 0x11) => return self.movf_d(fd, fs, early_cc),
+            (0x11, 0x11, early_cc, 1, 0x11) => return self.movt_d(fd, fs, early_cc),
             _ => {},
         }
+
+        Err(FpRegError) // reserved, not a double precision instruction either
     }
 
-    fn decode_trap_instruction(&mut self, instruction: u32)
+    fn decode_trap_instruction(&mut self, instruction: u32) -> bool
     {
         let opcode = instruction >> 26;
         let rs = ((instruction >> 21) & 0b11111) as u8;
         let rt = ((instruction >> 16) & 0b11111) as u8;
         let imm = (instruction & 0xFFFF) as u16;
+        let funct = instruction & 0b111111; // bits 6-15 hold a code for the handler, ignored here
 
         // trap instructions
-        match (opcode, rt, imm)
+        match (opcode, rt, funct)
         {
             (0, _,  0x34) => self.teq(rs, rt),
             (1, 0xc, _) => self.teqi(rs, imm),
@@ -348,8 +1013,10 @@ impl CPU
             (0, _, 0x33) => self.tltu(rs, rt),
             (1, 0xA, _) => self.tlti(rs, imm),
             (1, 0xB, _) => self.tltiu(rs, imm),
-            _ => {},
+            _ => return false,
         };
+
+        true
     }
 
     fn decode_int_instruction(&mut self, instruction: u32)
@@ -366,6 +1033,8 @@ impl CPU
         match (opcode, funct)
         {
             (0, 0) => self.sll(rd, rt, shamt),
+            (0, 1) if rt & 0b11 == 0 => self.movf(rd, rs, rt >> 2), // rt holds cc and tf
+            (0, 1) if rt & 0b11 == 1 => self.movt(rd, rs, rt >> 2),
             (0, 2) => self.srl(rd, rt, shamt),
             (0, 3) => self.sra(rd, rt, shamt),
             (0, 4) => self.sllv(rd, rt, rs),
@@ -373,7 +1042,7 @@ impl CPU
             (0, 7) => self.srav(rd, rt, rs),
             (0, 8) => self.jr(rs),
             (0, 9) => self.jalr(rd, rs),
-            (0, 12) => self.syscall(),
+            (0, 12) => self.syscall((instruction >> 6) & 0xF_FFFF),
             (0, 16) => self.mfhi(rd),
             (0, 17) => self.mthi(rs),
             (0, 18) => self.mflo(rd),
@@ -406,6 +1075,9 @@ impl CPU
             (13, _) => self.ori(rt, rs, imm),
             (14, _) => self.xori(rt, rs, imm),
             (15, _) => self.lui(rt, imm),
+            (0x1C, 0x02) => self.mul(rd, rs, rt), // SPECIAL2
+            (0x1C, 0x20) => self.clz(rd, rs),
+            (0x1C, 0x21) => self.clo(rd, rs),
             (32, _) => self.lb(rt, rs, imm),
             (33, _) => self.lh(rt, rs, imm),
             (34, _) => self.lwl(rt, rs, imm),
@@ -416,13 +1088,16 @@ impl CPU
             (40, _) => self.sb(rt, rs, imm),
             (41, _) => self.sh(rt, rs, imm),
             (43, _) => self.sw(rt, rs, imm),
-            _ => panic!("Bad instruction: {:X}", instruction),
+            (0x30, _) => self.ll(rt, rs, imm),
+            (0x38, _) => self.sc(rt, rs, imm),
+            (0x2F, _) | (0x33, _) => {}, // cache and pref, there are no caches to act on
+            _ => self.execute_exception(ExceptionCode::ReservedInstruction, None),
         }
     }
 
     fn write_back(&mut self)
     {
-        if let Some(_) = self.memory_buffer.partial_write
+        if self.memory_buffer.partial_write.is_some()
         {
             self.partial_write_back();
             return;
@@ -448,7 +1123,7 @@ impl CPU
             other_address =>
             {
                 let cp1_address = (other_address - 32) as usize;
-                let data: f32 = unsafe {mem::transmute(data)};
+                let data = f32::from_bits(data);
                 self.cp1_reg[cp1_address] = data;
             },
         }
@@ -481,7 +1156,7 @@ impl CPU
     }
 }
 
-impl CPU // opcodes
+impl Cpu // opcodes
 {
     fn sll(&mut self, rd: u8, rt: u8, shamt: u8)
     {
@@ -492,6 +1167,22 @@ impl CPU // opcodes
         self.write_to_reg(rd, result);
     }
 
+    fn movf(&mut self, rd: u8, rs: u8, cc_num: u8)
+    {
+        if !self.cc[cc_num as usize]
+        {
+            self.write_to_reg(rd, self.int_reg[rs as usize]);
+        }
+    }
+
+    fn movt(&mut self, rd: u8, rs: u8, cc_num: u8)
+    {
+        if self.cc[cc_num as usize]
+        {
+            self.write_to_reg(rd, self.int_reg[rs as usize]);
+        }
+    }
+
     fn srl(&mut self, rd: u8, rt: u8, shamt: u8)
     {
         let op1 = self.int_reg[rt as usize];
@@ -513,7 +1204,7 @@ impl CPU // opcodes
     fn sllv(&mut self, rd: u8, rt: u8, rs: u8)
     {
         let op1 = self.int_reg[rt as usize];
-        let op2 = self.int_reg[rs as usize];
+        let op2 = self.int_reg[rs as usize] & 0b11111; // only the low 5 bits count
 
         let result = op1 << op2;
         self.write_to_reg(rd, result);
@@ -522,7 +1213,7 @@ impl CPU // opcodes
     fn srlv(&mut self, rd: u8, rt: u8, rs: u8)
     {
         let op1 = self.int_reg[rt as usize];
-        let op2 = self.int_reg[rs as usize];
+        let op2 = self.int_reg[rs as usize] & 0b11111;
 
         let result = op1 >> op2;
         self.write_to_reg(rd, result);
@@ -531,7 +1222,7 @@ impl CPU // opcodes
     fn srav(&mut self, rd: u8, rt: u8, rs: u8)
     {
         let op1 = self.int_reg[rt as usize] as i32;
-        let op2 = self.int_reg[rs as usize] as i32;
+        let op2 = self.int_reg[rs as usize] & 0b11111;
 
         let result = (op1 >> op2) as u32;
         self.write_to_reg(rd, result);
@@ -539,22 +1230,34 @@ impl CPU // opcodes
 
     fn jr(&mut self, rs: u8)
     {
-        self.pc = self.int_reg[rs as usize];
+        let target = self.int_reg[rs as usize];
+        self.track_return(target);
+        self.jump_to(target);
     }
 
     fn jalr(&mut self, rd: u8, rs: u8)
     {
-        self.write_to_reg(rd, self.pc);
-        self.pc = self.int_reg[rs as usize];
+        let target = self.int_reg[rs as usize];
+        self.write_to_reg(rd, self.return_address());
+        self.track_call();
+        self.jump_to(target);
     }
 
-    fn syscall(&mut self)
+    fn syscall(&mut self, code: u32)
     {
+        self.syscall_code = code; // read by the handler or the host
+        if self.host_syscalls
+        {
+            self.pending_syscall = true; // Serviced by the host once the instruction completes.
+            return;
+        }
+
         self.execute_exception(ExceptionCode::Syscall, None); // Let the OS handle it.
     }
 
     fn mfhi(&mut self, rd: u8)
     {
+        self.wait_for_hilo();
         self.write_to_reg(rd, self.hi);
     }
 
@@ -565,6 +1268,7 @@ impl CPU // opcodes
 
     fn mflo(&mut self, rd: u8)
     {
+        self.wait_for_hilo();
         self.write_to_reg(rd, self.lo);
     }
 
@@ -575,8 +1279,8 @@ impl CPU // opcodes
 
     fn mult(&mut self, rs: u8, rt: u8) // signed multiplication
     {
-        let op1 = self.int_reg[rs as usize] as u64 as i64;
-        let op2 = self.int_reg[rt as usize] as u64 as i64;
+        let op1 = self.int_reg[rs as usize] as i32 as i64;
+        let op2 = self.int_reg[rt as usize] as i32 as i64;
 
         let result = (op1 * op2) as u64;
 
@@ -585,6 +1289,7 @@ impl CPU // opcodes
 
         self.hi = high;
         self.lo = low;
+        self.hilo_busy = self.hilo_latency;
     }
 
     fn multu(&mut self, rs: u8, rt: u8) // unsigned multiplication
@@ -599,30 +1304,52 @@ impl CPU // opcodes
 
         self.hi = high;
         self.lo = low;
+        self.hilo_busy = self.hilo_latency;
+    }
+
+    // The low word of the product goes to rd. No overflow trap, HI and LO are left alone.
+    fn mul(&mut self, rd: u8, rs: u8, rt: u8)
+    {
+        let result = self.int_reg[rs as usize].wrapping_mul(self.int_reg[rt as usize]);
+        self.write_to_reg(rd, result);
     }
 
     fn div(&mut self, rs: u8, rt: u8) // signed division
     {
         let op1 = self.int_reg[rs as usize] as i32;
         let op2 = self.int_reg[rt as usize] as i32;
+        if op2 == 0
+        {
+            return; // The result is unpredictable on MIPS, HI and LO are left as they were.
+        }
 
-        let quotient = (op1 / op2) as u32;
-        let modulo = (op1 % op2) as u32;
+        // The remainder takes the sign of the dividend like Rust's %, -7 / 2 is -3 remainder -1.
+        let (quotient, modulo) = match (op1, op2)
+        {
+            (i32::MIN, -1) => (i32::MIN, 0), // the quotient overflows, Rust would panic
+            _ => (op1 / op2, op1 % op2),
+        };
 
-        self.lo = quotient;
-        self.hi = modulo;
+        self.lo = quotient as u32;
+        self.hi = modulo as u32;
+        self.hilo_busy = self.hilo_latency;
     }
 
     fn divu(&mut self, rs: u8, rt: u8) // unsigned division
     {
         let op1 = self.int_reg[rs as usize];
         let op2 = self.int_reg[rt as usize];
+        if op2 == 0
+        {
+            return; // unpredictable, like div
+        }
 
         let quotient = op1 / op2;
         let modulo = op1 % op2;
 
         self.lo = quotient;
         self.hi = modulo;
+        self.hilo_busy = self.hilo_latency;
     }
 
     fn add(&mut self, rd: u8, rs: u8, rt: u8) // signed division with exception on overflow
@@ -630,12 +1357,13 @@ impl CPU // opcodes
         let op1 = self.int_reg[rs as usize] as i32;
         let op2 = self.int_reg[rt as usize] as i32;
 
-        let result = op1 + op2;
+        let (result, overflow) = op1.overflowing_add(op2);
 
-        // overflow check
-        if (op1 > 0 && op2 > 0 && result < 0) | (op1 < 0 && op2 < 0 && result > 0)
+        self.overflow = overflow;
+        if overflow && self.trap_on_overflow
         {
             self.execute_exception(ExceptionCode::Overflow, None);
+            return;
         }
 
         self.write_to_reg(rd, result as u32);
@@ -646,7 +1374,7 @@ impl CPU // opcodes
         let op1 = self.int_reg[rs as usize];
         let op2 = self.int_reg[rt as usize];
 
-        let result = op1 + op2;
+        let result = op1.wrapping_add(op2);
 
         self.write_to_reg(rd, result);
     }
@@ -656,11 +1384,13 @@ impl CPU // opcodes
         let op1 = self.int_reg[rs as usize] as i32;
         let op2 = self.int_reg[rt as usize] as i32;
 
-        let result = op1 - op2;
+        let (result, overflow) = op1.overflowing_sub(op2);
 
-        if (op1 < 0 && op2 > 0 && result > 0) || (op1 > 0 && op2 < 0 && result < 0)
+        self.overflow = overflow;
+        if overflow && self.trap_on_overflow
         {
             self.execute_exception(ExceptionCode::Overflow, None);
+            return;
         }
 
         self.write_to_reg(rd, result as u32);
@@ -671,7 +1401,7 @@ impl CPU // opcodes
         let op1 = self.int_reg[rs as usize];
         let op2 = self.int_reg[rt as usize];
 
-        let result = op1 - op2;
+        let result = op1.wrapping_sub(op2);
 
         self.write_to_reg(rd, result);
     }
@@ -721,9 +1451,7 @@ impl CPU // opcodes
         let op1 = self.int_reg[rs as usize] as i32;
         let op2 = self.int_reg[rt as usize] as i32;
 
-        let difference = op1 - op2;
-
-        let result = if difference < 0
+        let result = if op1 < op2
         {1}
         else
         {0};
@@ -746,17 +1474,18 @@ impl CPU // opcodes
     fn j(&mut self, address: u32)
     {
         let address = address & 0x_03_FF_FF_FF; // lower 26 bits
-        let upper = self.pc & 0xF0000000; // upper 4 bits
+        let upper = self.pc & 0xF0000000; // upper 4 bits of the delay slot address, the pc is already past the jump
         let lower = address << 2;
 
         let new_address = upper | lower;
-        self.pc = new_address;
+        self.jump_to(new_address);
     }
 
     fn jal(&mut self, address: u32)
     {
         const RETURN_ADDRESS_REG: u8 = 31;
-        self.write_to_reg(RETURN_ADDRESS_REG, self.pc);
+        self.write_to_reg(RETURN_ADDRESS_REG, self.return_address());
+        self.track_call();
 
         self.j(address);
     }
@@ -779,7 +1508,7 @@ impl CPU // opcodes
 
     fn blez(&mut self, rs: u8, imm: u16)
     {
-        if self.int_reg[rs as usize] <= 0
+        if self.int_reg[rs as usize] as i32 <= 0
         {
             self.branch(imm);
         }
@@ -787,7 +1516,7 @@ impl CPU // opcodes
 
     fn bgtz(&mut self, rs: u8, imm: u16)
     {
-        if self.int_reg[rs as usize] > 0
+        if self.int_reg[rs as usize] as i32 > 0
         {
             self.branch(imm);
         }
@@ -798,11 +1527,13 @@ impl CPU // opcodes
         let op1 = self.int_reg[rs as usize] as i32;
         let op2 = imm as i16 as i32;
 
-        let result = op1 + op2;
+        let (result, overflow) = op1.overflowing_add(op2);
 
-        if (op1 < 0 && op2 < 0 && result > 0) || (op1 > 0 && op2 > 0 && result < 0)
+        self.overflow = overflow;
+        if overflow && self.trap_on_overflow
         {
             self.execute_exception(ExceptionCode::Overflow, None);
+            return;
         }
 
         self.write_to_reg(rt, result as u32);
@@ -813,24 +1544,24 @@ impl CPU // opcodes
         let op1 = self.int_reg[rs as usize];
         let op2 = imm as i16 as i32 as u32; // sign extended
 
-        let result = op1 + op2;
+        let result = op1.wrapping_add(op2);
 
         self.write_to_reg(rt, result);
     }
 
-    fn slti(&mut self, rt: u8, rs: u8, imm: u16)
+    fn slti(&mut self, rt: u8, rs: u8, imm: u16) // signed comparison
     {
         let op1 = self.int_reg[rs as usize] as i32;
-        let op2 = imm as i16 as i32;
+        let op2 = imm as i16 as i32; // sign extended
 
         let result = if op1 < op2 {1} else {0};
         self.write_to_reg(rt, result);
     }
 
-    fn sltiu(&mut self, rt: u8, rs: u8, imm: u16)
+    fn sltiu(&mut self, rt: u8, rs: u8, imm: u16) // unsigned comparison
     {
         let op1 = self.int_reg[rs as usize];
-        let op2 = imm as i16 as i32 as u32;
+        let op2 = imm as i16 as i32 as u32; // sign extended, then compared as unsigned
 
         let result = if op1 < op2 {1} else {0};
         self.write_to_reg(rt, result);
@@ -863,91 +1594,100 @@ impl CPU // opcodes
         self.write_to_reg(rt, result);
     }
 
+    fn clz(&mut self, rd: u8, rs: u8)
+    {
+        let result = self.int_reg[rs as usize].leading_zeros(); // 32 for 0
+        self.write_to_reg(rd, result);
+    }
+
+    fn clo(&mut self, rd: u8, rs: u8)
+    {
+        let result = self.int_reg[rs as usize].leading_ones(); // 32 for 0xFFFFFFFF
+        self.write_to_reg(rd, result);
+    }
+
     fn lui(&mut self, rt: u8, imm: u16)
     {
         let result = (imm as u32) << 16;
         self.write_to_reg(rt, result);
     }
 
-    fn lb(&mut self, rt: u8, rs: u8, imm: u16)
+    // Loads are sign or zero extended to the full register in write_back.
+    fn load(&mut self, rt: u8, rs: u8, imm: u16, size: u8, sign_extended: bool)
     {
-        let address = self.int_reg[rs as usize] + (imm as i16 as i32 as u32);
+        let address = self.int_reg[rs as usize].wrapping_add(imm as i16 as i32 as u32);
         self.memory_buffer = MemoryBuffer
         {
             address,
             data: 0,
-            data_size: 1,
+            data_size: size,
             store: false,
             write_back_register: rt,
-            sign_extended: false,
+            sign_extended,
             partial_write: None,
+            second_word: None,
         };
     }
 
+    fn lb(&mut self, rt: u8, rs: u8, imm: u16)
+    {
+        self.load(rt, rs, imm, 1, true);
+    }
+
     fn lh(&mut self, rt: u8, rs: u8, imm: u16)
     {
-        let address = self.int_reg[rs as usize] + (imm as i16 as i32 as u32);
-        self.memory_buffer = MemoryBuffer
-        {
-            address,
-            data: 0,
-            data_size: 2,
-            store: false,
-            write_back_register: rt,
-            sign_extended: true,
-            partial_write: None,
-        }
+        self.load(rt, rs, imm, 2, true);
+        self.check_alignment(self.memory_buffer.address, 2, false); // an odd address raises AdEL
     }
 
     fn lw(&mut self, rt: u8, rs: u8, imm: u16)
     {
-        let address = self.int_reg[rs as usize] + (imm as i16 as i32 as u32);
-        self.memory_buffer = MemoryBuffer
+        self.load(rt, rs, imm, 4, false); // full word, nothing to extend
+        self.check_alignment(self.memory_buffer.address, 4, false);
+    }
+
+    fn ll(&mut self, rt: u8, rs: u8, imm: u16)
+    {
+        self.load(rt, rs, imm, 4, false);
+        // A misaligned ll raises AdEL and takes no reservation.
+        self.ll_bit = self.check_alignment(self.memory_buffer.address, 4, false);
+    }
+
+    fn sc(&mut self, rt: u8, rs: u8, imm: u16)
+    {
+        let address = self.int_reg[rs as usize].wrapping_add(imm as i16 as i32 as u32);
+        if !self.check_alignment(address, 4, true)
         {
-            address,
-            data: 0,
-            data_size: 4,
-            store: false,
-            write_back_register: rt,
-            sign_extended: true, // whatever
-            partial_write: None,
+            return; // AdES, rt keeps its value
+        }
+
+        if self.ll_bit
+        {
+            self.sw(rt, rs, imm);
+            self.write_to_reg(rt, 1);
+        }
+        else
+        {
+            self.write_to_reg(rt, 0); // reservation lost, nothing is stored
         }
+        self.ll_bit = false;
     }
 
     fn lbu(&mut self, rt: u8, rs: u8, imm: u16)
     {
-        let address = self.int_reg[rs as usize] + (imm as i16 as i32 as u32);
-        self.memory_buffer = MemoryBuffer
-        {
-            address,
-            data: 0,
-            data_size: 1,
-            store: false,
-            write_back_register: rt,
-            sign_extended: false,
-            partial_write: None,
-        }
+        self.load(rt, rs, imm, 1, false);
     }
 
     fn lhu(&mut self, rt: u8, rs: u8, imm: u16)
     {
-        let address = self.int_reg[rs as usize] + (imm as i16 as i32 as u32);
-        self.memory_buffer = MemoryBuffer
-        {
-            address,
-            data: 0,
-            data_size: 2,
-            store: false,
-            write_back_register: rt,
-            sign_extended: false,
-            partial_write: None,
-        }
+        self.load(rt, rs, imm, 2, false);
+        self.check_alignment(self.memory_buffer.address, 2, false);
     }
 
     fn sb(&mut self, rt: u8, rs: u8, imm: u16)
     {
         let data = self.int_reg[rt as usize] & 0xFF;
-        let address = self.int_reg[rs as usize] + (imm as i16 as i32 as u32);
+        let address = self.int_reg[rs as usize].wrapping_add(imm as i16 as i32 as u32);
 
         self.memory_buffer = MemoryBuffer
         {
@@ -958,13 +1698,14 @@ impl CPU // opcodes
             write_back_register: 0,
             sign_extended: false,
             partial_write: None,
+            second_word: None,
         }
     }
 
     fn sh(&mut self, rt: u8, rs: u8, imm: u16)
     {
         let data = self.int_reg[rt as usize] & 0xFFFF;
-        let address = self.int_reg[rs as usize] + (imm as i16 as i32 as u32);
+        let address = self.int_reg[rs as usize].wrapping_add(imm as i16 as i32 as u32);
 
         self.memory_buffer = MemoryBuffer
         {
@@ -975,13 +1716,15 @@ impl CPU // opcodes
             write_back_register: 0,
             sign_extended: false,
             partial_write: None,
-        }
+            second_word: None,
+        };
+        self.check_alignment(address, 2, true); // an odd address raises AdES
     }
 
     fn sw(&mut self, rt: u8, rs: u8, imm: u16)
     {
         let data = self.int_reg[rt as usize];
-        let address = self.int_reg[rs as usize] + (imm as i16 as i32 as u32);
+        let address = self.int_reg[rs as usize].wrapping_add(imm as i16 as i32 as u32);
 
         self.memory_buffer = MemoryBuffer
         {
@@ -992,12 +1735,14 @@ impl CPU // opcodes
             write_back_register: 0,
             sign_extended: false,
             partial_write: None,
-        }
+            second_word: None,
+        };
+        self.check_alignment(address, 4, true);
     }
 
     fn lwl(&mut self, rt: u8, base: u8, offset: u16)
     {
-        let address = (self.int_reg[base as usize] as i32 + (offset as i16 as i32)) as u32;
+        let address = self.int_reg[base as usize].wrapping_add(offset as i16 as i32 as u32);
         let word_address = address - address % 4;
 
         let bytes_count = (4 - address % 4) as usize;
@@ -1012,12 +1757,13 @@ impl CPU // opcodes
             write_back_register: rt,
             sign_extended: false,
             partial_write,
+            second_word: None,
         }
     }
 
     fn lwr(&mut self, rt: u8, base: u8, offset: u16)
     {
-        let address = (self.int_reg[base as usize] as i32 + (offset as i16 as i32)) as u32;
+        let address = self.int_reg[base as usize].wrapping_add(offset as i16 as i32 as u32);
         let word_address = address - address % 4;
 
         let bytes_count = (address % 4 + 1) as usize;
@@ -1032,25 +1778,63 @@ impl CPU // opcodes
             write_back_register: rt,
             sign_extended: false,
             partial_write,
+            second_word: None,
+        }
+    }
+
+    // No TLB, no caches and no Config2, only the coprocessors are reported.
+    fn config1(&self) -> u32
+    {
+        let mut config1 = 0;
+        if self.cp2_enabled
+        {
+            config1 |= CONFIG1_C2;
+        }
+        if self.cp0_reg[12] & STATUS_CU1 != 0
+        {
+            config1 |= CONFIG1_FP;
         }
+        config1
     }
 
-    fn mfc0(&mut self, rt: u8, rd: u8)
+    // Select (sel) picks among the registers sharing a number, only Config has more than one.
+    fn mfc0(&mut self, rt: u8, rd: u8, select: u8)
     {
         if !self.is_kernel_mode()
         {
-            panic!("Bad privilege");
+            self.execute_exception(ExceptionCode::CoprocessorUnusable, None);
+            return;
         }
-        self.write_to_reg(rt, self.cp0_reg[rd as usize]);
+
+        let value = match (rd, select)
+        {
+            (16, 1) => self.config1(),
+            (_, 0) => self.cp0_reg[rd as usize],
+            _ => 0, // not implemented
+        };
+        self.write_to_reg(rt, value);
     }
 
-    fn mtc0(&mut self, rt: u8, rd: u8)
+    fn mtc0(&mut self, rt: u8, rd: u8, select: u8)
     {
         if !self.is_kernel_mode()
         {
-            panic!("Bad privilege");
+            self.execute_exception(ExceptionCode::CoprocessorUnusable, None);
+            return;
+        }
+
+        match (rd, select)
+        {
+            (13, 0) => // Cause, only the software interrupts are writable
+            {
+                let value = self.int_reg[rt as usize] & CAUSE_IP_SOFTWARE;
+                self.software_interrupts = (value >> 8) as u8;
+                self.cp0_reg[13] = (self.cp0_reg[13] & !CAUSE_IP_SOFTWARE) | value;
+            },
+            (16, _) => {}, // Config is read only
+            (_, 0) => self.cp0_reg[rd as usize] = self.int_reg[rt as usize],
+            _ => {}, // not implemented
         }
-        self.cp0_reg[rd as usize] = self.int_reg[rt as usize];
     }
 
 
@@ -1102,9 +1886,10 @@ impl CPU // opcodes
             self.execute_exception(ExceptionCode::CalledTrap, None);
         }
     }
+
     fn tgei(&mut self, rs: u8, imm: u16)
     {
-        if self.int_reg[rs as usize] as i32 != imm as i16 as i32
+        if self.int_reg[rs as usize] as i32 >= imm as i16 as i32
         {
             self.execute_exception(ExceptionCode::CalledTrap, None);
         }
@@ -1112,7 +1897,7 @@ impl CPU // opcodes
 
     fn tgeiu(&mut self, rs: u8, imm: u16)
     {
-        if self.int_reg[rs as usize] >= imm as i16 as i32 as u32 // Imm is sign extended?
+        if self.int_reg[rs as usize] >= imm as i16 as i32 as u32 // imm is sign extended, then compared as unsigned
         {
             self.execute_exception(ExceptionCode::CalledTrap, None);
         }
@@ -1144,7 +1929,7 @@ impl CPU // opcodes
 
     fn tltiu(&mut self, rs: u8, imm: u16)
     {
-        if self.int_reg[rs as usize] < imm as i16 as i32 as u32 // Imm is signed extended?
+        if self.int_reg[rs as usize] < imm as i16 as i32 as u32 // imm is sign extended, then compared as unsigned
         {
             self.execute_exception(ExceptionCode::CalledTrap, None);
         }
@@ -1154,30 +1939,107 @@ impl CPU // opcodes
     {
         if !self.is_kernel_mode()
         {
-            panic!("Bad privilege");
+            self.execute_exception(ExceptionCode::CoprocessorUnusable, None);
+            return;
         }
 
-        let status = &mut self.cp0_reg[12];
+        self.cp0_reg[12] &= !STATUS_EXL; // Back to the mode and interrupt state from before the exception.
+    }
 
-        let old_previous = (*status & 0b111100) >> 2;
-        *status &= !0b1111; // clear previous and current
-        *status |= old_previous; // restore previous and current
+    fn wait(&mut self)
+    {
+        if !self.is_kernel_mode()
+        {
+            self.execute_exception(ExceptionCode::CoprocessorUnusable, None);
+            return;
+        }
+        self.waiting = true;
     }
 
     fn eret(&mut self)
     {
-        self.rfe();
-        self.pc = self.cp0_reg[14];
+        if !self.is_kernel_mode()
+        {
+            self.execute_exception(ExceptionCode::CoprocessorUnusable, None);
+            return;
+        }
+
+        let status = &mut self.cp0_reg[12];
+        if *status & STATUS_ERL != 0
+        {
+            *status &= !STATUS_ERL;
+            self.pc = self.cp0_reg[30]; // ErrorEPC
+        }
+        else
+        {
+            *status &= !STATUS_EXL;
+            self.pc = self.cp0_reg[14]; // EPC
+        }
+        self.ll_bit = false;
+        self.delayed_jump = None;
+
+        #[cfg(feature = "logging")]
+        log::debug!("eret to 0x{:08X}, Cause 0x{:08X}, {} mode",
+            self.pc, self.cp0_reg[13], if self.is_kernel_mode() {"kernel"} else {"user"});
     }
 }
 
-impl CPU
+impl Cpu
 {
+    // Stalls until the multiply or divide unit has finished, counting the hazard.
+    fn wait_for_hilo(&mut self)
+    {
+        if self.hilo_busy > 0
+        {
+            self.hilo_hazards += 1;
+            self.stall_cycles += self.hilo_busy as u64;
+            self.cycles += self.hilo_busy as u64;
+            self.hilo_busy = 0;
+        }
+    }
+
     fn branch(&mut self, imm: u16)
     {
-        let offset = ((imm as i16) * 4) as i32;
-        let new_pc = (self.pc as i32 + offset) as u32;
-        self.pc = new_pc;
+        let offset = (imm as i16 as i32) * 4;
+        let new_pc = (self.pc as i32).wrapping_add(offset) as u32;
+        self.jump_to(new_pc);
+    }
+
+    // With delay slots the jump waits for the next instruction to be fetched.
+    fn jump_to(&mut self, target: u32)
+    {
+        match self.delay_slots
+        {
+            true => self.delayed_jump = Some(target),
+            false => self.pc = target,
+        }
+    }
+
+    // Where a call returns to, past the delay slot if there is one.
+    fn return_address(&self) -> u32
+    {
+        match self.delay_slots
+        {
+            true => self.pc.wrapping_add(4),
+            false => self.pc,
+        }
+    }
+
+    fn track_call(&mut self)
+    {
+        if self.track_calls
+        {
+            self.call_stack.push(self.return_address());
+        }
+    }
+
+    fn track_return(&mut self, target: u32)
+    {
+        // Unwind to the frame we returned to, skipping frames left without a return.
+        if let Some(frame) = self.call_stack.iter().rposition(|&address| address == target)
+        {
+            self.call_stack.truncate(frame);
+        }
     }
 
     // fn set_interrupt_pending(&mut self, interrupt_number: u8)
@@ -1206,81 +2068,192 @@ impl CPU
             self.cp0_reg[8] = address;
         }
 
-        /* Set exception cause */
+        /*
+            Set exception cause. BD is set for an exception in a delay slot, EPC then points
+            to the branch so it is run again after the handler. Interrupts are never taken
+            between a jump and its delay slot. CE is 0 unless the caller sets it afterwards
+            for an unusable coprocessor other than CP0.
+         */
+        let in_delay_slot = exception_code != ExceptionCode::Interrupt && self.delay_slot_branch.is_some();
         let cause = &mut self.cp0_reg[13];
-        *cause &= !0b1111100; // clear old exception code
-        *cause |= (exception_code as u32 & 0b11111) << 2; // set new exception code
+        *cause &= !(CAUSE_EXC_CODE | CAUSE_CE | CAUSE_BD);
+        *cause |= (exception_code as u32) << 2 & CAUSE_EXC_CODE;
+        if in_delay_slot
+        {
+            *cause |= CAUSE_BD;
+        }
+        let faulting_address = match self.delay_slot_branch
+        {
+            Some(branch) if in_delay_slot => branch,
+            _ => self.instruction_address,
+        };
 
-        /* Set processor status */
-        let status = &mut self.cp0_reg[12];
-        let previous_current_status = 0b1111; // keep statuses
-        *status &= !0b111111; // clear statuses
-        *status |= previous_current_status << 2; // save statuses in old and previous
-        // Now current status is 00 (kernel, interrupts disabled)
+        // The host handles this code, the guest carries on with the next instruction.
+        if self.host_exceptions & (1 << exception_code as u32) != 0
+        {
+            self.host_exception = Some(exception_code as u8);
+            return;
+        }
+
+        // Stop the machine before the vector, with EPC pointing where the handler would return.
+        if self.halt_on_exception
+        {
+            self.cp0_reg[14] = match exception_code
+            {
+                ExceptionCode::Interrupt => self.pc,
+                _ => faulting_address,
+            };
+            self.halted_exception = Some(exception_code as u8);
+            return;
+        }
+
+        /*
+            Set processor status. EXL puts the CPU in kernel mode with interrupts disabled,
+            KSU and IE are left as they were so eret can restore them.
+            An exception nested in a handler (EXL already set) keeps the outer EPC.
+         */
+        if self.cp0_reg[12] & STATUS_EXL == 0
+        {
+            // Interrupts are taken between instructions and return to the next one,
+            // other exceptions return to the instruction that caused them.
+            let return_address = match exception_code
+            {
+                ExceptionCode::Interrupt => self.pc,
+                _ => faulting_address,
+            };
+            self.cp0_reg[14] = return_address; // Save return address in EPC
+        }
+        self.cp0_reg[12] |= STATUS_EXL;
+        self.ll_bit = false; // The handler may have touched the reserved word.
 
+        #[cfg(feature = "logging")]
+        log::debug!("exception {:?}, EPC 0x{:08X}, Cause 0x{:08X}, entering kernel mode",
+            exception_code, self.cp0_reg[14], self.cp0_reg[13]);
 
-        self.cp0_reg[14] = self.pc; // Save return address in EPC
-        self.pc = EXCEPTION_HANDLER_ADDRESS; // Jump to exception handler
+        self.exceptions_taken += 1;
+        self.delayed_jump = None;
+        self.pc = self.exception_vector; // Jump to exception handler
     }
 }
 
-impl CPU // FP coprocessor1
+impl Cpu // FP coprocessor1
 {
-    fn get_double_precision(&self, reg_num: u8) -> f64
+    fn get_double_precision(&self, reg_num: u8) -> Result<f64, FpRegError>
     {
         if reg_num % 2 == 1
         {
-            panic!("FP register not even");
+            return Err(FpRegError);
         }
 
-        let upper: u32 = unsafe {mem::transmute(self.cp1_reg[(reg_num + 1) as usize])};
-        let lower: u32 = unsafe {mem::transmute(self.cp1_reg[reg_num as usize])};
+        let upper = self.cp1_reg[(reg_num + 1) as usize].to_bits();
+        let lower = self.cp1_reg[reg_num as usize].to_bits();
 
         let joined: u64 = ((upper as u64) << 32) | (lower as u64);
-        let result: f64 = unsafe{mem::transmute(joined)};
-        result
+        let result = f64::from_bits(joined);
+        Ok(result)
     }
 
-    fn write_to_double_register(&mut self, reg_num: u8, data: f64)
+    fn write_to_double_register(&mut self, reg_num: u8, data: f64) -> Result<(), FpRegError>
     {
         if reg_num % 2 == 1
         {
-            panic!("FP register not even");
+            return Err(FpRegError);
         }
 
-        let bits: u64 = unsafe {mem::transmute(data)};
+        let bits = data.to_bits();
 
         let upper_bits: u32 = (bits >> 32) as u32;
         let lower_bits: u32 = (bits & 0xFFFFFFFF) as u32;
 
-        let upper: f32 = unsafe {mem::transmute(upper_bits)};
-        let lower: f32 = unsafe {mem::transmute(lower_bits)};
+        let upper = f32::from_bits(upper_bits);
+        let lower = f32::from_bits(lower_bits);
 
         self.cp1_reg[reg_num as usize] = lower;
         self.cp1_reg[(reg_num as usize) + 1] = upper;
+        Ok(())
     }
 
+    // mfc1, mtc1, lwc1 and swc1 copy the exact bits, there's no conversion and no byte order involved.
     fn mfc1(&mut self, rt: u8, fs: u8)
     {
         let op1 = self.cp1_reg[fs as usize];
-        let result: u32 = unsafe {mem::transmute(op1)};
+        let result = op1.to_bits();
         self.write_to_reg(rt, result);
     }
 
-    fn mtc1(&mut self, rt: u8, fs: u8)
+    fn mtc1(&mut self, rt: u8, fs: u8)
+    {
+        let op1 = self.int_reg[rt as usize];
+        let result = f32::from_bits(op1);
+
+        self.cp1_reg[fs as usize] = result;
+    }
+
+    fn lwc1(&mut self, ft: u8, base: u8, offset: u16)
+    {
+        let offset = offset as i16;
+        let address = (self.int_reg[base as usize] as i32).wrapping_add(offset as i32) as u32;
+        if !self.check_alignment(address, 4, false)
+        {
+            return;
+        }
+
+        let register_number = ft + 32;
+
+        self.memory_buffer = MemoryBuffer
+        {
+            address,
+            data: 0,
+            data_size: 4,
+            store: false,
+            write_back_register: register_number,
+            sign_extended: false,
+            partial_write: None,
+            second_word: None,
+        }
+    }
+
+    fn swc1(&mut self, ft: u8, base: u8, offset: u16)
+    {
+        let data = self.cp1_reg[ft as usize].to_bits();
+
+        let offset = offset as i16;
+        let address = (self.int_reg[base as usize] as i32).wrapping_add(offset as i32) as u32;
+        if !self.check_alignment(address, 4, true)
+        {
+            return;
+        }
+
+        self.memory_buffer = MemoryBuffer
+        {
+            address,
+            data,
+            data_size: 4,
+            store: true,
+            write_back_register: 0,
+            sign_extended: false,
+            partial_write: None,
+            second_word: None,
+        }
+    }
+
+    /*
+        The doubleword is moved as two words, the most significant one first (big endian).
+        It goes to the odd register of the pair, the least significant word to the even one.
+     */
+    fn ldc1(&mut self, ft: u8, base: u8, offset: u16) -> Result<(), FpRegError>
     {
-        let op1 = self.int_reg[rt as usize];
-        let result: f32 = unsafe {mem::transmute(op1)};
-
-        self.cp1_reg[fs as usize] = result;
-    }
+        if ft % 2 == 1
+        {
+            return Err(FpRegError);
+        }
 
-    fn lwc1(&mut self, ft: u8, base: u8, offset: u16)
-    {
         let offset = offset as i16;
-        let address = (self.int_reg[base as usize] as i32 + offset as i32) as u32;
-
-        let register_number = ft + 32;
+        let address = (self.int_reg[base as usize] as i32).wrapping_add(offset as i32) as u32;
+        if !self.check_alignment(address, 8, false)
+        {
+            return Ok(());
+        }
 
         self.memory_buffer = MemoryBuffer
         {
@@ -1288,54 +2261,68 @@ impl CPU // FP coprocessor1
             data: 0,
             data_size: 4,
             store: false,
-            write_back_register: register_number,
+            write_back_register: ft + 32 + 1,
             sign_extended: false,
             partial_write: None,
-        }
+            second_word: Some((0, ft + 32)),
+        };
+        Ok(())
     }
 
-    fn swc1(&mut self, ft: u8, base: u8, offset: u16)
+    fn sdc1(&mut self, ft: u8, base: u8, offset: u16) -> Result<(), FpRegError>
     {
-        let data: u32 = unsafe {mem::transmute(self.cp1_reg[ft as usize])};
+        if ft % 2 == 1
+        {
+            return Err(FpRegError);
+        }
+
+        let upper = self.cp1_reg[(ft + 1) as usize].to_bits();
+        let lower = self.cp1_reg[ft as usize].to_bits();
 
         let offset = offset as i16;
-        let address = (self.int_reg[base as usize] as i32 + offset as i32) as u32;
+        let address = (self.int_reg[base as usize] as i32).wrapping_add(offset as i32) as u32;
+        if !self.check_alignment(address, 8, true)
+        {
+            return Ok(());
+        }
 
         self.memory_buffer = MemoryBuffer
         {
             address,
-            data,
+            data: upper,
             data_size: 4,
             store: true,
             write_back_register: 0,
             sign_extended: false,
             partial_write: None,
-        }
+            second_word: Some((lower, 0)),
+        };
+        Ok(())
     }
 
-    fn abs_d(&mut self, fd: u8, fs: u8)
+    fn abs_d(&mut self, fd: u8, fs: u8) -> Result<(), FpRegError>
     {
-        let op1 = self.get_double_precision(fs);
-        let result = op1.abs();
+        let op1 = self.get_double_precision(fs)?;
+        let result = f64::from_bits(op1.to_bits() & !SIGN_BIT_D);
 
-        self.write_to_double_register(fd, result);
+        self.write_to_double_register(fd, result)
     }
 
     fn abs_s(&mut self, fd: u8, fs: u8)
     {
         let op1 = self.cp1_reg[fs as usize];
-        let result = op1.abs();
+        let result = f32::from_bits(op1.to_bits() & !SIGN_BIT_S); // NaN payloads are kept
         self.cp1_reg[fd as usize] = result;
     }
 
-    fn add_d(&mut self, fd: u8, fs: u8, ft: u8)
+    fn add_d(&mut self, fd: u8, fs: u8, ft: u8) -> Result<(), FpRegError>
     {
-        let op1 = self.get_double_precision(fs);
-        let op2 = self.get_double_precision(ft);
+        let op1 = self.get_double_precision(fs)?;
+        let op2 = self.get_double_precision(ft)?;
 
-        let result = op1 + op2;
+        let result = canonical_nan_d(op1 + op2);
 
-        self.write_to_double_register(fd, result);
+        self.write_to_double_register(fd, result)
     }
 
     fn add_s(&mut self, fd: u8, fs: u8, ft: u8)
@@ -1343,19 +2330,21 @@ impl CPU // FP coprocessor1
         let op1 = self.cp1_reg[fs as usize];
         let op2 = self.cp1_reg[ft as usize];
 
-        let result = op1 + op2;
+        let result = canonical_nan_s(op1 + op2);
 
         self.cp1_reg[fd as usize] = result;
     }
 
-    fn ceil_w_d(&mut self, fd: u8, fs: u8)
+    fn ceil_w_d(&mut self, fd: u8, fs: u8) -> Result<(), FpRegError>
     {
-        let op1 = self.get_double_precision(fs);
+        let op1 = self.get_double_precision(fs)?;
         let ceil_value = op1.ceil() as i32;
 
-        let bits: f32 = unsafe {mem::transmute(ceil_value)};
+        let bits = f32::from_bits(ceil_value as u32);
 
         self.cp1_reg[fd as usize] = bits;
+
+        Ok(())
     }
 
     fn ceil_w_s(&mut self, fd: u8, fs: u8)
@@ -1363,19 +2352,21 @@ impl CPU // FP coprocessor1
         let op1 = self.cp1_reg[fs as usize];
         let ceil_value = op1.ceil() as i32;
 
-        let result: f32 = unsafe {mem::transmute(ceil_value)};
+        let result = f32::from_bits(ceil_value as u32);
         self.cp1_reg[fd as usize] = result;
     }
 
-    fn c_eq_d(&mut self, cc_num: u8, fs: u8, ft: u8)
+    fn c_eq_d(&mut self, cc_num: u8, fs: u8, ft: u8) -> Result<(), FpRegError>
     {
-        let op1 = self.get_double_precision(fs);
-        let op2 = self.get_double_precision(ft);
+        let op1 = self.get_double_precision(fs)?;
+        let op2 = self.get_double_precision(ft)?;
 
         if op1 == op2
         {
             self.cc[cc_num as usize] = true;
         }
+
+        Ok(())
     }
 
     fn c_eq_s(&mut self, cc_num: u8, fs: u8, ft: u8)
@@ -1389,15 +2380,17 @@ impl CPU // FP coprocessor1
         }
     }
 
-    fn c_le_d(&mut self, cc_num: u8, fs: u8, ft: u8)
+    fn c_le_d(&mut self, cc_num: u8, fs: u8, ft: u8) -> Result<(), FpRegError>
     {
-        let op1 = self.get_double_precision(fs);
-        let op2 = self.get_double_precision(ft);
+        let op1 = self.get_double_precision(fs)?;
+        let op2 = self.get_double_precision(ft)?;
 
         if op1 <= op2
         {
             self.cc[cc_num as usize] = true;
         }
+
+        Ok(())
     }
 
     fn c_le_s(&mut self, cc_num: u8, fs: u8, ft: u8)
@@ -1411,15 +2404,17 @@ impl CPU // FP coprocessor1
         }
     }
 
-    fn c_lt_d(&mut self, cc_num: u8, fs: u8, ft: u8)
+    fn c_lt_d(&mut self, cc_num: u8, fs: u8, ft: u8) -> Result<(), FpRegError>
     {
-        let op1 = self.get_double_precision(fs);
-        let op2 = self.get_double_precision(ft);
+        let op1 = self.get_double_precision(fs)?;
+        let op2 = self.get_double_precision(ft)?;
 
         if op1 < op2
         {
             self.cc[cc_num as usize] = true;
         }
+
+        Ok(())
     }
 
     fn c_lt_s(&mut self, cc_num: u8, fs: u8, ft: u8)
@@ -1433,49 +2428,53 @@ impl CPU // FP coprocessor1
         }
     }
 
-    fn cvt_d_s(&mut self, fd: u8, fs: u8)
+    fn cvt_d_s(&mut self, fd: u8, fs: u8) -> Result<(), FpRegError>
     {
         let op1 = self.cp1_reg[fs as usize];
-        let result = op1 as f64;
+        let result = canonical_nan_d(op1 as f64);
 
-        self.write_to_double_register(fd, result);
+        self.write_to_double_register(fd, result)
     }
 
-    fn cvt_d_w(&mut self, fd: u8, fs: u8) // convert int to double
+    fn cvt_d_w(&mut self, fd: u8, fs: u8) -> Result<(), FpRegError> // convert int to double
     {
-        let op1 = self.get_double_precision(fs);
+        let op1 = self.get_double_precision(fs)?;
 
-        let bits: i64 = unsafe {mem::transmute(op1)};
+        let bits = op1.to_bits() as i64;
         let result = bits as f64;
 
-        self.write_to_double_register(fd, result);
+        self.write_to_double_register(fd, result)
     }
 
-    fn cvt_s_d(&mut self, fd: u8, fs: u8) // convert double to single
+    fn cvt_s_d(&mut self, fd: u8, fs: u8) -> Result<(), FpRegError> // convert double to single
     {
-        let op1 = self.get_double_precision(fs);
-        let result = op1 as f32;
+        let op1 = self.get_double_precision(fs)?;
+        let result = canonical_nan_s(op1 as f32);
 
         self.cp1_reg[fd as usize] = result;
+
+        Ok(())
     }
 
     fn cvt_s_w(&mut self, fd: u8, fs: u8) // convert int to single
     {
         let op1 = self.cp1_reg[fs as usize];
-        let bits: i32 = unsafe {mem::transmute(op1)};
+        let bits = op1.to_bits() as i32;
 
         let result = bits as f32;
         self.cp1_reg[fd as usize] = result;
     }
 
-    fn cvt_w_d(&mut self, fd: u8, fs: u8) // convert double to int32
+    fn cvt_w_d(&mut self, fd: u8, fs: u8) -> Result<(), FpRegError> // convert double to int32
     {
-        let op1 = self.get_double_precision(fs);
+        let op1 = self.get_double_precision(fs)?;
         let converted = op1 as i32;
 
-        let converted_bits: f32 = unsafe {mem::transmute(converted)};
+        let converted_bits = f32::from_bits(converted as u32);
 
         self.cp1_reg[fd as usize] = converted_bits;
+
+        Ok(())
     }
 
     fn cvt_w_s(&mut self, fd: u8, fs: u8) // convert single to int32
@@ -1483,19 +2482,19 @@ impl CPU // FP coprocessor1
         let op1 = self.cp1_reg[fs as usize];
         let converted = op1 as i32;
 
-        let converted_bits: f32 = unsafe {mem::transmute(converted)};
+        let converted_bits = f32::from_bits(converted as u32);
 
         self.cp1_reg[fd as usize] = converted_bits;
     }
 
-    fn div_d(&mut self, fd: u8, fs: u8, ft: u8)
+    fn div_d(&mut self, fd: u8, fs: u8, ft: u8) -> Result<(), FpRegError>
     {
-        let op1 = self.get_double_precision(fs);
-        let op2 = self.get_double_precision(ft);
+        let op1 = self.get_double_precision(fs)?;
+        let op2 = self.get_double_precision(ft)?;
 
-        let result = op1 / op2;
+        let result = canonical_nan_d(op1 / op2);
 
-        self.write_to_double_register(fd, result);
+        self.write_to_double_register(fd, result)
     }
 
     fn div_s(&mut self, fd: u8, fs: u8, ft: u8)
@@ -1503,18 +2502,20 @@ impl CPU // FP coprocessor1
         let op1 = self.cp1_reg[fs as usize];
         let op2 = self.cp1_reg[ft as usize];
 
-        let result = op1 / op2;
+        let result = canonical_nan_s(op1 / op2);
 
         self.cp1_reg[fd as usize] = result;
     }
 
-    fn floor_w_d(&mut self, fd: u8, fs: u8) // floor of f64 as i32
+    fn floor_w_d(&mut self, fd: u8, fs: u8) -> Result<(), FpRegError> // floor of f64 as i32
     {
-        let op1 = self.get_double_precision(fs);
+        let op1 = self.get_double_precision(fs)?;
         let result = op1.ceil() as i32;
 
-        let bits: f32 = unsafe {mem::transmute(result)};
+        let bits = f32::from_bits(result as u32);
         self.cp1_reg[fd as usize] = bits;
+
+        Ok(())
     }
 
     fn floor_w_s(&mut self, fd: u8, fs: u8) // floor of f32 as i32
@@ -1522,14 +2523,14 @@ impl CPU // FP coprocessor1
         let op1 = self.cp1_reg[fs as usize];
         let result = op1.ceil() as i32;
 
-        let bits: f32 = unsafe {mem::transmute(result)};
+        let bits = f32::from_bits(result as u32);
         self.cp1_reg[fd as usize] = bits;
     }
 
-    fn mov_d(&mut self, fd: u8, fs: u8)
+    fn mov_d(&mut self, fd: u8, fs: u8) -> Result<(), FpRegError>
     {
-        let op1 = self.get_double_precision(fs);
-        self.write_to_double_register(fd, op1);
+        let op1 = self.get_double_precision(fs)?;
+        self.write_to_double_register(fd, op1)
     }
 
     fn mov_s(&mut self, fd: u8, fs: u8)
@@ -1538,44 +2539,50 @@ impl CPU // FP coprocessor1
         self.cp1_reg[fd as usize] = op1;
     }
 
-    fn movf_d(&mut self, fd: u8, fs: u8, cc_num: u8)
+    fn movf_d(&mut self, fd: u8, fs: u8, cc_num: u8) -> Result<(), FpRegError>
     {
-        if self.cc[cc_num as usize] == false
+        if !self.cc[cc_num as usize]
         {
-            self.mov_d(fd, fs);
+            self.mov_d(fd, fs)?;
         }
+
+        Ok(())
     }
 
     fn movf_s(&mut self, fd: u8, fs: u8, cc_num: u8)
     {
-        if self.cc[cc_num as usize] == false
+        if !self.cc[cc_num as usize]
         {
             self.mov_s(fd, fs);
         }
     }
 
-    fn movt_d(&mut self, fd: u8, fs: u8, cc_num: u8)
+    fn movt_d(&mut self, fd: u8, fs: u8, cc_num: u8) -> Result<(), FpRegError>
     {
-        if self.cc[cc_num as usize] == true
+        if self.cc[cc_num as usize]
         {
-            self.mov_d(fd, fs);
+            self.mov_d(fd, fs)?;
         }
+
+        Ok(())
     }
 
     fn movt_s(&mut self, fd: u8, fs: u8, cc_num: u8)
     {
-        if self.cc[cc_num as usize] == true
+        if self.cc[cc_num as usize]
         {
             self.mov_s(fd, fs);
         }
     }
 
-    fn movn_d(&mut self, fd: u8, fs: u8, rt: u8)
+    fn movn_d(&mut self, fd: u8, fs: u8, rt: u8) -> Result<(), FpRegError>
     {
         if self.cp0_reg[rt as usize] != 0
         {
-            self.mov_d(fd, fs);
+            self.mov_d(fd, fs)?;
         }
+
+        Ok(())
     }
 
     fn movn_s(&mut self, fd: u8, fs: u8, rt: u8)
@@ -1586,12 +2593,14 @@ impl CPU // FP coprocessor1
         }
     }
 
-    fn movz_d(&mut self, fd: u8, fs: u8, rt: u8)
+    fn movz_d(&mut self, fd: u8, fs: u8, rt: u8) -> Result<(), FpRegError>
     {
         if self.cp0_reg[rt as usize] == 0
         {
-            self.mov_d(fd, fs);
+            self.mov_d(fd, fs)?;
         }
+
+        Ok(())
     }
 
     fn movz_s(&mut self, fd: u8, fs: u8, rt: u8)
@@ -1602,14 +2611,14 @@ impl CPU // FP coprocessor1
         }
     }
 
-    fn mul_d(&mut self, fd: u8, fs: u8, ft: u8)
+    fn mul_d(&mut self, fd: u8, fs: u8, ft: u8) -> Result<(), FpRegError>
     {
-        let op1 = self.get_double_precision(fs);
-        let op2 = self.get_double_precision(ft);
+        let op1 = self.get_double_precision(fs)?;
+        let op2 = self.get_double_precision(ft)?;
 
-        let result = op1 * op2;
+        let result = canonical_nan_d(op1 * op2);
 
-        self.write_to_double_register(fd, result);
+        self.write_to_double_register(fd, result)
     }
 
     fn mul_s(&mut self, fd: u8, fs: u8, ft: u8)
@@ -1617,34 +2626,36 @@ impl CPU // FP coprocessor1
         let op1 = self.cp1_reg[fs as usize];
         let op2 = self.cp1_reg[ft as usize];
 
-        let result = op1 * op2;
+        let result = canonical_nan_s(op1 * op2);
 
         self.cp1_reg[fd as usize] = result;
     }
 
-    fn neg_d(&mut self, fd: u8, fs: u8)
+    fn neg_d(&mut self, fd: u8, fs: u8) -> Result<(), FpRegError>
     {
-        let op1 = self.get_double_precision(fs);
-        let result = -op1;
+        let op1 = self.get_double_precision(fs)?;
+        let result = f64::from_bits(op1.to_bits() ^ SIGN_BIT_D);
 
-        self.write_to_double_register(fd, result);
+        self.write_to_double_register(fd, result)
     }
 
     fn neg_s(&mut self, fd: u8, fs: u8)
     {
         let op1 = self.cp1_reg[fs as usize];
-        let result = -op1;
+        let result = f32::from_bits(op1.to_bits() ^ SIGN_BIT_S);
 
         self.cp1_reg[fd as usize] = result;
     }
 
-    fn round_w_d(&mut self, fd: u8, fs: u8)
+    fn round_w_d(&mut self, fd: u8, fs: u8) -> Result<(), FpRegError>
     {
-        let op1 = self.get_double_precision(fs);
+        let op1 = self.get_double_precision(fs)?;
         let result = op1.round() as i32;
 
-        let bits: f32 = unsafe {mem::transmute(result)};
+        let bits = f32::from_bits(result as u32);
         self.cp1_reg[fd as usize] = bits;
+
+        Ok(())
     }
 
     fn round_w_s(&mut self, fd: u8, fs: u8)
@@ -1652,32 +2663,32 @@ impl CPU // FP coprocessor1
         let op1 = self.cp1_reg[fs as usize];
         let result = op1.round() as i32;
 
-        let bits: f32 = unsafe {mem::transmute(result)};
+        let bits = f32::from_bits(result as u32);
         self.cp1_reg[fd as usize] = bits;
     }
 
-    fn sqrt_d(&mut self, fd: u8, fs: u8)
+    fn sqrt_d(&mut self, fd: u8, fs: u8) -> Result<(), FpRegError>
     {
-        let op1 = self.get_double_precision(fs);
-        let result = op1.sqrt();
-        self.write_to_double_register(fd, result);
+        let op1 = self.get_double_precision(fs)?;
+        let result = canonical_nan_d(op1.sqrt());
+        self.write_to_double_register(fd, result)
     }
 
     fn sqrt_s(&mut self, fd: u8, fs: u8)
     {
         let op1 = self.cp1_reg[fs as usize];
-        let result = op1.sqrt();
+        let result = canonical_nan_s(op1.sqrt());
         self.cp1_reg[fd as usize] = result;
     }
 
-    fn sub_d(&mut self, fd: u8, fs: u8, ft: u8)
+    fn sub_d(&mut self, fd: u8, fs: u8, ft: u8) -> Result<(), FpRegError>
     {
-        let op1 = self.get_double_precision(fs);
-        let op2 = self.get_double_precision(ft);
+        let op1 = self.get_double_precision(fs)?;
+        let op2 = self.get_double_precision(ft)?;
 
-        let result = op1 - op2;
+        let result = canonical_nan_d(op1 - op2);
 
-        self.write_to_double_register(fd, result);
+        self.write_to_double_register(fd, result)
     }
 
     fn sub_s(&mut self, fd: u8, fs: u8, ft: u8)
@@ -1685,17 +2696,19 @@ impl CPU // FP coprocessor1
         let op1 = self.cp1_reg[fs as usize];
         let op2 = self.cp1_reg[ft as usize];
 
-        let result = op1 - op2;
+        let result = canonical_nan_s(op1 - op2);
         self.cp1_reg[fd as usize] = result;
     }
 
-    fn trunc_d(&mut self, fd: u8, fs: u8)
+    fn trunc_d(&mut self, fd: u8, fs: u8) -> Result<(), FpRegError>
     {
-        let op1 = self.get_double_precision(fs);
+        let op1 = self.get_double_precision(fs)?;
         let result = op1.trunc() as i32;
 
-        let bits: f32 = unsafe {mem::transmute(result)};
+        let bits = f32::from_bits(result as u32);
         self.cp1_reg[fd as usize] = bits;
+
+        Ok(())
     }
 
     fn trunc_s(&mut self, fd: u8, fs: u8)
@@ -1703,7 +2716,1127 @@ impl CPU // FP coprocessor1
         let op1 = self.cp1_reg[fs as usize];
         let result = op1.trunc() as i32;
 
-        let bits: f32 = unsafe {mem::transmute(result)};
+        let bits = f32::from_bits(result as u32);
         self.cp1_reg[fd as usize] = bits;
     }
 }
+
+#[cfg(test)]
+mod tests
+{
+    use alloc::{format, vec};
+    use alloc::vec::Vec;
+    use crate::computer::{test_computer, ExecutionMode, HaltReason, Phase};
+    use crate::computer::fault::FaultKind;
+    use super::{Cpu, ExceptionCode, CANONICAL_NAN_S, CAUSE_BD, CAUSE_CE, CAUSE_EXC_CODE, CONFIG, CONFIG1_C2, CONFIG1_FP, EXCEPTION_HANDLER_ADDRESS, STATUS_EXL, STATUS_IE};
+
+    #[test]
+    fn blez_and_bgtz_compare_signed()
+    {
+        let mut cpu = Cpu::new();
+        cpu.int_reg[8] = 0x8000_0000; // i32::MIN
+        cpu.int_reg[9] = 1;
+
+        cpu.blez(8, 4);
+        assert_eq!(cpu.pc, 16); // taken for a negative value
+        cpu.bgtz(8, 4);
+        assert_eq!(cpu.pc, 16); // not taken for it
+
+        cpu.blez(9, 4);
+        assert_eq!(cpu.pc, 16); // not taken for a positive value
+        cpu.bgtz(9, 4);
+        assert_eq!(cpu.pc, 32);
+    }
+
+    #[test]
+    fn slt_compares_without_overflowing()
+    {
+        let mut cpu = Cpu::new();
+        cpu.int_reg[8] = 0x8000_0000; // i32::MIN
+        cpu.int_reg[9] = 1;
+
+        cpu.slt(10, 0, 8);
+        cpu.slt(11, 8, 0);
+        cpu.slt(12, 8, 9);
+        cpu.slt(13, 9, 8);
+
+        assert_eq!(cpu.int_reg[10], 0); // 0 < i32::MIN
+        assert_eq!(cpu.int_reg[11], 1); // i32::MIN < 0
+        assert_eq!(cpu.int_reg[12], 1);
+        assert_eq!(cpu.int_reg[13], 0);
+    }
+
+    #[test]
+    fn slti_sign_extends_the_immediate()
+    {
+        let mut cpu = Cpu::new();
+        cpu.int_reg[8] = -5i32 as u32;
+        cpu.int_reg[14] = 0x8000_0000; // i32::MIN
+
+        cpu.slti(9, 0, -1i16 as u16);
+        cpu.slti(10, 8, 0);
+        cpu.slti(11, 8, -5i16 as u16);
+        cpu.slti(12, 8, 0x8000);
+        cpu.slti(13, 0, 0x7FFF);
+        cpu.slti(15, 14, 0x8000);
+
+        assert_eq!(cpu.int_reg[9], 0); // 0 < -1
+        assert_eq!(cpu.int_reg[10], 1); // -5 < 0
+        assert_eq!(cpu.int_reg[11], 0); // -5 < -5
+        assert_eq!(cpu.int_reg[12], 0); // -5 < -32768
+        assert_eq!(cpu.int_reg[13], 1); // 0 < 32767
+        assert_eq!(cpu.int_reg[15], 1); // i32::MIN < -32768
+    }
+
+    #[test]
+    fn odd_double_register_is_a_reserved_instruction()
+    {
+        let mut cpu = Cpu::new();
+        assert!(cpu.get_double_precision(1).is_err());
+
+        cpu.decode_and_execute(0x4622_0800); // add.d $f0, $f1, $f2
+        assert_eq!((cpu.cp0_reg[13] >> 2) & 0b11111, 10); // Reserved Instruction
+        assert_eq!(cpu.pc, EXCEPTION_HANDLER_ADDRESS);
+    }
+
+    #[test]
+    fn nested_exceptions_unwind_to_the_saved_state()
+    {
+        let mut cpu = Cpu::new();
+        let status = cpu.cp0_reg[12];
+
+        cpu.instruction_address = 0x100;
+        cpu.execute_exception(ExceptionCode::Syscall, None);
+        assert_eq!(cpu.cp0_reg[14], 0x100);
+        assert_ne!(cpu.cp0_reg[12] & STATUS_EXL, 0);
+
+        // A fault in the handler with EXL still set keeps the outer EPC.
+        cpu.instruction_address = 0x8000_0190;
+        cpu.execute_exception(ExceptionCode::Syscall, None);
+        assert_eq!(cpu.cp0_reg[14], 0x100);
+
+        // The outer handler saves EPC and Status, clears EXL and raises a second syscall.
+        let (saved_epc, saved_status) = (cpu.cp0_reg[14], cpu.cp0_reg[12]);
+        cpu.cp0_reg[12] &= !STATUS_EXL;
+        cpu.instruction_address = 0x8000_01A0;
+        cpu.execute_exception(ExceptionCode::Syscall, None);
+        assert_eq!(cpu.cp0_reg[14], 0x8000_01A0);
+
+        cpu.cp0_reg[14] += 4; // the inner handler steps past the syscall
+        cpu.eret();
+        assert_eq!(cpu.pc, 0x8000_01A4);
+
+        cpu.cp0_reg[14] = saved_epc + 4;
+        cpu.cp0_reg[12] = saved_status;
+        cpu.eret();
+        assert_eq!(cpu.pc, 0x104); // and the outer one past the first syscall
+        assert_eq!(cpu.cp0_reg[12], status);
+    }
+
+    #[test]
+    fn epc_points_at_the_faulting_instruction()
+    {
+        let mut cpu = Cpu::new();
+        cpu.instruction_address = 8; // as fetched: pc already points past the syscall
+        cpu.pc = 12;
+
+        cpu.decode_and_execute(0x0000_000C); // syscall
+        assert_eq!(cpu.cp0_reg[14], 8); // EPC of the syscall, not of the instruction after it
+        assert_eq!(cpu.pc, EXCEPTION_HANDLER_ADDRESS);
+    }
+
+    #[test]
+    fn trap_immediates_compare_the_sign_extended_value()
+    {
+        // Whether the trap runs into the exception handler with $t0 holding the value.
+        fn traps(value: u32, trap: fn(&mut Cpu, u8, u16), imm: u16) -> bool
+        {
+            let mut cpu = Cpu::new();
+            cpu.int_reg[8] = value;
+            trap(&mut cpu, 8, imm);
+            cpu.pc == EXCEPTION_HANDLER_ADDRESS
+        }
+
+        assert!(traps(5, Cpu::tgei, 5));
+        assert!(traps(0, Cpu::tgei, 0xFFFF)); // 0 >= -1
+        assert!(!traps(-2i32 as u32, Cpu::tgei, 0xFFFF));
+
+        // -1 becomes 0xFFFFFFFF, the largest unsigned value.
+        assert!(!traps(0, Cpu::tgeiu, 0xFFFF));
+        assert!(traps(0, Cpu::tltiu, 0xFFFF));
+        assert!(traps(u32::MAX, Cpu::tgeiu, 0xFFFF));
+        assert!(!traps(u32::MAX, Cpu::tltiu, 0xFFFF));
+
+        // -0x8000 becomes 0xFFFF8000, above every positive signed value.
+        assert!(!traps(0x7FFF, Cpu::tgeiu, 0x8000));
+        assert!(traps(0x7FFF, Cpu::tltiu, 0x8000));
+        assert!(traps(0xFFFF_8000, Cpu::tgeiu, 0x8000));
+
+        // Positive immediates stay small.
+        assert!(traps(0, Cpu::tgeiu, 0));
+        assert!(!traps(0, Cpu::tltiu, 0));
+        assert!(!traps(0x7FFF, Cpu::tltiu, 0x7FFF));
+    }
+
+    #[test]
+    fn integer_movf_and_movt_follow_their_condition_code()
+    {
+        let mut cpu = Cpu::new();
+        cpu.cc[3] = true;
+        cpu.int_reg[9] = 7;
+
+        cpu.decode_and_execute(0x012D_5001); // movt $t2, $t1, 3
+        cpu.decode_and_execute(0x012C_5801); // movf $t3, $t1, 3
+        cpu.decode_and_execute(0x0120_6001); // movf $t4, $t1, 0
+        cpu.decode_and_execute(0x0121_6801); // movt $t5, $t1, 0
+
+        assert_eq!(cpu.int_reg[10], 7); // cc 3 is set
+        assert_eq!(cpu.int_reg[11], 0);
+        assert_eq!(cpu.int_reg[12], 7); // cc 0 is clear
+        assert_eq!(cpu.int_reg[13], 0);
+    }
+
+    #[test]
+    fn exception_between_ll_and_sc_fails_the_sc()
+    {
+        let mut cpu = Cpu::new();
+        cpu.int_reg[16] = 0x2000;
+        cpu.int_reg[9] = 5;
+        cpu.int_reg[10] = 6;
+
+        cpu.execute(0xC208_0000); // ll $t0, 0($s0)
+        cpu.execute(0xE209_0000); // sc $t1, 0($s0)
+        assert_eq!(cpu.int_reg[9], 1); // the undisturbed sc stores
+        assert!(cpu.memory_buffer.store);
+        assert_eq!((cpu.memory_buffer.address, cpu.memory_buffer.data), (0x2000, 5));
+
+        cpu.execute(0xC208_0000); // ll $t0, 0($s0)
+        cpu.execute(0x0000_000C); // syscall
+        cpu.memory_buffer.data_size = 0;
+        cpu.execute(0xE20A_0000); // sc $t2, 0($s0)
+        assert_eq!(cpu.int_reg[10], 0); // the exception cleared the reservation
+        assert_eq!(cpu.memory_buffer.data_size, 0); // and nothing is stored
+    }
+
+    #[test]
+    fn overflow_traps_or_wraps_as_configured()
+    {
+        let cases = [
+            (0x0109_5020, 0x8000_0000), // add $t2, $t0, $t1
+            (0x210A_0001, 0x8000_0000), // addi $t2, $t0, 1
+            (0x0169_5022, 0x7FFF_FFFF), // sub $t2, $t3, $t1
+        ];
+
+        for (instruction, wrapped) in cases
+        {
+            for trap in [true, false]
+            {
+                let mut cpu = Cpu::new();
+                cpu.int_reg[8] = 0x7FFF_FFFF;
+                cpu.int_reg[9] = 1;
+                cpu.int_reg[11] = 0x8000_0000;
+                cpu.instruction_address = 8;
+                cpu.set_trap_on_overflow(trap);
+
+                cpu.decode_and_execute(instruction);
+                assert!(cpu.overflow());
+                match trap
+                {
+                    true =>
+                    {
+                        assert_eq!(cpu.pc, EXCEPTION_HANDLER_ADDRESS);
+                        assert_eq!(cpu.cp0_reg[14], 8); // EPC of the overflowing instruction
+                        assert_eq!(cpu.int_reg[10], 0, "{:08X}", instruction); // not written
+                    },
+                    false => assert_eq!(cpu.int_reg[10], wrapped, "{:08X}", instruction),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reading_hi_right_after_mult_is_a_hazard()
+    {
+        let mut cpu = Cpu::new();
+        cpu.int_reg[8] = 0x10000;
+        cpu.int_reg[9] = 0x30000;
+
+        cpu.execute(0x0109_0018); // mult $t0, $t1
+        cpu.execute(0x0000_5010); // mfhi $t2
+        assert_eq!(cpu.int_reg[10], 3);
+        assert_eq!(cpu.hilo_hazards(), 0); // off by default
+
+        cpu.set_hilo_latency(3);
+        cpu.execute(0x0109_0018); // mult $t0, $t1
+        cpu.execute(0x0000_5010); // mfhi $t2
+        assert_eq!(cpu.hilo_hazards(), 1);
+        assert_eq!(cpu.stall_cycles(), 2);
+
+        // Enough instructions in between hide the latency.
+        cpu.execute(0x0109_0018); // mult $t0, $t1
+        cpu.execute(0); // nop
+        cpu.execute(0); // nop
+        cpu.execute(0x0000_5012); // mflo $t2
+        assert_eq!(cpu.hilo_hazards(), 1);
+    }
+
+    #[test]
+    fn ticks_visit_the_phases_in_order()
+    {
+        let mut cpu = Cpu::new();
+        cpu.set_memory_latency(1);
+        let mut phases = vec![cpu.phase()];
+
+        for data in [0, 0x2408_0001, 0, 0] // fetch, then execute addiu $t0, $zero, 1
+        {
+            cpu.tick(data, 0);
+            phases.push(cpu.phase());
+        }
+        assert_eq!(phases, [Phase::Fetch, Phase::DecodeAndExecute, Phase::WriteBack, Phase::InterruptCheck,
+            Phase::Fetch]);
+        assert_eq!(cpu.int_reg[8], 1);
+
+        // A load waits for the memory between executing and writing back.
+        cpu.tick(0, 0);
+        cpu.tick(0x8D09_1FFF, 0); // lw $t1, 0x1FFF($t0)
+        assert_eq!(cpu.phase(), Phase::MemoryWait);
+        cpu.tick(0, 0);
+        assert_eq!(cpu.phase(), Phase::WriteBack);
+    }
+
+    #[test]
+    fn wait_idles_until_an_unmasked_interrupt_request()
+    {
+        let mut cpu = Cpu::new();
+        cpu.cp0_reg[12] = (cpu.cp0_reg[12] & !0xFF00) | 1 << (8 + 2); // unmask interrupt line 2 only
+        cpu.decode_and_execute(0x4200_0020); // wait
+        assert!(cpu.is_waiting());
+
+        cpu.check_interrupts(0);
+        assert!(cpu.is_waiting());
+
+        cpu.check_interrupts(1 << 3);
+        assert!(cpu.is_waiting()); // a masked line doesn't wake the CPU
+
+        cpu.check_interrupts(1 << 2);
+        assert!(!cpu.is_waiting());
+    }
+
+    #[test]
+    fn interrupt_pending_in_a_handler_is_taken_right_after_eret()
+    {
+        let mut cpu = Cpu::new();
+        cpu.cp0_reg[12] = STATUS_EXL | STATUS_IE | 1 << (8 + 2); // in a handler, line 2 unmasked
+        cpu.cp0_reg[14] = 0x40;
+        cpu.pc = 0x8000_0200;
+
+        cpu.check_interrupts(1 << 2);
+        assert_eq!(cpu.pc, 0x8000_0200); // EXL holds the request back
+
+        cpu.decode_and_execute(0x4200_0018); // eret
+        assert_eq!(cpu.pc, 0x40);
+        cpu.check_interrupts(1 << 2);
+        assert_eq!(cpu.pc, EXCEPTION_HANDLER_ADDRESS); // taken before the next fetch
+        assert_eq!(cpu.cp0_reg[14], 0x40); // which runs once the handler returns
+        assert_eq!((cpu.cp0_reg[13] >> 2) & 0b11111, ExceptionCode::Interrupt as u32);
+    }
+
+    #[test]
+    fn clz_and_clo_boundaries()
+    {
+        let special2 = |funct: u32, rd: u32, rs: u32| 0x1C << 26 | rs << 21 | rd << 16 | rd << 11 | funct;
+        let mut cpu = Cpu::new();
+        cpu.int_reg[9] = 0xFFFF_FFFF;
+        cpu.int_reg[11] = 0x8000_0000;
+
+        cpu.decode_and_execute(special2(0x20, 8, 0)); // clz $t0, $zero
+        cpu.decode_and_execute(special2(0x21, 10, 9)); // clo $t2, $t1
+        cpu.decode_and_execute(special2(0x20, 12, 11)); // clz $t4, $t3
+        cpu.decode_and_execute(special2(0x21, 13, 11)); // clo $t5, $t3
+        cpu.decode_and_execute(special2(0x21, 14, 0)); // clo $t6, $zero
+        cpu.decode_and_execute(special2(0x20, 15, 9)); // clz $t7, $t1
+
+        assert_eq!(cpu.int_reg[8], 32); // clz of 0
+        assert_eq!(cpu.int_reg[10], 32); // clo of 0xFFFFFFFF
+        assert_eq!(cpu.int_reg[12], 0); // clz of 0x80000000
+        assert_eq!(cpu.int_reg[13], 1);
+        assert_eq!(cpu.int_reg[14], 0);
+        assert_eq!(cpu.int_reg[15], 0);
+    }
+
+    #[cfg(all(feature = "logging", feature = "std"))]
+    mod logging
+    {
+        use std::cell::RefCell;
+        use log::{LevelFilter, Log, Metadata, Record};
+        use super::Cpu;
+
+        // Keeps the messages per thread, as the tests run in parallel.
+        struct TestLogger;
+
+        std::thread_local!
+        {
+            static MESSAGES: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+        }
+
+        impl Log for TestLogger
+        {
+            fn enabled(&self, _: &Metadata) -> bool
+            {
+                true
+            }
+
+            fn log(&self, record: &Record)
+            {
+                MESSAGES.with(|messages| messages.borrow_mut().push(record.args().to_string()));
+            }
+
+            fn flush(&self) {}
+        }
+
+        #[test]
+        fn overflow_logs_the_exception()
+        {
+            static LOGGER: TestLogger = TestLogger;
+            let _ = log::set_logger(&LOGGER); // set once per test binary
+            log::set_max_level(LevelFilter::Debug);
+
+            let mut cpu = Cpu::new();
+            cpu.int_reg[8] = 0x7FFF_0000;
+            cpu.instruction_address = 4;
+            cpu.decode_and_execute(0x0108_4820); // add $t1, $t0, $t0
+
+            let messages = MESSAGES.with(|messages| messages.take());
+            assert!(messages.iter().any(|message|
+                message.starts_with("exception Overflow, EPC 0x00000004, Cause 0x00000030")), "{:?}", messages);
+        }
+    }
+
+    #[test]
+    fn syscall_code_is_visible_after_the_exception()
+    {
+        let mut cpu = Cpu::new();
+        cpu.decode_and_execute(0x12345 << 6 | 0x0000_000C); // syscall 0x12345
+
+        assert_eq!((cpu.cp0_reg[13] >> 2) & 0b11111, ExceptionCode::Syscall as u32);
+        assert_eq!(cpu.pc, EXCEPTION_HANDLER_ADDRESS);
+        assert_eq!(cpu.syscall_code(), 0x12345);
+    }
+
+    #[test]
+    fn exception_in_a_delay_slot_points_epc_at_the_branch()
+    {
+        let mut cpu = Cpu::new();
+        cpu.set_delay_slots(true);
+        cpu.fetch();
+        cpu.decode_and_execute(0x1000_0004); // beq $zero, $zero, 4 at 0
+        assert_eq!(cpu.pc, 4); // the delay slot comes first
+
+        cpu.fetch();
+        assert_eq!(cpu.pc, 0x14); // the branch target follows the slot
+        cpu.decode_and_execute(0x0000_000C); // syscall in the delay slot
+
+        assert_eq!(cpu.cp0_reg[13] >> 31, 1); // BD
+        assert_eq!(cpu.cp0_reg[14], 0); // EPC at the branch, which runs again after the handler
+        assert_eq!(cpu.pc, EXCEPTION_HANDLER_ADDRESS);
+    }
+
+    #[test]
+    fn each_exception_rewrites_exc_code_bd_and_ce()
+    {
+        // Fields left over from an earlier exception must not leak into the next one.
+        let mut cpu = Cpu::new();
+        cpu.cp0_reg[13] = CAUSE_BD | CAUSE_CE | CAUSE_EXC_CODE;
+        cpu.fetch();
+        cpu.decode_and_execute(0x0000_000C); // syscall
+        assert_eq!(cpu.cp0_reg[13] & (CAUSE_BD | CAUSE_CE | CAUSE_EXC_CODE), (ExceptionCode::Syscall as u32) << 2);
+
+        // Only an exception in a delay slot sets BD.
+        let mut cpu = Cpu::new();
+        cpu.cp0_reg[13] = CAUSE_CE;
+        cpu.set_delay_slots(true);
+        cpu.fetch();
+        cpu.decode_and_execute(0x1000_0004); // beq $zero, $zero, 4
+        cpu.fetch();
+        cpu.decode_and_execute(0x0000_0034); // teq $zero, $zero in the delay slot
+        assert_eq!(cpu.cp0_reg[13] & (CAUSE_BD | CAUSE_CE | CAUSE_EXC_CODE),
+            CAUSE_BD | (ExceptionCode::CalledTrap as u32) << 2);
+    }
+
+    #[test]
+    fn bad_instruction_fetches_raise_address_and_bus_errors()
+    {
+        // AdEL for a misaligned PC, IBE for a PC outside the memory map. Only AdEL sets BadVAddr.
+        for (target, exception_code, bad_address) in [(0x1001, ExceptionCode::IllegalAddressLoad, 0x1001),
+            (0x0010_0000, ExceptionCode::BusErrorOnInstructionFetch, 0)]
+        {
+            let mut computer = test_computer(&format!("
+                li $t0, {}
+                jr $t0", target));
+
+            // Without a handler in memory the machine stops where the handler would be fetched.
+            match computer.run()
+            {
+                HaltReason::Fault(fault) => assert_eq!(fault.kind, FaultKind::BadAddress(EXCEPTION_HANDLER_ADDRESS)),
+                reason => panic!("Expected a fault, got {:?}", reason),
+            }
+            assert_eq!((computer.cpu.cp0_reg[13] >> 2) & 0b11111, exception_code as u32);
+            assert_eq!(computer.cpu.cp0_reg[14], target); // EPC
+            assert_eq!(computer.cpu.cp0_reg[8], bad_address);
+        }
+    }
+
+    #[test]
+    fn double_round_trips_through_sdc1_and_ldc1()
+    {
+        let value = -1234.5678_f64;
+        let bits = value.to_bits();
+        let mut computer = test_computer(&format!("
+            li $t0, {}
+            li $t1, {}
+            mtc1 $t0, $f1
+            mtc1 $t1, $f0
+            li $t2, 0x2000
+            sdc1 $f0, 8($t2)
+            ldc1 $f2, 8($t2)
+            done:
+            b done", bits >> 32, bits & 0xFFFF_FFFF));
+        computer.add_breakpoint_symbol("done").unwrap();
+        computer.run();
+
+        assert_eq!(computer.cpu.get_double_precision(2).ok(), Some(value));
+        let stored: Vec<u8> = (0x2008..0x2010).map(|address| computer.bus.read_data(address, 1) as u8).collect();
+        assert_eq!(stored, value.to_be_bytes());
+    }
+
+    #[test]
+    fn invalid_fp_operations_give_the_canonical_nan()
+    {
+        let mut computer = test_computer("
+            lui $t0, 0xBF80
+            mtc1 $t0, $f0
+            sqrt.s $f2, $f0
+            mfc1 $t1, $f2
+            mtc1 $zero, $f4
+            div.s $f6, $f4, $f4
+            mfc1 $t2, $f6
+            done:
+            b done");
+        computer.add_breakpoint_symbol("done").unwrap();
+        computer.run();
+
+        assert_eq!(computer.cpu.register(9), CANONICAL_NAN_S); // sqrt(-1.0)
+        assert_eq!(computer.cpu.register(10), CANONICAL_NAN_S); // 0.0 / 0.0
+    }
+
+    #[test]
+    fn abs_and_neg_only_touch_the_sign_bit()
+    {
+        let mut computer = test_computer("
+            lui $t0, 0x8000
+            mtc1 $t0, $f0
+            neg.s $f2, $f0
+            mfc1 $s0, $f2
+            li $t1, 0xFFC01234
+            mtc1 $t1, $f4
+            abs.s $f6, $f4
+            mfc1 $s1, $f6
+            neg.s $f8, $f6
+            mfc1 $s2, $f8
+            mtc1 $zero, $f10
+            mtc1 $t0, $f11
+            neg.d $f12, $f10
+            mfc1 $s3, $f13
+            mfc1 $s4, $f12
+            done:
+            b done");
+        computer.add_breakpoint_symbol("done").unwrap();
+        computer.run();
+
+        let registers = computer.cpu.registers();
+        assert_eq!(registers[16], 0); // -0.0 negated is +0.0
+        assert_eq!(registers[17], 0x7FC0_1234); // the NaN payload is kept
+        assert_eq!(registers[18], 0xFFC0_1234);
+        assert_eq!((registers[19], registers[20]), (0, 0)); // the double -0.0 negated
+    }
+
+    #[test]
+    fn cp1_transfers_keep_the_exact_bits()
+    {
+        let mut computer = test_computer("
+            lui $t0, 0x3F80
+            mtc1 $t0, $f0
+            mfc1 $t1, $f0
+            li $t2, 0x7FC01234
+            mtc1 $t2, $f2
+            li $t3, 0x2000
+            swc1 $f2, 0($t3)
+            lwc1 $f4, 0($t3)
+            mfc1 $t4, $f4
+            done:
+            b done");
+        computer.add_breakpoint_symbol("done").unwrap();
+        computer.run();
+
+        assert_eq!(computer.cpu.register(9), 0x3F80_0000);
+        assert_eq!(computer.cpu.cp1_reg[0], 1.0);
+        assert_eq!(computer.bus.read_data(0x2000, 4), 0x7FC0_1234); // a NaN payload through memory
+        assert_eq!(computer.cpu.register(12), 0x7FC0_1234);
+    }
+
+    #[test]
+    fn misaligned_words_raise_address_errors()
+    {
+        // AdEL for the loads, AdES for the stores, BadVAddr holds the address.
+        for (instruction, exception_code) in [("lw $t1", ExceptionCode::IllegalAddressLoad),
+            ("ll $t1", ExceptionCode::IllegalAddressLoad), ("sw $t1", ExceptionCode::IllegalAddressStore),
+            ("sc $t1", ExceptionCode::IllegalAddressStore), ("lwc1 $f0", ExceptionCode::IllegalAddressLoad)]
+        {
+            let mut computer = test_computer(&format!("
+                li $t0, 0x2000
+                ll $t2, 0($t0)
+                {}, 2($t0)", instruction));
+
+            // Without a handler in memory the machine stops where the handler would be fetched.
+            match computer.run()
+            {
+                HaltReason::Fault(fault) => assert_eq!(fault.kind, FaultKind::BadAddress(EXCEPTION_HANDLER_ADDRESS)),
+                reason => panic!("Expected a fault, got {:?}", reason),
+            }
+            assert_eq!((computer.cpu.cp0_reg[13] >> 2) & 0b11111, exception_code as u32, "{}", instruction);
+            assert_eq!(computer.cpu.cp0_reg[8], 0x2002);
+            assert_eq!(computer.cpu.register(9), 0); // nothing written back, not even the sc flag
+        }
+    }
+
+    #[test]
+    fn simultaneous_interrupts_enter_the_higher_priority_line()
+    {
+        // Both software interrupts at once, IP1 wins by default and IP0 once it is put first.
+        for (priority, line) in [([7, 6, 5, 4, 3, 2, 1, 0], 1), ([0, 1, 2, 3, 4, 5, 6, 7], 0)]
+        {
+            let mut cpu = Cpu::new();
+            cpu.set_interrupt_priority(priority);
+            cpu.pc = 8;
+            cpu.check_interrupts(0b11);
+
+            assert_eq!(cpu.pc, EXCEPTION_HANDLER_ADDRESS);
+            assert_eq!((cpu.cp0_reg[13] >> 8) & 0xFF, 1 << line); // the handler sees only its line
+            assert_eq!(cpu.cp0_reg[14], 8);
+        }
+    }
+
+    #[test]
+    fn register_traps_follow_their_conditions()
+    {
+        // Pairs: equal, greater, less, and -1 against 1 (less signed, greater unsigned).
+        let pairs = [(1, 1), (2, 1), (1, 2), (-1i32 as u32, 1)];
+        let expected = [
+            (0x34, [true, false, false, false]), // teq
+            (0x36, [false, true, true, true]), // tne
+            (0x30, [true, true, false, false]), // tge
+            (0x31, [true, true, false, true]), // tgeu
+            (0x32, [false, false, true, true]), // tlt
+            (0x33, [false, false, true, false]), // tltu
+        ];
+
+        for (funct, trapping) in expected
+        {
+            for ((a, b), trap) in pairs.into_iter().zip(trapping)
+            {
+                let mut cpu = Cpu::new();
+                cpu.int_reg[8] = a;
+                cpu.int_reg[9] = b;
+                cpu.decode_and_execute(8 << 21 | 9 << 16 | 0x2A << 6 | funct); // with a code for the handler
+                assert_eq!(cpu.pc == EXCEPTION_HANDLER_ADDRESS, trap, "funct 0x{:02X}, {}, {}", funct, a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn variable_shifts_use_the_low_five_bits()
+    {
+        let mut computer = test_computer("
+            li $t0, 33
+            lui $t1, 0x8000
+            ori $t1, $t1, 2
+            sllv $s0, $t1, $t0
+            srlv $s1, $t1, $t0
+            srav $s2, $t1, $t0
+            done: b done");
+        computer.add_breakpoint_symbol("done").unwrap();
+        computer.run();
+
+        let registers = computer.cpu.registers();
+        assert_eq!(registers[16], 4); // like a shift by 1
+        assert_eq!(registers[17], 0x40000001);
+        assert_eq!(registers[18], 0xC0000001);
+    }
+
+    #[test]
+    fn lui_and_ori_build_a_full_word()
+    {
+        let mut computer = test_computer("
+            lui $t0, 0x8000
+            ori $t0, $t0, 0x0001
+            lui $t1, 0xFFFF
+            ori $t2, $zero, 0xFFFF
+            done: b done");
+        computer.add_breakpoint_symbol("done").unwrap();
+        computer.run();
+
+        let registers = computer.cpu.registers();
+        assert_eq!(registers[8], 0x80000001); // ori doesn't sign extend into the upper half
+        assert_eq!(registers[9], 0xFFFF0000);
+        assert_eq!(registers[10], 0x0000FFFF);
+    }
+
+    #[test]
+    fn mult_sign_extends_and_multu_does_not()
+    {
+        let mut computer = test_computer("
+            li $t0, -2
+            li $t1, 3
+            mult $t0, $t1
+            mfhi $s0
+            mflo $s1
+            multu $t0, $t1
+            mfhi $s2
+            mflo $s3
+            lui $t2, 0x8000
+            mult $t2, $t2
+            mfhi $s4
+            mflo $s5
+            done: b done");
+        computer.add_breakpoint_symbol("done").unwrap();
+        computer.run();
+
+        let registers = computer.cpu.registers();
+        assert_eq!((registers[16], registers[17]), (0xFFFFFFFF, 0xFFFFFFFA)); // -6
+        assert_eq!((registers[18], registers[19]), (2, 0xFFFFFFFA)); // 0xFFFFFFFE * 3
+        assert_eq!((registers[20], registers[21]), (0x40000000, 0)); // i32::MIN squared
+    }
+
+    #[test]
+    fn unknown_opcodes_raise_reserved_instruction()
+    {
+        // A free primary opcode, a free SPECIAL function and a free SPECIAL2 function.
+        for instruction in [0xFC00_0000, 0x0000_003F, 0x7000_003F]
+        {
+            let mut cpu = Cpu::new();
+            cpu.pc = 8;
+            cpu.fetch();
+            cpu.decode_and_execute(instruction);
+
+            assert_eq!(cpu.pc, EXCEPTION_HANDLER_ADDRESS, "0x{:08X}", instruction);
+            assert_eq!((cpu.cp0_reg[13] >> 2) & 0b11111, ExceptionCode::ReservedInstruction as u32);
+            assert_eq!(cpu.cp0_reg[14], 8);
+            assert_eq!(cpu.fault, None);
+        }
+    }
+
+    #[test]
+    fn random_instruction_words_never_panic()
+    {
+        // xorshift64 from a fixed seed, the same words on every run
+        let mut state = 0x9E3779B97F4A7C15_u64;
+        let mut random = ||
+        {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u32
+        };
+
+        for round in 0..64
+        {
+            let mut computer = test_computer("");
+            for address in (0..0x400).step_by(4)
+            {
+                computer.bus.write_data(address, random(), 4);
+            }
+            let mode = match round % 2
+            {
+                0 => ExecutionMode::Functional,
+                _ => ExecutionMode::Phased,
+            };
+            computer.set_execution_mode(mode);
+
+            // Every word is run, a fault ends nothing but the attempt at it.
+            for address in (0..0x400).step_by(4)
+            {
+                computer.cpu.set_pc(address);
+                computer.run_for(4);
+            }
+        }
+    }
+
+    #[test]
+    fn pref_and_cache_run_as_no_ops()
+    {
+        // The addresses are never accessed, not even one outside of the memory.
+        let mut computer = test_computer("
+            lui $t0, 0x7000
+            pref 0, 4($t0)
+            cache 1, 0($t0)
+            li $t1, 1");
+
+        assert_eq!(computer.run_for(4), HaltReason::StepLimit);
+        assert_eq!(computer.cpu.register(9), 1);
+        assert_eq!(computer.cpu.pc(), 16); // no exception was taken
+    }
+
+    #[test]
+    fn halt_on_exception_stops_at_the_overflowing_add()
+    {
+        let mut computer = test_computer("
+            lui $t0, 0x7FFF
+            ori $t0, $t0, 0xFFFF
+            add $t1, $t0, $t0
+            li $t2, 1");
+        computer.set_halt_on_exception(true);
+
+        let HaltReason::Fault(fault) = computer.run_for(10) else
+        {
+            panic!("The overflow didn't halt the machine.");
+        };
+        assert_eq!(fault.kind, FaultKind::UnhandledException(12)); // Ov
+        assert_eq!(fault.pc, 8);
+        assert_eq!(computer.cpu.epc(), 8);
+        assert_eq!(computer.cpu.cp0_reg[12] & STATUS_EXL, 0); // the vector wasn't entered
+        assert_eq!(computer.cpu.register(10), 0);
+    }
+
+    #[test]
+    fn div_remainder_takes_the_sign_of_the_dividend()
+    {
+        let cases = [
+            (-7, 2, -3, -1),
+            (7, -2, -3, 1),
+            (-7, -2, 3, -1),
+            (7, 2, 3, 1),
+            (i32::MIN, -1, i32::MIN, 0),
+            (i32::MIN, 1, i32::MIN, 0),
+        ];
+
+        for (dividend, divisor, quotient, remainder) in cases
+        {
+            let mut cpu = Cpu::new();
+            cpu.int_reg[8] = dividend as u32;
+            cpu.int_reg[9] = divisor as u32;
+            cpu.execute(0x0109_001A); // div $t0, $t1
+            cpu.execute(0x0000_8012); // mflo $s0
+            cpu.execute(0x0000_8810); // mfhi $s1
+
+            assert_eq!(cpu.int_reg[16] as i32, quotient, "{} / {}", dividend, divisor);
+            assert_eq!(cpu.int_reg[17] as i32, remainder, "{} % {}", dividend, divisor);
+        }
+    }
+
+    #[test]
+    fn division_by_zero_leaves_hi_and_lo()
+    {
+        for instruction in [0x0120_001A, 0x0120_001B] // div and divu $t1, $zero
+        {
+            let mut cpu = Cpu::new();
+            cpu.hi = 5;
+            cpu.lo = 5;
+            cpu.int_reg[9] = 7;
+            cpu.execute(instruction);
+
+            assert_eq!((cpu.hi, cpu.lo), (5, 5), "0x{:08X}", instruction);
+        }
+    }
+
+    #[test]
+    fn halfwords_round_trip_at_even_addresses_and_fault_at_odd_ones()
+    {
+        let mut computer = test_computer("
+            li $s0, 0x2000
+            li $t0, 0x1234FEDC
+            sh $t0, 2($s0)
+            lh $t1, 2($s0)
+            lhu $t2, 2($s0)");
+        computer.run_for(6);
+
+        let registers = computer.cpu.registers();
+        assert_eq!(computer.bus.read_data(0x2000, 4), 0x0000FEDC); // big endian, the word's low half
+        assert_eq!(registers[9], 0xFFFFFEDC);
+        assert_eq!(registers[10], 0x0000FEDC);
+
+        for (instruction, exception_code) in [("sh $t0", 5), ("lh $t1", 4), ("lhu $t2", 4)]
+        {
+            let mut computer = test_computer(&format!("
+                li $s0, 0x2000
+                {}, 3($s0)", instruction));
+            computer.set_halt_on_exception(true);
+
+            let reason = computer.run_for(4);
+            assert!(matches!(reason, HaltReason::Fault(fault) if fault.kind == FaultKind::UnhandledException(exception_code)),
+                "{}", instruction);
+            assert_eq!(computer.cpu.cp0_reg[8], 0x2003);
+        }
+    }
+
+    #[test]
+    fn mul_wraps_into_rd_and_leaves_hi_lo_alone()
+    {
+        let mut computer = test_computer("
+            li $t0, 3
+            li $t1, 5
+            mult $t0, $t1
+            li $t0, 0x12345678
+            li $t1, 0x9ABCDEF0
+            mul $s0, $t0, $t1
+            mfhi $s1
+            mflo $s2
+        done:
+            b done");
+        computer.add_breakpoint_symbol("done").unwrap();
+        computer.run();
+
+        let registers = computer.registers();
+        assert_eq!(registers[16], 0x12345678u32.wrapping_mul(0x9ABCDEF0)); // the low word, no trap
+        assert_eq!(registers[17], 0); // HI and LO still hold 3 * 5
+        assert_eq!(registers[18], 15);
+    }
+
+    #[test]
+    fn instructions_outside_the_whitelist_are_reserved()
+    {
+        use alloc::collections::BTreeSet;
+        use alloc::string::ToString;
+
+        let mut computer = test_computer("
+            addiu $t0, $zero, 5
+            addu $t1, $t0, $t0
+            subu $t2, $t0, $t0
+            addiu $t3, $zero, 1");
+        let allowed = ["addiu", "addu"].iter().map(|mnemonic| mnemonic.to_string()).collect::<BTreeSet<_>>();
+        computer.set_allowed_instructions(Some(allowed));
+        computer.set_halt_on_exception(true);
+
+        match computer.run_for(4)
+        {
+            HaltReason::Fault(fault) => assert_eq!(fault.kind, FaultKind::UnhandledException(10)),
+            reason => panic!("subu didn't raise Reserved Instruction: {:?}", reason),
+        }
+        assert_eq!(computer.registers()[9], 10); // the whitelisted ones executed
+        assert_eq!(computer.registers()[11], 0);
+        assert_eq!(computer.cpu.epc(), 0x8);
+    }
+
+    #[test]
+    fn sub_word_stores_change_only_their_bytes()
+    {
+        for mode in [ExecutionMode::Functional, ExecutionMode::Phased]
+        {
+            let mut computer = test_computer("
+                li $t0, 0x2000
+                li $t1, 0xAABBCCDD
+                sw $t1, 0($t0)
+                sw $t1, 4($t0)
+                sw $t1, 8($t0)
+                li $t2, 0x12345678
+                sb $t2, 1($t0)
+                sh $t2, 6($t0)
+                sb $t2, 11($t0)");
+            computer.set_execution_mode(mode);
+            computer.run_for(64);
+
+            // Only the low byte or halfword of the register is stored, the neighbours are kept.
+            assert_eq!(computer.bus.read_data(0x2000, 4), 0xAA78CCDD, "{:?}", mode);
+            assert_eq!(computer.bus.read_data(0x2004, 4), 0xAABB5678, "{:?}", mode);
+            assert_eq!(computer.bus.read_data(0x2008, 4), 0xAABBCC78, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn values_round_trip_through_cp2()
+    {
+        let source = "
+            li $t0, 0x12345678
+            li $t1, 0x9ABC
+            mtc2 $t0, $5
+            ctc2 $t1, $5
+            mfc2 $s0, $5
+            cfc2 $s1, $5";
+
+        let mut computer = test_computer(source);
+        computer.set_coprocessor2(true);
+        computer.run_for(7);
+        assert_eq!(computer.registers()[16], 0x12345678);
+        assert_eq!(computer.registers()[17], 0x9ABC); // the control registers are separate
+
+        // Without CP2 the first move is Coprocessor Unusable, with CE naming coprocessor 2.
+        let mut computer = test_computer(source);
+        computer.set_halt_on_exception(true);
+        assert!(matches!(computer.run_for(7), HaltReason::Fault(fault) if fault.kind == FaultKind::UnhandledException(11)));
+        assert_eq!((computer.cpu.cp0_reg[13] & CAUSE_CE) >> 28, 2);
+    }
+
+    #[test]
+    fn cp0_moves_and_operations_dispatch_apart()
+    {
+        let mut computer = test_computer("
+            la $t0, resume
+            mtc0 $t0, $14
+            mfc0 $s0, $14
+            mfc0 $s1, $12
+            ori $t1, $s1, 2
+            mtc0 $t1, $12
+            rfe
+            mfc0 $s2, $12
+            mtc0 $t1, $12
+            eret
+            li $s3, 1
+            resume:
+            li $s4, 7");
+        computer.run_for(13);
+        let registers = computer.registers();
+
+        assert_eq!(registers[16], 0x30); // mtc0 and mfc0 move EPC, the address of resume
+        assert_eq!(registers[18], registers[17]); // rfe cleared EXL and went on in sequence
+        assert_eq!(registers[19], 0); // eret jumped to EPC
+        assert_eq!(registers[20], 7);
+
+        // A move with bits set where an operation has its funct, and an unknown operation.
+        let eret_funct_in_mfc0 = (0x10 << 26) | (8 << 16) | (14 << 11) | 0x18;
+        let unknown_operation = (0x10 << 26) | (1 << 25) | 0x3F;
+        for word in [eret_funct_in_mfc0, unknown_operation]
+        {
+            let mut computer = test_computer("nop");
+            computer.bus.write_data(0, word, 4);
+            computer.set_halt_on_exception(true);
+            assert!(matches!(computer.run_for(1), HaltReason::Fault(fault) if fault.kind == FaultKind::UnhandledException(10)),
+                "0x{:08X}", word);
+        }
+    }
+
+    #[test]
+    fn sra_propagates_the_sign_and_srl_fills_zeros()
+    {
+        let mut computer = test_computer("
+            lui $t0, 0x8000
+            li $t1, 36
+            lui $t2, 0x4000
+            sra $s0, $t0, 4
+            srl $s1, $t0, 4
+            srav $s2, $t0, $t1
+            srlv $s3, $t0, $t1
+            sra $s4, $t2, 4
+            srav $s5, $t0, $zero
+        done:
+            b done");
+        computer.add_breakpoint_symbol("done").unwrap();
+        computer.run();
+
+        let registers = computer.registers();
+        assert_eq!(registers[16], 0xF8000000);
+        assert_eq!(registers[17], 0x08000000);
+        assert_eq!(registers[18], 0xF8000000); // the amount is masked to its low 5 bits
+        assert_eq!(registers[19], 0x08000000);
+        assert_eq!(registers[20], 0x04000000); // a clear sign bit shifts in zeros
+        assert_eq!(registers[21], 0x80000000); // a shift by 0 keeps the word
+    }
+
+    #[test]
+    fn config_registers_report_the_configuration()
+    {
+        let mut computer = test_computer("
+            mfc0 $s0, $16
+            mfc0 $s1, $16, 1
+            mtc0 $zero, $16
+            mfc0 $s2, $16
+            mfc0 $t0, $12
+            lui $t1, 0x2000
+            or $t0, $t0, $t1
+            mtc0 $t0, $12
+            mfc0 $s3, $16, 1");
+        computer.set_coprocessor2(true);
+        computer.run_for(9);
+        let registers = computer.registers();
+
+        assert_eq!(registers[16], CONFIG);
+        assert_eq!(registers[16] & 0x8000, 0x8000); // big endian
+        assert_eq!(registers[17], CONFIG1_C2); // no FPU until Status sets CU1
+        assert_eq!(registers[18], CONFIG); // read only
+        assert_eq!(registers[19], CONFIG1_C2 | CONFIG1_FP);
+    }
+
+    #[test]
+    fn handler_clearing_the_software_interrupt_is_not_reentered()
+    {
+        let mut cpu = Cpu::new();
+        cpu.cp0_reg[12] = STATUS_IE | 1 << 8; // line 0 unmasked
+        cpu.int_reg[8] = 0x100;
+        cpu.decode_and_execute(0x4088_6800); // mtc0 $t0, $13 requests IP0
+        let resume = cpu.pc;
+        cpu.check_interrupts(0);
+        assert_eq!(cpu.pc, EXCEPTION_HANDLER_ADDRESS);
+
+        // Without an acknowledgement the request is still pending at the eret.
+        cpu.decode_and_execute(0x4200_0018); // eret
+        assert_eq!(cpu.pc, resume);
+        cpu.check_interrupts(0);
+        assert_eq!(cpu.pc, EXCEPTION_HANDLER_ADDRESS);
+
+        // Clearing IP0 in Cause acknowledges it, the program goes on.
+        cpu.decode_and_execute(0x4080_6800); // mtc0 $zero, $13
+        assert_eq!(cpu.cp0_reg[13] & 0x300, 0);
+        cpu.decode_and_execute(0x4200_0018); // eret
+        cpu.check_interrupts(0);
+        assert_eq!(cpu.pc, resume);
+    }
+
+    #[test]
+    fn jumps_take_the_upper_bits_from_the_delay_slot_address()
+    {
+        // The RAM doesn't reach these addresses, so the jump is executed as if just fetched there.
+        let jump = |word: u32, address: u32, delay_slots: bool|
+        {
+            let mut computer = test_computer("nop");
+            computer.cpu.set_delay_slots(delay_slots);
+            computer.cpu.set_pc(address.wrapping_add(4));
+            computer.cpu.decode_and_execute(word);
+            (computer.cpu.delayed_jump.unwrap_or(computer.cpu.pc()), computer.registers()[31])
+        };
+        let j = (2 << 26) | 0x40;
+        let jal = (3 << 26) | 0x40;
+
+        for delay_slots in [false, true]
+        {
+            assert_eq!(jump(j, 0x0FFFFFFC, delay_slots).0, 0x10000100); // the delay slot is in the next region
+            assert_eq!(jump(j, 0x0FFFFFF8, delay_slots).0, 0x00000100);
+            assert_eq!(jump(j, 0xBFC00000, delay_slots).0, 0xB0000100);
+        }
+        assert_eq!(jump(jal, 0x0FFFFFFC, false), (0x10000100, 0x10000000));
+        assert_eq!(jump(jal, 0x0FFFFFFC, true), (0x10000100, 0x10000004)); // past the delay slot
+    }
+
+    #[test]
+    fn unknown_cp1_encodings_are_reserved()
+    {
+        let execute = |word: u32|
+        {
+            let mut computer = test_computer("nop");
+            computer.bus.write_data(0, word, 4);
+            computer.set_halt_on_exception(true);
+            computer.run_for(1)
+        };
+        let cp1 = |format: u32, ft: u32, fs: u32, fd: u32, funct: u32|
+            (0x11 << 26) | (format << 21) | (ft << 16) | (fs << 11) | (fd << 6) | funct;
+
+        let bogus_funct = cp1(0x10, 2, 1, 0, 0x3F);
+        let bogus_format = cp1(0x15, 2, 1, 0, 0); // add in the unimplemented L format
+        let odd_double = cp1(0x11, 2, 1, 0, 0); // add.d $f0, $f1, $f2
+        for word in [bogus_funct, bogus_format, odd_double]
+        {
+            assert!(matches!(execute(word), HaltReason::Fault(fault) if fault.kind == FaultKind::UnhandledException(10)),
+                "0x{:08X}", word);
+        }
+
+        let add_s = cp1(0x10, 2, 1, 0, 0);
+        assert_eq!(execute(add_s), HaltReason::StepLimit);
+    }
+}
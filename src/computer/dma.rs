@@ -0,0 +1,170 @@
+/*
+    DMA controller, mapped at its base address:
+        word 0: control, bit 0 start, bit 1 interrupt enable, bit 2 done (write 0 to acknowledge),
+                bit 3 error (the block isn't wholly in the RAM or VRAM, nothing was copied)
+        word 1: source address
+        word 2: destination address
+        word 3: length in bytes
+    Writing the control word with the start bit set copies the block on the next step, all at
+    once. Then the start bit clears, done is set and, with interrupts enabled, the controller
+    requests its interrupt line until acknowledged. The blocks may overlap.
+ */
+const CONTROL_START: u32 = 1 << 0;
+const CONTROL_INTERRUPT_ENABLE: u32 = 1 << 1;
+const CONTROL_DONE: u32 = 1 << 2;
+const CONTROL_ERROR: u32 = 1 << 3;
+
+pub(super) const DMA_SIZE: u32 = 16;
+
+// A block copy requested by the guest.
+pub(super) struct Transfer
+{
+    pub(super) source: u32,
+    pub(super) destination: u32,
+    pub(super) length: u32,
+}
+
+pub(super) struct DmaController
+{
+    base: u32,
+    interrupt_line: u8,
+    control: u32,
+    source: u32,
+    destination: u32,
+    length: u32,
+}
+
+impl DmaController
+{
+    pub(super) fn new(base: u32, interrupt_line: u8) -> DmaController
+    {
+        if interrupt_line > 7
+        {
+            panic!("Bad interrupt line: {}", interrupt_line);
+        }
+
+        DmaController
+        {
+            base,
+            interrupt_line,
+            control: 0,
+            source: 0,
+            destination: 0,
+            length: 0,
+        }
+    }
+
+    pub(super) fn contains(&self, address: u32) -> bool
+    {
+        address.wrapping_sub(self.base) < DMA_SIZE
+    }
+
+    pub(super) fn read(&self, address: u32) -> u32
+    {
+        match (address - self.base) / 4
+        {
+            0 => self.control,
+            1 => self.source,
+            2 => self.destination,
+            _ => self.length,
+        }
+    }
+
+    pub(super) fn write(&mut self, address: u32, data: u32)
+    {
+        let mask = CONTROL_START | CONTROL_INTERRUPT_ENABLE | CONTROL_DONE | CONTROL_ERROR;
+        match (address - self.base) / 4
+        {
+            0 => self.control = data & mask,
+            1 => self.source = data,
+            2 => self.destination = data,
+            _ => self.length = data,
+        }
+    }
+
+    // The transfer to run this step, if the guest started one.
+    pub(super) fn take_transfer(&mut self) -> Option<Transfer>
+    {
+        if self.control & CONTROL_START == 0
+        {
+            return None;
+        }
+
+        self.control &= !(CONTROL_START | CONTROL_ERROR);
+        Some(Transfer
+        {
+            source: self.source,
+            destination: self.destination,
+            length: self.length,
+        })
+    }
+
+    pub(super) fn finish(&mut self, copied: bool)
+    {
+        self.control |= CONTROL_DONE;
+        if !copied
+        {
+            self.control |= CONTROL_ERROR;
+        }
+    }
+
+    pub(super) fn interrupt_requests(&self) -> u8
+    {
+        let requesting = self.control & CONTROL_INTERRUPT_ENABLE != 0 &&
+            self.control & CONTROL_DONE != 0;
+
+        (requesting as u8) << self.interrupt_line
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use alloc::format;
+    use crate::computer::test_computer;
+    use super::{CONTROL_DONE, CONTROL_ERROR};
+
+    // Copies length bytes from 0x2000 to 0x3000 with the controller at 0x3F00.
+    fn dma_copy(length: u32) -> crate::computer::Computer
+    {
+        let mut computer = test_computer(&format!("
+            li $s0, 0x2000
+            li $t0, 0x11223344
+            sw $t0, 0($s0)
+            li $t0, 0x55667788
+            sw $t0, 4($s0)
+            li $s1, 0x3F00
+            sw $s0, 4($s1)
+            li $t1, 0x3000
+            sw $t1, 8($s1)
+            li $t2, {}
+            sw $t2, 12($s1)
+            li $t3, 1
+            sw $t3, 0($s1)
+            nop
+            lw $t4, 0($s1)", length));
+        computer.add_dma_controller(0x3F00, 3);
+        computer.run_for(20);
+        computer
+    }
+
+    #[test]
+    fn started_transfer_copies_the_block()
+    {
+        let computer = dma_copy(8);
+
+        assert_eq!(computer.bus.read_data(0x3000, 4), 0x11223344);
+        assert_eq!(computer.bus.read_data(0x3004, 4), 0x55667788);
+        assert_eq!(computer.bus.read_data(0x3008, 4), 0); // nothing past the length
+        assert_eq!(computer.registers()[12], CONTROL_DONE); // start cleared, done set
+    }
+
+    #[test]
+    fn transfer_out_of_the_memory_sets_the_error_bit()
+    {
+        let computer = dma_copy(0x2000); // the destination block runs past the 16 KiB
+
+        assert_eq!(computer.bus.read_data(0x3000, 4), 0); // nothing was copied
+        assert_eq!(computer.registers()[12], CONTROL_DONE | CONTROL_ERROR);
+    }
+}
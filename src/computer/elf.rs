@@ -0,0 +1,176 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+const PT_LOAD: u32 = 1;
+const ELFDATA2MSB: u8 = 2;
+const EM_MIPS: u16 = 8;
+
+// A PT_LOAD segment: the file bytes go to the address, the rest up to memory_size is zeroed.
+pub(super) struct Segment<'a>
+{
+    pub(super) address: u32,
+    pub(super) bytes: &'a [u8],
+    pub(super) memory_size: u32,
+}
+
+// Reads big-endian MIPS executables, the byte order of this machine.
+pub(super) struct ElfReader<'a>
+{
+    bytes: &'a [u8],
+}
+
+impl<'a> ElfReader<'a>
+{
+    pub(super) fn new(bytes: &'a [u8]) -> Result<ElfReader<'a>, String>
+    {
+        if bytes.len() < 0x34 || bytes[0..4] != [0x7F, b'E', b'L', b'F']
+        {
+            return Err("Not an ELF file".to_string());
+        }
+
+        // EI_CLASS == ELFCLASS32
+        if bytes[4] != 1
+        {
+            return Err("Only 32-bit ELF files are supported".to_string());
+        }
+
+        // EI_DATA, a little-endian file would load byte swapped
+        if bytes[5] != ELFDATA2MSB
+        {
+            return Err("Only big-endian ELF files are supported".to_string());
+        }
+
+        let reader = ElfReader { bytes };
+        let machine = reader.halfword(0x12)?;
+        if machine != EM_MIPS
+        {
+            return Err(format!("Not a MIPS executable, e_machine is {}", machine));
+        }
+
+        Ok(reader)
+    }
+
+    pub(super) fn slice(&self, offset: usize, size: usize) -> Result<&'a [u8], String>
+    {
+        offset.checked_add(size).and_then(|end| self.bytes.get(offset..end))
+            .ok_or_else(|| format!("ELF offset 0x{:X} out of range", offset))
+    }
+
+    pub(super) fn byte(&self, offset: usize) -> Result<u8, String>
+    {
+        Ok(self.slice(offset, 1)?[0])
+    }
+
+    pub(super) fn halfword(&self, offset: usize) -> Result<u16, String>
+    {
+        let bytes: [u8; 2] = self.slice(offset, 2)?.try_into().unwrap();
+        Ok(u16::from_be_bytes(bytes))
+    }
+
+    pub(super) fn word(&self, offset: usize) -> Result<u32, String>
+    {
+        let bytes: [u8; 4] = self.slice(offset, 4)?.try_into().unwrap();
+        Ok(u32::from_be_bytes(bytes))
+    }
+
+    pub(super) fn string(&self, offset: usize) -> Result<String, String>
+    {
+        let tail = self.bytes.get(offset..)
+            .ok_or_else(|| format!("ELF offset 0x{:X} out of range", offset))?;
+        let end = tail.iter().position(|&byte| byte == 0).unwrap_or(tail.len());
+
+        Ok(String::from_utf8_lossy(&tail[..end]).into_owned())
+    }
+
+    pub(super) fn entry(&self) -> Result<u32, String>
+    {
+        self.word(0x18)
+    }
+
+    pub(super) fn loadable_segments(&self) -> Result<Vec<Segment<'a>>, String>
+    {
+        let program_headers_offset = self.word(0x1C)? as usize;
+        let program_header_size = self.halfword(0x2A)? as usize;
+        let program_headers_count = self.halfword(0x2C)? as usize;
+
+        let mut segments = Vec::new();
+        for index in 0..program_headers_count
+        {
+            let header = program_headers_offset + index * program_header_size;
+            if self.word(header)? != PT_LOAD
+            {
+                continue;
+            }
+
+            let file_offset = self.word(header + 0x04)? as usize;
+            let address = self.word(header + 0x08)?;
+            let file_size = self.word(header + 0x10)?;
+            let memory_size = self.word(header + 0x14)?;
+            if file_size > memory_size
+            {
+                return Err(format!("Segment at 0x{:08X} has more file bytes than memory", address));
+            }
+
+            segments.push(Segment
+            {
+                address,
+                bytes: self.slice(file_offset, file_size as usize)?,
+                memory_size,
+            });
+        }
+
+        Ok(segments)
+    }
+}
+
+// A minimal executable with the code as its only PT_LOAD segment, placed at the address.
+#[cfg(test)]
+pub(super) fn test_elf(code: &[u8], address: u32, entry: u32) -> Vec<u8>
+{
+    const HEADERS_SIZE: u32 = 0x34 + 0x20;
+
+    let mut elf = Vec::new();
+    elf.extend([0x7F, b'E', b'L', b'F', 1, ELFDATA2MSB, 1]);
+    elf.resize(0x10, 0);
+    elf.extend(2u16.to_be_bytes()); // ET_EXEC
+    elf.extend(EM_MIPS.to_be_bytes());
+    elf.extend(1u32.to_be_bytes()); // version
+    elf.extend(entry.to_be_bytes());
+    elf.extend(0x34u32.to_be_bytes()); // program headers right after this header
+    elf.extend(0u32.to_be_bytes()); // no section headers
+    elf.extend(0u32.to_be_bytes()); // flags
+    elf.extend(0x34u16.to_be_bytes());
+    elf.extend(0x20u16.to_be_bytes());
+    elf.extend(1u16.to_be_bytes());
+    elf.extend([0; 6]); // section header size, count and string table index
+
+    let size = code.len() as u32;
+    for field in [PT_LOAD, HEADERS_SIZE, address, address, size, size, 5, 4] // R+X, word aligned
+    {
+        elf.extend(field.to_be_bytes());
+    }
+    elf.extend(code);
+    elf
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn little_endian_and_foreign_files_are_rejected()
+    {
+        let elf = test_elf(&[0; 4], 0x100, 0x100);
+        assert!(ElfReader::new(&elf).is_ok());
+
+        let mut little_endian = elf.clone();
+        little_endian[5] = 1; // ELFDATA2LSB
+        assert_eq!(ElfReader::new(&little_endian).err().unwrap(), "Only big-endian ELF files are supported");
+
+        let mut x86 = elf.clone();
+        x86[0x12..0x14].copy_from_slice(&3u16.to_be_bytes()); // EM_386
+        assert_eq!(ElfReader::new(&x86).err().unwrap(), "Not a MIPS executable, e_machine is 3");
+    }
+}
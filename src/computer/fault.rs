@@ -0,0 +1,110 @@
+use core::fmt;
+use alloc::string::String;
+use crate::disassembler::REGISTER_NAMES;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FaultKind
+{
+    BadAddress(u32), // access outside of the RAM
+    UnhandledException(u8), // Cause ExcCode with no guest handler, see Computer::set_monitor and set_halt_on_exception
+    JumpToZero, // jr or jalr to address 0, see Computer::set_jump_to_zero_guard
+}
+
+// Mnemonic of a Cause ExcCode.
+pub fn exception_name(code: u8) -> &'static str
+{
+    match code
+    {
+        0 => "Int",
+        4 => "AdEL",
+        5 => "AdES",
+        6 => "IBE",
+        7 => "DBE",
+        8 => "Sys",
+        9 => "Bp",
+        10 => "RI",
+        11 => "CpU",
+        12 => "Ov",
+        13 => "Tr",
+        _ => "?",
+    }
+}
+
+// State of the machine at an unrecoverable condition.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fault
+{
+    pub kind: FaultKind,
+    pub pc: u32, // address of the faulting instruction
+    pub symbol: Option<String>, // the pc as symbol+0xoffset, if a symbol covers it
+    pub instruction: u32,
+    pub text: String, // its disassembly
+    pub registers: [u32; 32],
+}
+
+impl fmt::Display for Fault
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self.kind
+        {
+            FaultKind::BadAddress(address) => writeln!(f, "Bad address: 0x{:08X}", address)?,
+            FaultKind::UnhandledException(code) =>
+                writeln!(f, "Unhandled exception: {} ({})", exception_name(code), code)?,
+            FaultKind::JumpToZero =>
+                writeln!(f, "Jump to address 0, is the return address missing a jal or a restore from the stack?")?,
+        }
+
+        match &self.symbol
+        {
+            Some(symbol) => writeln!(f, "PC: 0x{:08X} ({})", self.pc, symbol)?,
+            None => writeln!(f, "PC: 0x{:08X}", self.pc)?,
+        }
+        writeln!(f, "Instruction: 0x{:08X}    {}", self.instruction, self.text)?;
+
+        for (row, registers) in self.registers.chunks(4).enumerate()
+        {
+            for (column, value) in registers.iter().enumerate()
+            {
+                let name = REGISTER_NAMES[row * 4 + column];
+                write!(f, "{:>5}: 0x{:08X}  ", name, value)?;
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use alloc::string::ToString;
+    use alloc::vec::Vec;
+    use crate::computer::{computer_with, HaltReason};
+    use super::FaultKind;
+
+    #[test]
+    fn report_shows_the_bad_instruction_and_the_registers()
+    {
+        let mut computer = computer_with(&[
+            0x2408_002A, // addiu $t0, $zero, 42
+            0xFC00_0000, // not an instruction
+        ]);
+        computer.add_symbol(0, "main");
+        computer.set_monitor(true);
+
+        let HaltReason::Fault(fault) = computer.run() else
+        {
+            panic!("Expected a fault");
+        };
+        assert_eq!(fault.kind, FaultKind::UnhandledException(10));
+
+        let report = fault.to_string();
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines[0], "Unhandled exception: RI (10)");
+        assert_eq!(lines[1], "PC: 0x00000004 (main+0x4)");
+        assert_eq!(lines[2], "Instruction: 0xFC000000    .word 0xFC000000");
+        assert!(lines[5].contains("$t0: 0x0000002A"));
+    }
+}
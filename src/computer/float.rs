@@ -0,0 +1,54 @@
+// Rounding and square root of floats live in std, so without it they come from libm.
+pub(super) trait FloatMath
+{
+    fn ceil(self) -> Self;
+    fn round(self) -> Self;
+    fn sqrt(self) -> Self;
+    fn trunc(self) -> Self;
+}
+
+impl FloatMath for f32
+{
+    fn ceil(self) -> f32
+    {
+        libm::ceilf(self)
+    }
+
+    fn round(self) -> f32
+    {
+        libm::roundf(self)
+    }
+
+    fn sqrt(self) -> f32
+    {
+        libm::sqrtf(self)
+    }
+
+    fn trunc(self) -> f32
+    {
+        libm::truncf(self)
+    }
+}
+
+impl FloatMath for f64
+{
+    fn ceil(self) -> f64
+    {
+        libm::ceil(self)
+    }
+
+    fn round(self) -> f64
+    {
+        libm::round(self)
+    }
+
+    fn sqrt(self) -> f64
+    {
+        libm::sqrt(self)
+    }
+
+    fn trunc(self) -> f64
+    {
+        libm::trunc(self)
+    }
+}
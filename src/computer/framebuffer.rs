@@ -0,0 +1,331 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+use crate::computer::bus::Bus;
+
+/*
+    The VRAM region starts with a control header the guest can rewrite at any time:
+        word 0: display mode
+        word 1: width in pixels (0 = window width)
+        word 2: height in pixels (0 = window height)
+        word 3: palette address (indexed mode, 256 words of 0x00RRGGBB)
+        word 4: displayed page (0 = pixels after the header, 1 = back buffer region)
+        words 5-7: reserved
+    Pixel data follows the header.
+ */
+const HEADER_SIZE: u32 = 32;
+const PALETTE_SIZE: usize = 256;
+
+const MODE_DIRECT: u32 = 0; // one 0x00RRGGBB word per pixel
+const MODE_INDEXED: u32 = 1; // one palette index byte per pixel
+
+struct DisplayHeader
+{
+    mode: u32,
+    width: usize,
+    height: usize,
+    palette: u32,
+    pixels: Range<u32>,
+}
+
+// A frame rendered from VRAM, one 0x00RRGGBB word per pixel.
+pub(super) struct Frame
+{
+    pub(super) pixels: Vec<u32>,
+    pub(super) width: usize,
+    pub(super) height: usize,
+}
+
+// Turns the VRAM into the pixels of a frame, apart from the window so it works without one.
+pub(super) struct Framebuffer
+{
+    vram: Range<u32>,
+    back_buffer: Option<Range<u32>>,
+    width: usize,
+    height: usize,
+    fast_path: bool, // copy direct color pixels in bulk instead of word by word
+    max_size: Option<(usize, usize)>, // the largest frame the guest may request
+}
+
+impl Framebuffer
+{
+    pub(super) fn new(width: usize, height: usize, vram: Range<u32>, back_buffer: Option<Range<u32>>)
+        -> Result<Framebuffer, String>
+    {
+        let pixels = vram.len().saturating_sub(HEADER_SIZE as usize) / 4;
+        if pixels < width * height
+        {
+            return Err(format!("VRAM holds {} pixels, the display needs {}", pixels, width * height));
+        }
+
+        if let Some(back_buffer) = &back_buffer
+        {
+            let pixels = back_buffer.len() / 4;
+            if pixels < width * height
+            {
+                return Err(format!("Back buffer holds {} pixels, the display needs {}", pixels, width * height));
+            }
+        }
+
+        Ok(Framebuffer
+        {
+            vram,
+            back_buffer,
+            width,
+            height,
+            fast_path: true,
+            max_size: None,
+        })
+    }
+
+    pub(super) fn set_fast_path(&mut self, enabled: bool)
+    {
+        self.fast_path = enabled;
+    }
+
+    pub(super) fn set_max_size(&mut self, max_size: Option<(usize, usize)>)
+    {
+        self.max_size = max_size;
+    }
+
+    pub(super) fn render(&self, bus: &Bus) -> Frame
+    {
+        let header = self.read_header(bus);
+        let pixels_start = header.pixels.start;
+        let pixels_count = header.width * header.height;
+
+        let pixels: Vec<u32> = match header.mode
+        {
+            MODE_INDEXED =>
+            {
+                let palette = Self::read_palette(bus, header.palette);
+                (pixels_start..).take(pixels_count)
+                    .map(|addr| palette[bus.read_data(addr, 1) as usize])
+                    .collect()
+            },
+            _ => match bus.slice(pixels_start, pixels_count * 4).filter(|_| self.fast_path)
+            {
+                Some(bytes) => bytes.chunks_exact(4)
+                    .map(|pixel| u32::from_be_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]))
+                    .collect(),
+                // A page the bus can't hand out in one piece is read word by word.
+                None => (pixels_start..).step_by(4).take(pixels_count)
+                    .map(|addr| bus.read_data(addr, 4))
+                    .collect(),
+            },
+        };
+
+        Frame
+        {
+            pixels,
+            width: header.width,
+            height: header.height,
+        }
+    }
+
+    fn read_header(&self, bus: &Bus) -> DisplayHeader
+    {
+        let start = self.vram.start;
+        let mode = bus.read_data(start, 4);
+        let width = bus.read_data(start + 4, 4) as usize;
+        let height = bus.read_data(start + 8, 4) as usize;
+        let palette = bus.read_data(start + 12, 4);
+        let page = bus.read_data(start + 16, 4);
+
+        let pixels = match (page, &self.back_buffer)
+        {
+            (1, Some(back_buffer)) => back_buffer.clone(),
+            _ => start + HEADER_SIZE..self.vram.end,
+        };
+
+        let (mode, bytes_per_pixel) = match mode
+        {
+            MODE_INDEXED => (MODE_INDEXED, 1),
+            _ => (MODE_DIRECT, 4), // Unknown modes show direct color.
+        };
+        let capacity = pixels.len() / bytes_per_pixel;
+
+        /*
+            Fall back to the window size if the requested frame is empty, doesn't fit in VRAM
+            or is over the maximum, so a bad header never makes a frame bigger than the VRAM.
+         */
+        let within_max = self.max_size
+            .is_none_or(|(max_width, max_height)| width <= max_width && height <= max_height);
+        let fits = width > 0 && height > 0 && within_max &&
+            width.checked_mul(height).is_some_and(|pixels| pixels <= capacity);
+        let (width, height) = match fits
+        {
+            true => (width, height),
+            false => (self.width, self.height),
+        };
+
+        DisplayHeader
+        {
+            mode,
+            width,
+            height,
+            palette,
+            pixels,
+        }
+    }
+
+    fn read_palette(bus: &Bus, address: u32) -> Vec<u32>
+    {
+        match bus.slice(address, PALETTE_SIZE * 4)
+        {
+            Some(palette) => palette.chunks_exact(4)
+                .map(|color| u32::from_be_bytes([color[0], color[1], color[2], color[3]]))
+                .collect(),
+            None => vec![0; PALETTE_SIZE], // Out of memory, show black.
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use crate::computer::bus::Bus;
+    use crate::computer::memory::Memory;
+    use super::{Framebuffer, HEADER_SIZE, MODE_INDEXED};
+
+    #[test]
+    fn display_reads_the_layout_vram()
+    {
+        let mut bus = Bus::new(Memory::new(0x2000), 0x1000..0x1400 + HEADER_SIZE);
+        let framebuffer = Framebuffer::new(16, 16, 0x1000..0x1400 + HEADER_SIZE, None).unwrap();
+
+        // The last pixel word ends the VRAM, the word past it is data and not shown.
+        bus.write_data(0x13FC + HEADER_SIZE, 0x00AB_CDEF, 4);
+        bus.write_data(0x1400 + HEADER_SIZE, 0x0012_3456, 4);
+
+        let pixels = framebuffer.render(&bus).pixels;
+        assert_eq!(pixels.len(), 16 * 16);
+        assert_eq!(pixels.last(), Some(&0x00AB_CDEF));
+        assert!(!pixels.contains(&0x0012_3456));
+    }
+
+    #[test]
+    fn vram_too_small_for_the_display_is_an_error()
+    {
+        assert!(Framebuffer::new(16, 16, 0x1000..0x1400, None).is_err()); // no room for the header
+    }
+
+    #[test]
+    fn mode_word_switches_the_pixel_format()
+    {
+        const PALETTE: u32 = 0x1800;
+
+        let mut bus = Bus::new(Memory::new(0x2000), 0x1000..0x1400 + HEADER_SIZE);
+        let framebuffer = Framebuffer::new(16, 16, 0x1000..0x1400 + HEADER_SIZE, None).unwrap();
+        bus.write_data(PALETTE + 0x12 * 4, 0x00FF_0000, 4);
+        bus.write_data(PALETTE + 0x34 * 4, 0x0000_FF00, 4);
+        bus.write_data(0x1000 + 12, PALETTE, 4);
+        bus.write_data(0x1000 + HEADER_SIZE, 0x1234_0000, 4);
+
+        // The same bytes are one direct color pixel, then two palette indices.
+        assert_eq!(framebuffer.render(&bus).pixels[0], 0x1234_0000);
+
+        bus.write_data(0x1000, MODE_INDEXED, 4);
+        let pixels = framebuffer.render(&bus).pixels;
+        assert_eq!(pixels[0], 0x00FF_0000);
+        assert_eq!(pixels[1], 0x0000_FF00);
+    }
+
+    #[test]
+    fn back_buffer_too_small_for_the_display_is_an_error()
+    {
+        let back_buffer = Some(0x1800..0x1BFC); // a word short
+        assert!(Framebuffer::new(16, 16, 0x1000..0x1400 + HEADER_SIZE, back_buffer).is_err());
+    }
+
+    #[test]
+    fn flipping_shows_the_back_buffer()
+    {
+        const BACK_BUFFER: u32 = 0x1800;
+
+        let mut bus = Bus::new(Memory::new(0x2000), 0x1000..0x1400 + HEADER_SIZE);
+        let back_buffer = Some(BACK_BUFFER..BACK_BUFFER + 16 * 16 * 4);
+        let framebuffer = Framebuffer::new(16, 16, 0x1000..0x1400 + HEADER_SIZE, back_buffer).unwrap();
+        bus.write_data(0x1000 + HEADER_SIZE, 0x0000_00FF, 4);
+        bus.write_data(BACK_BUFFER, 0x00FF_0000, 4);
+
+        assert_eq!(framebuffer.render(&bus).pixels[0], 0x0000_00FF);
+        bus.write_data(0x1000 + 16, 1, 4);
+        assert_eq!(framebuffer.render(&bus).pixels[0], 0x00FF_0000);
+        bus.write_data(0x1000 + 16, 0, 4);
+        assert_eq!(framebuffer.render(&bus).pixels[0], 0x0000_00FF);
+    }
+
+    #[test]
+    fn fast_path_matches_the_per_word_path()
+    {
+        let mut bus = Bus::new(Memory::new(0x2000), 0x1000..0x1400 + HEADER_SIZE);
+        let mut framebuffer = Framebuffer::new(16, 16, 0x1000..0x1400 + HEADER_SIZE, None).unwrap();
+        for (index, address) in (0x1000 + HEADER_SIZE..0x1400 + HEADER_SIZE).step_by(4).enumerate()
+        {
+            let pixel = (index as u32).wrapping_mul(0x9E37_79B9) & 0x00FF_FFFF;
+            bus.write_data(address, pixel, 4);
+        }
+
+        let fast = framebuffer.render(&bus).pixels;
+        framebuffer.set_fast_path(false);
+        let per_word = framebuffer.render(&bus).pixels;
+
+        assert_eq!(fast, per_word);
+        assert_eq!(fast[1], 0x9E37_79B9 & 0x00FF_FFFF);
+    }
+
+    #[test]
+    fn frame_callback_receives_the_stored_pixels()
+    {
+        use alloc::boxed::Box;
+        use alloc::rc::Rc;
+        use alloc::vec::Vec;
+        use core::cell::RefCell;
+        use crate::computer::test_computer;
+
+        let frames = Rc::new(RefCell::new(Vec::new()));
+        let received = frames.clone();
+        let mut computer = test_computer("
+            li $s0, 0x1000
+            lui $t0, 0x12
+            sw $t0, 0x24($s0)");
+        computer.set_frame_callback(Box::new(move |pixels, width, height|
+        {
+            received.borrow_mut().push((pixels.to_vec(), width, height));
+        }));
+        computer.run_for(3);
+
+        let frames = frames.borrow();
+        assert_eq!(frames.len(), 3); // one per instruction
+        let (pixels, width, height) = &frames[2];
+        assert_eq!((*width, *height), (8, 8));
+        assert_eq!(pixels[..2], [0, 0x0012_0000]);
+        assert_eq!(frames[1].0[1], 0); // not stored yet
+    }
+
+    #[test]
+    fn absurd_frame_sizes_fall_back_to_the_window_size()
+    {
+        let mut bus = Bus::new(Memory::new(0x2000), 0x1000..0x1400);
+        let mut framebuffer = Framebuffer::new(8, 8, 0x1000..0x1400, None).unwrap();
+        let mut request = |framebuffer: &Framebuffer, width: u32, height: u32|
+        {
+            bus.write_data(0x1004, width, 4);
+            bus.write_data(0x1008, height, 4);
+            let frame = framebuffer.render(&bus);
+            ((frame.width, frame.height), frame.pixels.len())
+        };
+
+        assert_eq!(request(&framebuffer, 12, 10), ((12, 10), 120));
+        assert_eq!(request(&framebuffer, 100_000, 100_000), ((8, 8), 64));
+        assert_eq!(request(&framebuffer, u32::MAX, u32::MAX), ((8, 8), 64));
+
+        // Within the VRAM but over the configured maximum.
+        framebuffer.set_max_size(Some((4, 4)));
+        assert_eq!(request(&framebuffer, 8, 4), ((8, 8), 64));
+        assert_eq!(request(&framebuffer, 4, 4), ((4, 4), 16));
+    }
+}
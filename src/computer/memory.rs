@@ -1,3 +1,9 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+
 pub(super) struct Memory
 {
     data: Vec<u8>,
@@ -12,6 +18,68 @@ impl Memory
             data: vec![0; size],
         }
     }
+
+    pub(super) fn len(&self) -> usize
+    {
+        self.data.len()
+    }
+
+    pub(super) fn as_slice(&self) -> &[u8]
+    {
+        &self.data
+    }
+
+    // Raw bytes, writes through the slice bypass the big-endian accessors.
+    pub(super) fn as_mut_slice(&mut self) -> &mut [u8]
+    {
+        &mut self.data
+    }
+
+    pub(super) fn contains(&self, address: u32, size: u8) -> bool
+    {
+        (address as usize).checked_add(size as usize)
+            .is_some_and(|end| end <= self.data.len())
+    }
+
+    fn check_range(&self, start: u32, length: u32) -> Result<Range<usize>, String>
+    {
+        let start = start as usize;
+        match start.checked_add(length as usize)
+        {
+            Some(end) if end <= self.data.len() => Ok(start..end),
+            _ => Err(format!("0x{:X} bytes at 0x{:08X} are out of the memory", length, start)),
+        }
+    }
+
+    pub(super) fn fill(&mut self, start: u32, length: u32, byte: u8) -> Result<(), String>
+    {
+        let range = self.check_range(start, length)?;
+        self.data[range].fill(byte);
+        Ok(())
+    }
+
+    pub(super) fn read_bytes(&self, start: u32, length: u32) -> Result<&[u8], String>
+    {
+        let range = self.check_range(start, length)?;
+        Ok(&self.data[range])
+    }
+
+    pub(super) fn write_bytes(&mut self, start: u32, bytes: &[u8]) -> Result<(), String>
+    {
+        let range = self.check_range(start, bytes.len() as u32)?;
+        self.data[range].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    // Copies like memmove, the ranges may overlap.
+    pub(super) fn copy_within(&mut self, source: u32, destination: u32, length: u32) -> Result<(), String>
+    {
+        let source = self.check_range(source, length)?;
+        let destination = self.check_range(destination, length)?;
+        self.data.copy_within(source, destination.start);
+        Ok(())
+    }
+
     fn read_byte(&self, address: usize) -> u32
     {
         self.data[address] as u32
@@ -61,10 +129,7 @@ impl Memory
     fn write_word(&mut self, address: usize, data: u32)
     {
         let bytes: [u8; 4] = u32::to_be_bytes(data);
-        for i in 0..4
-        {
-            self.data[address + i] = bytes[i];
-        }
+        self.data[address..address + 4].copy_from_slice(&bytes);
     }
 
     pub(super) fn write_data(&mut self, address: u32, data: u32, size: u8)
@@ -80,6 +145,39 @@ impl Memory
     }
 }
 
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn fill_sets_only_the_range()
+    {
+        let mut memory = Memory::new(16);
+        memory.fill(4, 8, 0xAA).unwrap();
+
+        assert_eq!(memory.as_slice()[..4], [0; 4]);
+        assert_eq!(memory.as_slice()[4..12], [0xAA; 8]);
+        assert_eq!(memory.as_slice()[12..], [0; 4]);
+        assert!(memory.fill(12, 8, 0xFF).is_err());
+        assert!(memory.fill(u32::MAX, 2, 0xFF).is_err());
+        assert_eq!(memory.as_slice()[12..], [0; 4]); // a refused fill changes nothing
+    }
 
+    #[test]
+    fn copy_within_handles_overlapping_blocks()
+    {
+        let mut memory = Memory::new(8);
+        memory.write_bytes(0, &[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
 
+        memory.copy_within(0, 2, 4).unwrap(); // forwards over its own source
+        assert_eq!(memory.as_slice(), [1, 2, 1, 2, 3, 4, 7, 8]);
+        memory.copy_within(2, 0, 4).unwrap(); // backwards
+        assert_eq!(memory.as_slice(), [1, 2, 3, 4, 3, 4, 7, 8]);
+        memory.copy_within(4, 0, 4).unwrap(); // disjoint
+        assert_eq!(memory.as_slice(), [3, 4, 7, 8, 3, 4, 7, 8]);
 
+        assert!(memory.copy_within(6, 0, 4).is_err());
+        assert!(memory.copy_within(0, 6, 4).is_err());
+    }
+}
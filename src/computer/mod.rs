@@ -1,85 +1,2286 @@
-use crate::computer::cpu::CPU;
+use alloc::boxed::Box;
+#[cfg(feature = "gui")]
+use alloc::rc::Rc;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "gui")]
+use core::cell::Cell;
+use core::ops::Range;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::io::{BufRead, Cursor, Write};
+#[cfg(feature = "std")]
+use std::path::PathBuf;
+use crate::computer::bus::Bus;
+use crate::computer::console::ConsoleInput;
+use crate::computer::cpu::Cpu;
+use crate::computer::dma::DmaController;
+use crate::computer::elf::ElfReader;
+use crate::computer::fault::{Fault, FaultKind};
+use crate::computer::framebuffer::Framebuffer;
 use crate::computer::memory::Memory;
+use crate::computer::scoreboard::Scoreboard;
+use crate::assembler::{assemble, Assembly};
+use crate::data_section::assemble_data;
+use crate::image::Image;
 use crate::memory_layout::MemoryLayout;
+use crate::computer::symbols::SymbolTable;
+use crate::disassembler::{disassemble, DisasmOptions};
+use crate::computer::timer::IntervalTimer;
+use crate::computer::trace::TraceEntry;
+#[cfg(feature = "std")]
+use crate::computer::syscalls::SyscallService;
+#[cfg(feature = "gui")]
 use crate::computer::video::Video;
 
+mod bus;
+mod console;
 pub mod cpu;
+mod dma;
+mod elf;
+pub mod fault;
+#[cfg(not(any(feature = "std", test)))]
+mod float;
+mod framebuffer;
 mod memory;
+pub mod profile;
+pub mod scoreboard;
+mod symbols;
+#[cfg(feature = "std")]
+mod syscalls;
+mod timer;
+pub mod trace;
+#[cfg(feature = "gui")]
 mod video;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExecutionMode
+{
+    Functional, // whole instructions at once, fast
+    Phased, // the CPU exchanges memory requests with the computer every phase
+}
+
+// Phase the CPU will run on its next tick.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase
+{
+    Fetch,
+    DecodeAndExecute,
+    MemoryWait,
+    WriteBack,
+    InterruptCheck,
+}
+
+// A memory access the CPU asked for, see PhaseSnapshot.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryRequest
+{
+    pub address: u32,
+    pub data: u32, // to store
+    pub size: u8,
+    pub store: bool,
+    pub register: u8, // to load into
+}
+
+// What a single tick of the CPU did, see Computer::tick_once.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PhaseSnapshot
+{
+    pub phase: Phase, // the phase that ran
+    pub next_phase: Phase,
+    pub request: Option<MemoryRequest>, // the access for the next phase to carry out
+    pub fault: Option<Fault>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum HaltReason
+{
+    WindowClosed,
+    Breakpoint(u32),
+    Fault(Fault),
+    Exited(i32), // exit code of the guest, see set_host_syscalls and set_halt_register
+    Reached(u32), // the target of run_to
+    StepLimit,
+    RegisterChanged { register: u8, old: u32, new: u32, pc: u32 }, // see watch_register
+    ExceptionStorm, // see set_exception_storm_limit
+}
+
+pub type ExceptionHandler = Box<dyn FnMut(&mut Computer)>; // see Computer::on_exception
+pub type FrameCallback = Box<dyn FnMut(&[u32], usize, usize)>; // pixels, width, height, see Computer::set_frame_callback
+
 pub struct Computer
 {
-    cpu: CPU,
-    ram: Memory,
-    video: Video,
+    cpu: Cpu,
+    bus: Bus,
+    framebuffer: Framebuffer,
+    frame_callback: Option<FrameCallback>, // None when headless
+    display_scale: usize, // window pixels per framebuffer pixel, see set_display_scale
+    #[cfg(feature = "gui")]
+    window_open: Option<Rc<Cell<bool>>>, // kept up to date by the window's frame callback
+    program: Range<u32>,
+    data: Range<u32>,
+    symbols: SymbolTable,
+    breakpoints: BTreeSet<u32>,
+    watched_registers: BTreeSet<u8>,
+    execution_mode: ExecutionMode,
+    monitor: bool, // see set_monitor
+    tick_interrupt_requests: u8, // of the devices at the last fetch of tick_once
+    jump_to_zero_guard: bool, // see set_jump_to_zero_guard
+    instruction_trace: Option<Vec<TraceEntry>>, // see set_instruction_trace
+    exception_storm_limit: Option<u32>, // see set_exception_storm_limit
+    exception_streak: u32, // steps in a row that entered the exception vector
+    exception_handlers: BTreeMap<u8, ExceptionHandler>, // see on_exception
+    profile: Option<BTreeMap<u32, u64>>, // runs of every instruction address, see set_profiling
+    scoreboard: Option<Scoreboard>, // see set_scoreboard
+    seed: u64, // of the random syscalls, see set_seed
+    #[cfg(feature = "std")]
+    syscalls: SyscallService,
 }
 
 impl Computer
 {
-    pub fn new(memory_size: usize, display_width: usize, display_height: usize,
-        memory_layout: MemoryLayout) -> Computer
+    pub fn new(memory_size: usize, display_width: usize, display_height: usize, display_scale: usize,
+        memory_layout: MemoryLayout) -> Result<Computer, String>
     {
+        let mut computer = Computer::new_headless(memory_size, display_width, display_height, memory_layout)?;
+        computer.set_display_scale(display_scale)?;
+        #[cfg(feature = "gui")]
+        {
+            let mut video = Video::new(display_width, display_height, display_scale)?;
+            let window_open = Rc::new(Cell::new(true));
+            let callback_window_open = window_open.clone();
+            computer.set_frame_callback(Box::new(move |pixels, width, height|
+            {
+                // A window that can't be updated any more ends the run like a closed one.
+                let updated = video.display(pixels, width, height).is_ok();
+                callback_window_open.set(updated && video.is_open());
+            }));
+            computer.window_open = Some(window_open);
+        }
+        Ok(computer)
+    }
+
+    // A computer without a window, the screen is still rendered for framebuffer and screenshots.
+    pub fn new_headless(memory_size: usize, display_width: usize, display_height: usize,
+        memory_layout: MemoryLayout) -> Result<Computer, String>
+    {
+        check_word_aligned("Program base", memory_layout.program.start)?;
+
+        let video_ram = &memory_layout.video_ram;
+        if video_ram.end as usize > memory_size
+        {
+            return Err(format!("VRAM ends at 0x{:08X}, past the 0x{:X} bytes of RAM", video_ram.end, memory_size));
+        }
+
+        if let Some(back_buffer) = &memory_layout.back_buffer
+        {
+            if back_buffer.end as usize > memory_size
+            {
+                return Err(format!("Back buffer ends at 0x{:08X}, past the 0x{:X} bytes of RAM",
+                    back_buffer.end, memory_size));
+            }
+        }
+        let framebuffer = Framebuffer::new(display_width, display_height, memory_layout.video_ram.clone(),
+                                           memory_layout.back_buffer.clone())?;
+
         let mut ram = Memory::new(memory_size);
 
         let program_start = memory_layout.program.start;
         let loop_instruction: u32 = 0b0000_1000_0000_0000_0000_0000_0000_0000;
         ram.write_data(program_start, loop_instruction, 4);
 
-        let vram_start = memory_layout.video_ram.start;
-        //
-        // println!("filling vram");
-        // for address in (vram_start..memory_size).step_by(4)
-        // {
-        //     ram.write_data(address as u32, 0xFF_00_00_FF, 4);
-        // }
-        // println!("vram filled");
+        let mut computer = Computer
+        {
+            cpu: Cpu::new(),
+            bus: Bus::new(ram, video_ram.clone()),
+            framebuffer,
+            frame_callback: None,
+            display_scale: 1,
+            #[cfg(feature = "gui")]
+            window_open: None,
+            program: memory_layout.program,
+            data: memory_layout.data.clone(),
+            symbols: SymbolTable::new(),
+            breakpoints: BTreeSet::new(),
+            watched_registers: BTreeSet::new(),
+            execution_mode: ExecutionMode::Phased,
+            monitor: false,
+            tick_interrupt_requests: 0,
+            jump_to_zero_guard: false,
+            instruction_trace: None,
+            exception_storm_limit: None,
+            exception_streak: 0,
+            exception_handlers: BTreeMap::new(),
+            profile: None,
+            scoreboard: None,
+            seed: 0,
+            #[cfg(feature = "std")]
+            syscalls: SyscallService::new(memory_layout.data),
+        };
+
+        let stack_top = computer.stack_top();
+        computer.set_stack_top(stack_top);
+        Ok(computer)
+    }
+
+    /*
+        A headless computer running the given program image from the start of the program
+        region, without touching the file system (for embedding and fuzzing).
+     */
+    pub fn with_program(program: &[u8], memory_size: usize, display_width: usize, display_height: usize,
+        memory_layout: MemoryLayout) -> Result<Computer, String>
+    {
+        let mut computer = Computer::new_headless(memory_size, display_width, display_height, memory_layout)?;
+        computer.load_program(program, false)?;
+        computer.cpu.set_pc(computer.program.start);
+        Ok(computer)
+    }
+
+    // Advances the devices and runs one instruction with their interrupt requests.
+    fn step(&mut self) -> Result<(), FaultKind>
+    {
+        let interrupt_requests = self.bus.step();
+        self.cpu_step(interrupt_requests)?;
+
+        match self.cpu.take_halted_exception()
+        {
+            Some(code) => Err(FaultKind::UnhandledException(code)),
+            None => Ok(()),
+        }
+    }
+
+    fn cpu_step(&mut self, interrupt_requests: u8) -> Result<(), FaultKind>
+    {
+        if self.cpu.is_waiting()
+        {
+            self.cpu.check_interrupts(interrupt_requests); // Idle until an interrupt wakes the CPU.
+            return Ok(());
+        }
 
-        Computer
+        match self.execution_mode
         {
-            cpu: CPU::new(),
-            ram,
-            video: Video::new(display_width, display_height, vram_start),
+            ExecutionMode::Functional => self.cpu.execute_one(&mut self.bus)?,
+            ExecutionMode::Phased => self.cpu_phases(interrupt_requests)?,
         }
+        self.finish_instruction(self.execution_mode, interrupt_requests)
     }
 
-    fn cpu_step(&mut self, interrupt_requests: u8)
+    // Services the instruction that has just run and sends the interrupt requests.
+    fn finish_instruction(&mut self, execution_mode: ExecutionMode, interrupt_requests: u8) -> Result<(), FaultKind>
     {
-        // FETCH
-        let mem_request = self.cpu.tick(0, interrupt_requests);
-        let pc = mem_request.address;
-        let instruction = self.ram.read_data(pc, 4);
+        self.trace_instruction();
+        self.profile_instruction();
+        self.score_instruction();
 
-        // EXECUTE
-        let mem_request = self.cpu.tick(instruction, interrupt_requests);
+        #[cfg(feature = "std")]
+        if self.cpu.take_pending_syscall()
+        {
+            self.service_syscall()?;
+        }
+
+        // Send interrupt requests.
+        match execution_mode
+        {
+            ExecutionMode::Functional => self.cpu.check_interrupts(interrupt_requests),
+            ExecutionMode::Phased =>
+            {
+                self.cpu.tick(0, interrupt_requests);
+            },
+        }
 
-        // check for memory request
-        match (mem_request.data_size, mem_request.store, mem_request.address)
+        self.run_exception_handler();
+        Ok(())
+    }
+
+    // Fetch, execute and write back through the memory requests of the CPU.
+    fn cpu_phases(&mut self, interrupt_requests: u8) -> Result<(), FaultKind>
+    {
+        while self.cpu.phase() != Phase::InterruptCheck
+        {
+            self.tick_phase(interrupt_requests)?;
+        }
+        Ok(())
+    }
+
+    // Runs the next phase of the CPU, answering the memory request of the previous one.
+    fn tick_phase(&mut self, interrupt_requests: u8) -> Result<(), FaultKind>
+    {
+        let mem_request = self.cpu.memory_request();
+        match self.cpu.phase()
         {
-            (0, _, _) => self.cpu.tick(0, interrupt_requests), // no cpu ram transmission
-            (size, false, addr) => // load from RAM
+            Phase::Fetch =>
             {
-                let data = self.ram.read_data(addr, size);
-                self.cpu.tick(data, interrupt_requests)
+                let fetch_request = self.cpu.tick(0, interrupt_requests);
+                self.cpu.check_fetch(&self.bus, fetch_request.address)?; // aborts to the interrupt check
             },
-            (size, true, addr) => // write to RAM
+            Phase::DecodeAndExecute =>
             {
-                let data = mem_request.data;
-                self.ram.write_data(addr, data, size);
-                self.cpu.tick(0, interrupt_requests)
+                let instruction = self.bus.read_data(mem_request.address, 4);
+                let mem_request = self.cpu.tick(instruction, interrupt_requests);
+                if let Some(fault) = self.cpu.take_fault()
+                {
+                    return Err(fault);
+                }
+
+                let (size, address) = (mem_request.data_size, mem_request.address);
+                if size > 0 && !self.bus.contains(address, size)
+                {
+                    return Err(FaultKind::BadAddress(address));
+                }
+            },
+            Phase::MemoryWait =>
+            {
+                self.cpu.tick(0, interrupt_requests);
+            },
+            Phase::WriteBack =>
+            {
+                let next_request = match (mem_request.data_size, mem_request.store, mem_request.address)
+                {
+                    (0, _, _) => self.cpu.tick(0, interrupt_requests), // no cpu ram transmission
+                    (size, false, addr) => // load from RAM
+                    {
+                        let data = self.bus.load(addr, size);
+                        self.cpu.tick(data, interrupt_requests)
+                    },
+                    (size, true, addr) => // write to RAM
+                    {
+                        self.bus.write_data(addr, mem_request.data, size);
+                        self.cpu.tick(0, interrupt_requests)
+                    }
+                };
+
+                // A doubleword asks for its second word in another write back.
+                let (size, address) = (next_request.data_size, next_request.address);
+                if self.cpu.phase() == Phase::WriteBack && !self.bus.contains(address, size)
+                {
+                    return Err(FaultKind::BadAddress(address));
+                }
+            },
+            Phase::InterruptCheck =>
+            {
+                self.cpu.tick(0, interrupt_requests);
+            },
+        }
+        Ok(())
+    }
+
+    /*
+        Runs exactly one phase of the CPU, for showing the phases one by one. The devices
+        advance once per instruction, at its fetch, and the instruction is finished (syscalls,
+        profiling) at the interrupt check. Works in either execution mode.
+     */
+    pub fn tick_once(&mut self) -> PhaseSnapshot
+    {
+        let phase = self.cpu.phase();
+        if phase == Phase::Fetch
+        {
+            self.tick_interrupt_requests = self.bus.step();
+            if self.cpu.is_waiting()
+            {
+                self.cpu.check_interrupts(self.tick_interrupt_requests); // Idle until an interrupt wakes the CPU.
             }
+        }
+
+        let interrupt_requests = self.tick_interrupt_requests;
+        let result = match (phase, self.cpu.is_waiting())
+        {
+            (Phase::Fetch, true) => Ok(()),
+            (Phase::InterruptCheck, _) => self.finish_instruction(ExecutionMode::Phased, interrupt_requests),
+            _ => self.tick_phase(interrupt_requests),
         };
+        let result = result.and_then(|()| match self.cpu.take_halted_exception()
+        {
+            Some(code) => Err(FaultKind::UnhandledException(code)),
+            None => Ok(()),
+        });
 
-        // Send interrupt requests.
-        self.cpu.tick(0, interrupt_requests);
+        let request = self.cpu.memory_request();
+        PhaseSnapshot
+        {
+            phase,
+            next_phase: self.cpu.phase(),
+            request: (request.data_size > 0).then_some(MemoryRequest
+            {
+                address: request.address,
+                data: request.data,
+                size: request.data_size,
+                store: request.store,
+                register: request.write_back_register,
+            }),
+            fault: result.err().map(|kind| self.fault(kind)),
+        }
+    }
+
+    pub fn run(&mut self) -> HaltReason
+    {
+        while self.is_display_open() // Stop once the user closes the window.
+        {
+            if let Some(reason) = self.run_step()
+            {
+                return reason;
+            }
+
+            let pc = self.cpu.pc();
+            if self.breakpoints.contains(&pc)
+            {
+                return HaltReason::Breakpoint(pc);
+            }
+        }
+
+        HaltReason::WindowClosed
     }
 
-    pub fn run(&mut self)
+    // Runs until the next instruction to fetch is at target_pc, or for at most max_steps.
+    pub fn run_to(&mut self, target_pc: u32, max_steps: u64) -> HaltReason
     {
-        let interrupt_requests = 0;
+        let mut steps = 0;
         loop
         {
-            self.cpu_step(interrupt_requests);
-            self.video.display(&self.ram);
+            if self.cpu.pc() == target_pc
+            {
+                return HaltReason::Reached(target_pc);
+            }
+            if steps == max_steps
+            {
+                return HaltReason::StepLimit;
+            }
+
+            if let Some(reason) = self.run_step()
+            {
+                return reason;
+            }
+            steps += 1;
+        }
+    }
+
+    fn run_exception_handler(&mut self)
+    {
+        let Some(code) = self.cpu.take_host_exception() else
+        {
+            return;
+        };
+
+        // The handler gets the whole computer, so it is out of the map while it runs.
+        if let Some(mut handler) = self.exception_handlers.remove(&code)
+        {
+            handler(self);
+            self.exception_handlers.entry(code).or_insert(handler);
+        }
+    }
+
+    /*
+        Handles the exceptions with the given Cause ExcCode in the host instead of the guest
+        vector: the CPU doesn't enter kernel mode, the handler runs once the instruction
+        has finished and the guest goes on with the next one.
+     */
+    pub fn on_exception(&mut self, code: u8, handler: ExceptionHandler) -> Result<(), String>
+    {
+        if code >= 32
+        {
+            return Err(format!("There is no exception code {}", code));
+        }
+
+        self.cpu.set_host_exception(code, true);
+        self.exception_handlers.insert(code, handler);
+        Ok(())
+    }
+
+    pub fn remove_exception_handler(&mut self, code: u8)
+    {
+        self.cpu.set_host_exception(code, false);
+        self.exception_handlers.remove(&code);
+    }
+
+    // Runs at most the given number of instructions, stopping at breakpoints like run.
+    pub fn run_for(&mut self, steps: u64) -> HaltReason
+    {
+        for _ in 0..steps
+        {
+            if let Some(reason) = self.run_step()
+            {
+                return reason;
+            }
+
+            let pc = self.cpu.pc();
+            if self.breakpoints.contains(&pc)
+            {
+                return HaltReason::Breakpoint(pc);
+            }
+        }
+
+        HaltReason::StepLimit
+    }
+
+    /*
+        Writes the instruction word at the PC and runs it, for trying out single opcodes
+        without the assembler. Returns the reason if the machine stopped, like run_for.
+     */
+    pub fn execute_instruction(&mut self, instruction: u32) -> Option<HaltReason>
+    {
+        let pc = self.cpu.pc();
+        if !self.bus.ram.contains(pc, 4)
+        {
+            return Some(HaltReason::Fault(self.fault(FaultKind::BadAddress(pc))));
+        }
+
+        self.bus.ram.write_data(pc, instruction, 4);
+        self.run_step()
+    }
+
+    // Runs one instruction and refreshes the display, returns the reason if the machine stopped.
+    fn run_step(&mut self) -> Option<HaltReason>
+    {
+        let registers = self.cpu.registers();
+        let exceptions_taken = self.cpu.exceptions_taken();
+        if let Err(kind) = self.step()
+        {
+            return Some(HaltReason::Fault(self.fault(kind)));
+        }
+
+        if let Some(reason) = self.register_change(&registers)
+        {
+            return Some(reason);
+        }
+
+        if let Some(fault) = self.monitor_exception()
+        {
+            return Some(HaltReason::Fault(fault));
+        }
+
+        if self.is_jump_to_zero()
+        {
+            return Some(HaltReason::Fault(self.fault(FaultKind::JumpToZero)));
+        }
+
+        if self.is_exception_storm(exceptions_taken)
+        {
+            return Some(HaltReason::ExceptionStorm);
+        }
+
+        #[cfg(feature = "std")]
+        if let Some(code) = self.syscalls.take_exit_code()
+        {
+            return Some(HaltReason::Exited(code));
+        }
+
+        if let Some(code) = self.bus.take_exit_code()
+        {
+            return Some(HaltReason::Exited(code));
+        }
+
+        if let Some(callback) = &mut self.frame_callback
+        {
+            let frame = self.framebuffer.render(&self.bus);
+            callback(&frame.pixels, frame.width, frame.height);
+        }
+
+        None
+    }
+
+    /*
+        Runs at most max_steps instructions with the given seed and input, without the display
+        and the breakpoints, and returns the hash of the final state.
+        The same program, seed, input and step count always give the same hash.
+     */
+    #[cfg(feature = "std")]
+    pub fn run_deterministic(&mut self, seed: u64, input: &[u8], max_steps: u64) -> u64
+    {
+        self.set_seed(seed);
+        self.set_input(Box::new(Cursor::new(input.to_vec())));
+
+        for _ in 0..max_steps
+        {
+            if self.step().is_err() || self.syscalls.take_exit_code().is_some() || self.bus.take_exit_code().is_some()
+            {
+                break;
+            }
+        }
+
+        self.state_hash()
+    }
+
+    // FNV-1a hash of the CPU registers, the whole RAM and VRAM.
+    pub fn state_hash(&self) -> u64
+    {
+        const FNV_OFFSET_BASIS: u64 = 0xCBF2_9CE4_8422_2325;
+        const FNV_PRIME: u64 = 0x0000_0100_0000_01B3;
+
+        let registers = self.cpu.state_words().into_iter()
+            .flat_map(u32::to_be_bytes);
+        let memory = self.bus.ram.as_slice().iter().chain(self.bus.vram.as_slice()).copied();
+
+        registers.chain(memory).fold(FNV_OFFSET_BASIS, |hash, byte|
+            (hash ^ byte as u64).wrapping_mul(FNV_PRIME))
+    }
+
+    // Restarts the generator of the random syscalls (MARS 40-42) from the seed, 0 at power on.
+    pub fn set_seed(&mut self, seed: u64)
+    {
+        self.seed = seed;
+        #[cfg(feature = "std")]
+        self.syscalls.seed_random(seed);
+    }
+
+    pub fn seed(&self) -> u64
+    {
+        self.seed
+    }
+
+    // Reports the first watched register whose value differs from before the step.
+    fn register_change(&self, before: &[u32; 32]) -> Option<HaltReason>
+    {
+        let after = self.cpu.registers();
+        self.watched_registers.iter()
+            .find(|&&register| before[register as usize] != after[register as usize])
+            .map(|&register| HaltReason::RegisterChanged
+            {
+                register,
+                old: before[register as usize],
+                new: after[register as usize],
+                pc: self.cpu.instruction_address(),
+            })
+    }
+
+    /*
+        The built-in monitor stands in for a missing exception handler: once the CPU jumps
+        to the vector and nothing is installed there (no memory or a zero word), it prints
+        the exception to the console and halts the machine.
+     */
+    fn monitor_exception(&mut self) -> Option<Fault>
+    {
+        let vector = self.cpu.exception_vector();
+        let handler_installed = self.bus.contains(vector, 4) && self.bus.read_data(vector, 4) != 0;
+        if !self.monitor || self.cpu.pc() != vector || handler_installed
+        {
+            return None;
+        }
+
+        let fault = self.fault(FaultKind::UnhandledException(self.cpu.exception_code()));
+        #[cfg(feature = "std")]
+        self.syscalls.print(format!("{}", fault).as_bytes());
+        Some(fault)
+    }
+
+    fn is_jump_to_zero(&self) -> bool
+    {
+        let jump = self.cpu.instruction_address();
+        if !self.jump_to_zero_guard || self.cpu.pc() != 0 || !self.bus.contains(jump, 4)
+        {
+            return false;
+        }
+
+        let instruction = self.bus.read_data(jump, 4);
+        instruction >> 26 == 0 && matches!(instruction & 0b111111, 8 | 9) // jr, jalr
+    }
+
+    /*
+        Halts with a fault when jr or jalr goes to address 0, almost always a return through
+        a $ra that was never set. Off by default, a program may legitimately start at 0.
+     */
+    pub fn set_jump_to_zero_guard(&mut self, enabled: bool)
+    {
+        self.jump_to_zero_guard = enabled;
+    }
+
+    // Counts the steps entering the exception vector in a row, any other step ends the streak.
+    fn is_exception_storm(&mut self, exceptions_taken_before: u64) -> bool
+    {
+        self.exception_streak = match self.cpu.exceptions_taken() == exceptions_taken_before
+        {
+            true => 0,
+            false => self.exception_streak.saturating_add(1),
+        };
+        self.exception_storm_limit.is_some_and(|limit| self.exception_streak > limit)
+    }
+
+    /*
+        Stops run once more than limit steps in a row have entered the exception vector, as when
+        the handler itself faults. None, the default, lets the machine go on forever.
+     */
+    pub fn set_exception_storm_limit(&mut self, limit: Option<u32>)
+    {
+        self.exception_storm_limit = limit;
+        self.exception_streak = 0;
+    }
+
+    /*
+        Makes the moves to and from coprocessor 2 (mfc2, mtc2, cfc2, ctc2) work on a register file
+        of its own. It has no operations. Off by default, then CP2 instructions raise
+        Coprocessor Unusable.
+     */
+    pub fn set_coprocessor2(&mut self, enabled: bool)
+    {
+        self.cpu.set_cp2_enabled(enabled);
+    }
+
+    /*
+        Restricts the CPU to a subset of the instruction set for teaching, any other instruction
+        raises Reserved Instruction. Mnemonics are as disassemble shows them (nop, add.s, ...),
+        None allows everything again.
+     */
+    pub fn set_allowed_instructions(&mut self, mnemonics: Option<BTreeSet<String>>)
+    {
+        self.cpu.set_allowed_instructions(mnemonics);
+    }
+
+    // Halts unhandled exceptions with a report instead of jumping to an empty vector, off by default.
+    pub fn set_monitor(&mut self, enabled: bool)
+    {
+        self.monitor = enabled;
+    }
+
+    /*
+        Stops the machine at the first exception instead of jumping to the vector, for debugging.
+        The fault reports the ExcCode and the EPC, exceptions with a host handler aren't affected.
+     */
+    pub fn set_halt_on_exception(&mut self, enabled: bool)
+    {
+        self.cpu.set_halt_on_exception(enabled);
+    }
+
+    // Captures the machine state for the fault report.
+    fn fault(&self, kind: FaultKind) -> Fault
+    {
+        // An exception is reported at the address it would return to.
+        let pc = match kind
+        {
+            FaultKind::UnhandledException(_) => self.cpu.epc(),
+            _ => self.cpu.instruction_address(),
+        };
+
+        let instruction = match self.bus.ram.contains(pc, 4)
+        {
+            true => self.bus.ram.read_data(pc, 4),
+            false => 0,
+        };
+
+        Fault
+        {
+            kind,
+            pc,
+            symbol: self.symbols.symbol(pc),
+            instruction,
+            text: disassemble(instruction, pc, self.symbols.disasm_options()),
+            registers: self.cpu.registers(),
         }
     }
+
+    #[cfg(feature = "gui")]
+    fn is_display_open(&self) -> bool
+    {
+        self.window_open.as_ref().is_none_or(|open| open.get())
+    }
+
+    #[cfg(not(feature = "gui"))]
+    fn is_display_open(&self) -> bool
+    {
+        true // Without a window the machine runs until it halts by itself.
+    }
+
+    /*
+        Maps an interval timer (see timer) at the base address, over the RAM.
+        It requests the given interrupt line, 0-7 (Cause IP0-IP7).
+     */
+    pub fn add_interval_timer(&mut self, base: u32, interrupt_line: u8)
+    {
+        self.bus.add_timer(IntervalTimer::new(base, interrupt_line));
+    }
+
+    /*
+        Maps a DMA controller (see dma) at the base address, over the RAM.
+        It requests the given interrupt line, 0-7, once a copy is done.
+     */
+    pub fn add_dma_controller(&mut self, base: u32, interrupt_line: u8)
+    {
+        self.bus.add_dma_controller(DmaController::new(base, interrupt_line));
+    }
+
+    /*
+        Maps the console input (see console) at the base address, over the RAM, replacing the
+        one mapped before. It requests the given interrupt line, 0-7, while input is queued.
+     */
+    pub fn add_console_input(&mut self, base: u32, interrupt_line: u8)
+    {
+        self.bus.set_console(ConsoleInput::new(base, interrupt_line));
+    }
+
+    // Queues bytes for the guest to read from the console input.
+    pub fn queue_console_input(&mut self, bytes: &[u8]) -> Result<(), String>
+    {
+        let console = self.bus.console_mut().ok_or("There is no console input, see add_console_input")?;
+        console.queue(bytes);
+        Ok(())
+    }
+
+    /*
+        Maps a word at the address, over the RAM, that stops the machine when the guest
+        writes it: run returns Exited with the written value as the exit code. Reads give 0.
+        None removes the register.
+     */
+    pub fn set_halt_register(&mut self, address: Option<u32>)
+    {
+        self.bus.set_halt_register(address);
+    }
+
+    /*
+        Orders the interrupt lines 0-7 from the highest priority, IP7 first by default.
+        Of the lines pending together the first one is taken and the handler finds only it
+        in Cause IP, the others are taken once it returns.
+     */
+    pub fn set_interrupt_priority(&mut self, priority: [u8; 8]) -> Result<(), String>
+    {
+        let lines = priority.iter()
+            .fold(0u8, |lines, &line| lines | 1u8.checked_shl(line as u32).unwrap_or(0));
+        if lines != 0xFF
+        {
+            return Err(format!("Interrupt priority {:?} doesn't list every line 0-7 once", priority));
+        }
+
+        self.cpu.set_interrupt_priority(priority);
+        Ok(())
+    }
+
+    /*
+        Hands every rendered frame to the callback instead of a window, for embedding the
+        machine in another GUI. Replaces the window of a computer made with new.
+     */
+    pub fn set_frame_callback(&mut self, callback: FrameCallback)
+    {
+        self.frame_callback = Some(callback);
+        #[cfg(feature = "gui")]
+        {
+            self.window_open = None;
+        }
+    }
+
+    // Limits the frame size the guest can set in the display header, None for any that fits in VRAM.
+    pub fn set_max_frame_size(&mut self, max_size: Option<(usize, usize)>)
+    {
+        self.framebuffer.set_max_size(max_size);
+    }
+
+    // Reads the direct color pixels with one bulk copy instead of word by word, on by default.
+    pub fn set_fast_vram(&mut self, enabled: bool)
+    {
+        self.framebuffer.set_fast_path(enabled);
+    }
+
+    // The current screen as 0x00RRGGBB pixels, row by row, see framebuffer_size.
+    pub fn framebuffer(&self) -> Vec<u32>
+    {
+        self.framebuffer.render(&self.bus).pixels
+    }
+
+    // Width and height of the current screen, set by the VRAM header.
+    pub fn framebuffer_size(&self) -> (usize, usize)
+    {
+        let frame = self.framebuffer.render(&self.bus);
+        (frame.width, frame.height)
+    }
+
+    // Writes the current screen as a binary PPM image.
+    #[cfg(feature = "std")]
+    pub fn save_screenshot(&self, path: &str) -> Result<(), String>
+    {
+        let frame = self.framebuffer.render(&self.bus);
+
+        let mut image = format!("P6\n{} {}\n255\n", frame.width, frame.height).into_bytes();
+        for pixel in frame.pixels
+        {
+            let [_, red, green, blue] = pixel.to_be_bytes();
+            image.extend([red, green, blue]);
+        }
+
+        fs::write(path, image).map_err(|error| error.to_string())
+    }
+
+    // Address of the next instruction to fetch.
+    pub fn pc(&self) -> u32
+    {
+        self.cpu.pc()
+    }
+
+    pub fn registers(&self) -> [u32; 32]
+    {
+        self.cpu.registers()
+    }
+
+    pub fn set_register(&mut self, register: u8, value: u32) -> Result<(), String>
+    {
+        if register >= 32
+        {
+            return Err(format!("There is no register {}", register));
+        }
+
+        self.cpu.set_register(register, value);
+        Ok(())
+    }
+
+    // HI in the upper and LO in the lower half, as left by a 64-bit multiply.
+    pub fn hilo(&self) -> u64
+    {
+        self.cpu.hilo()
+    }
+
+    pub fn set_hilo(&mut self, value: u64)
+    {
+        self.cpu.set_hilo(value);
+    }
+
+    // Continues from the given address, also after a fault stopped an instruction halfway.
+    pub fn set_pc(&mut self, pc: u32) -> Result<(), String>
+    {
+        check_word_aligned("PC", pc)?;
+        self.cpu.set_pc(pc);
+        Ok(())
+    }
+
+    pub fn current_phase(&self) -> Phase
+    {
+        self.cpu.phase()
+    }
+
+    pub fn set_execution_mode(&mut self, mode: ExecutionMode)
+    {
+        self.execution_mode = mode;
+    }
+
+    // The guest RAM, words are stored big endian. VRAM is separate, see vram.
+    pub fn memory(&self) -> &[u8]
+    {
+        self.bus.ram.as_slice()
+    }
+
+    // Sets the bytes of a range lying wholly in the RAM or in VRAM, e.g. to clear the screen.
+    pub fn fill_memory(&mut self, start: u32, length: u32, byte: u8) -> Result<(), String>
+    {
+        self.bus.fill(start, length, byte)
+    }
+
+    // Copies a block of the RAM or VRAM to another place in either, the two may overlap.
+    pub fn copy_memory(&mut self, source: u32, destination: u32, length: u32) -> Result<(), String>
+    {
+        self.bus.copy(source, destination, length)
+    }
+
+    // The VRAM buffer, starting at the beginning of the video_ram range of the layout.
+    pub fn vram(&self) -> &[u8]
+    {
+        self.bus.vram.as_slice()
+    }
+
+    /*
+        Direct access to the guest memory for loaders and tools. Writes through the slice
+        bypass the big-endian word helpers, multi-byte values have to be stored big endian.
+     */
+    pub fn memory_mut(&mut self) -> &mut [u8]
+    {
+        self.bus.ram.as_mut_slice()
+    }
+
+    /*
+        Copies a raw program image to the start of the program region.
+        With swap_bytes every word is byte-swapped, for images built for a little-endian machine.
+     */
+    pub fn load_program(&mut self, image: &[u8], swap_bytes: bool) -> Result<(), String>
+    {
+        if swap_bytes && !image.len().is_multiple_of(4)
+        {
+            return Err(format!("Image of {} bytes isn't made of whole words", image.len()));
+        }
+
+        let start = self.program.start as usize;
+        let end = start + image.len();
+        if end > self.program.end as usize || end > self.bus.ram.len()
+        {
+            return Err(format!("Program of {} bytes doesn't fit in the program region", image.len()));
+        }
+
+        let destination = &mut self.bus.ram.as_mut_slice()[start..end];
+        destination.copy_from_slice(image);
+        if swap_bytes
+        {
+            destination.chunks_exact_mut(4).for_each(|word| word.reverse());
+        }
+
+        Ok(())
+    }
+
+    /*
+        Assembles the source at the start of the program region, its labels are added
+        to the symbols. The listing of the returned assembly maps source lines to addresses.
+     */
+    pub fn load_assembly(&mut self, source: &str) -> Result<Assembly, String>
+    {
+        let assembly = assemble(source, self.program.start)?;
+
+        let size = assembly.words.len() * 4;
+        let end = self.program.start as usize + size;
+        if end > self.program.end as usize || end > self.bus.ram.len()
+        {
+            return Err(format!("Program of {} bytes doesn't fit in the program region", size));
+        }
+
+        for (address, &word) in (assembly.base..).step_by(4).zip(&assembly.words)
+        {
+            self.bus.ram.write_data(address, word, 4);
+        }
+
+        for (name, &address) in &assembly.labels
+        {
+            self.add_symbol(address, name);
+        }
+
+        Ok(assembly)
+    }
+
+    /*
+        Assembles the data directives (see data_section) at the start of the data region
+        and returns the addresses of their labels, which are added to the symbols too.
+        The sbrk heap starts past the data.
+     */
+    pub fn load_data(&mut self, lines: &[&str]) -> Result<BTreeMap<String, u32>, String>
+    {
+        let image = assemble_data(lines, self.data.start)?;
+
+        let end = self.data.start as usize + image.bytes.len();
+        if end > self.data.end as usize || end > self.bus.ram.len()
+        {
+            return Err(format!("Data section of {} bytes doesn't fit in the data region",
+                image.bytes.len()));
+        }
+
+        for (address, &byte) in (image.base..).zip(&image.bytes)
+        {
+            self.bus.ram.write_data(address, byte as u32, 1);
+        }
+        #[cfg(feature = "std")]
+        self.syscalls.reserve_heap(end as u32);
+
+        for (name, &address) in &image.labels
+        {
+            self.add_symbol(address, name);
+        }
+
+        Ok(image.labels)
+    }
+
+    // Places the sections of a program image (see image) at their bases and jumps to its entry.
+    pub fn load_image_bytes(&mut self, bytes: &[u8]) -> Result<(), String>
+    {
+        let image = Image::parse(bytes)?;
+        check_word_aligned("Program base", image.program_base)?;
+        check_word_aligned("Entry point", image.entry)?;
+
+        let sections = [("Program", image.program_base, &image.program),
+                        ("Data", image.data_base, &image.data)];
+
+        // Check both sections first so a bad image leaves the memory untouched.
+        for (name, base, section) in sections
+        {
+            if base as usize + section.len() > self.bus.ram.len()
+            {
+                return Err(format!("{} section at 0x{:08X} doesn't fit in the memory", name, base));
+            }
+        }
+
+        for (_, base, section) in sections
+        {
+            let start = base as usize;
+            self.bus.ram.as_mut_slice()[start..start + section.len()].copy_from_slice(section);
+        }
+
+        self.cpu.set_pc(image.entry);
+        Ok(())
+    }
+
+    /*
+        Loads the PT_LOAD segments of an ELF executable moved up by the bias, for position
+        independent programs, and jumps to the moved entry point, which is returned.
+     */
+    pub fn load_elf(&mut self, elf: &[u8], bias: u32) -> Result<u32, String>
+    {
+        let reader = ElfReader::new(elf)?;
+        let entry = reader.entry()?.wrapping_add(bias);
+        check_word_aligned("Entry point", entry)?;
+        let segments = reader.loadable_segments()?;
+
+        // Check every segment first so a bad file leaves the memory untouched.
+        for segment in &segments
+        {
+            let start = segment.address.checked_add(bias);
+            let end = start.and_then(|start| start.checked_add(segment.memory_size));
+            if end.is_none_or(|end| end as usize > self.bus.ram.len())
+            {
+                return Err(format!("Segment at 0x{:08X} doesn't fit in the memory with a bias of 0x{:X}",
+                    segment.address, bias));
+            }
+        }
+
+        for segment in &segments
+        {
+            let start = (segment.address + bias) as usize;
+            let (file_part, zeroed_part) = self.bus.ram.as_mut_slice()[start..start + segment.memory_size as usize]
+                .split_at_mut(segment.bytes.len());
+            file_part.copy_from_slice(segment.bytes);
+            zeroed_part.fill(0);
+        }
+
+        self.cpu.set_pc(entry);
+        Ok(entry)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load_elf_file(&mut self, path: &str, bias: u32) -> Result<u32, String>
+    {
+        let elf = fs::read(path).map_err(|error| error.to_string())?;
+        self.load_elf(&elf, bias)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load_image(&mut self, path: &str) -> Result<(), String>
+    {
+        let bytes = fs::read(path).map_err(|error| error.to_string())?;
+        self.load_image_bytes(&bytes)
+    }
+
+    /*
+        Moves the exception vector, 0x80000180 by default, which lies outside of a RAM
+        smaller than 2 GiB. The handler runs in kernel mode wherever it is.
+     */
+    pub fn set_exception_vector(&mut self, address: u32) -> Result<(), String>
+    {
+        check_word_aligned("Exception vector", address)?;
+        self.cpu.set_exception_vector(address);
+        Ok(())
+    }
+
+    /*
+        Writes exception handler code, apart from the program, at the base address and
+        makes it the exception vector. The bytes are big endian words.
+     */
+    pub fn load_handler_bytes(&mut self, handler: &[u8], base: u32) -> Result<(), String>
+    {
+        check_word_aligned("Handler base", base)?;
+        self.bus.ram.write_bytes(base, handler)
+            .map_err(|_| format!("Handler of {} bytes at 0x{:08X} doesn't fit in the memory", handler.len(), base))?;
+        self.cpu.set_exception_vector(base);
+        Ok(())
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load_handler(&mut self, path: &str, base: u32) -> Result<(), String>
+    {
+        let handler = fs::read(path).map_err(|error| error.to_string())?;
+        self.load_handler_bytes(&handler, base)
+    }
+
+    pub fn load_symbols(&mut self, elf: &[u8]) -> Result<(), String>
+    {
+        self.symbols = SymbolTable::from_elf(elf)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load_symbols_file(&mut self, elf_path: &str) -> Result<(), String>
+    {
+        let elf = fs::read(elf_path).map_err(|error| error.to_string())?;
+        self.load_symbols(&elf)
+    }
+
+    pub fn add_symbol(&mut self, address: u32, name: &str)
+    {
+        self.symbols.insert(address, name.to_string());
+    }
+
+    pub fn symbolize(&self, address: u32) -> String
+    {
+        self.symbols.symbolize(address)
+    }
+
+    // Disassembler options naming the branch and jump targets after the loaded symbols.
+    pub fn disasm_options(&self) -> DisasmOptions<'_>
+    {
+        self.symbols.disasm_options()
+    }
+
+    // Code field (bits 6-25) of the last executed syscall instruction.
+    pub fn syscall_code(&self) -> u32
+    {
+        self.cpu.syscall_code()
+    }
+
+    // Services syscalls on the host (MARS conventions) instead of the guest's exception handler.
+    #[cfg(feature = "std")]
+    pub fn set_host_syscalls(&mut self, enabled: bool)
+    {
+        self.cpu.set_host_syscalls(enabled);
+    }
+
+    // Input for the read syscalls, stdin by default.
+    #[cfg(feature = "std")]
+    pub fn set_input(&mut self, input: Box<dyn BufRead>)
+    {
+        self.syscalls.set_input(input);
+    }
+
+    // Output of the print syscalls, stdout by default.
+    #[cfg(feature = "std")]
+    pub fn set_output(&mut self, output: Box<dyn Write>)
+    {
+        self.syscalls.set_output(output);
+    }
+
+    // Restricts the file syscalls to relative paths inside the given directory.
+    #[cfg(feature = "std")]
+    pub fn set_file_sandbox(&mut self, root: Option<PathBuf>)
+    {
+        self.syscalls.set_sandbox(root);
+    }
+
+    // Lowest address of the stack, the sbrk heap may not grow past it.
+    #[cfg(feature = "std")]
+    pub fn set_stack_limit(&mut self, limit: u32)
+    {
+        self.syscalls.set_heap_limit(limit);
+    }
+
+    /*
+        Records how many window pixels show one framebuffer pixel: 1, 2, 4, 8, 16 or 32.
+        The window of Computer::new scales up with nearest neighbour, the framebuffer keeps its size.
+     */
+    pub fn set_display_scale(&mut self, scale: usize) -> Result<(), String>
+    {
+        if !scale.is_power_of_two() || scale > 32
+        {
+            return Err(format!("Unsupported display scale: {}", scale));
+        }
+        self.display_scale = scale;
+        Ok(())
+    }
+
+    pub fn display_scale(&self) -> usize
+    {
+        self.display_scale
+    }
+
+    /*
+        With delay slots the instruction after a jump or a taken branch runs before the
+        target, like on the real pipeline. Off by default, the assembler doesn't fill the slots.
+     */
+    pub fn set_delay_slots(&mut self, enabled: bool)
+    {
+        self.cpu.set_delay_slots(enabled);
+    }
+
+    // With the trap disabled add, addi and sub wrap like addu, addiu and subu.
+    pub fn set_trap_on_overflow(&mut self, enabled: bool)
+    {
+        self.cpu.set_trap_on_overflow(enabled);
+    }
+
+    // Whether the last add, addi or sub overflowed.
+    pub fn overflow(&self) -> bool
+    {
+        self.cpu.overflow()
+    }
+
+    /*
+        Models the multiply/divide unit of the classic pipeline: HI and LO become readable
+        the given number of cycles (instructions) after mult, multu, div or divu.
+        An earlier mfhi or mflo stalls and is counted as a hazard. 0 disables the model.
+     */
+    pub fn set_hilo_latency(&mut self, cycles: u32)
+    {
+        self.cpu.set_hilo_latency(cycles);
+    }
+
+    pub fn hilo_hazards(&self) -> u64
+    {
+        self.cpu.hilo_hazards()
+    }
+
+    // Cycles lost waiting for the multiply/divide unit or the memory.
+    pub fn stall_cycles(&self) -> u64
+    {
+        self.cpu.stall_cycles()
+    }
+
+    // Extra cycles every load and store takes in the phased execution mode.
+    pub fn set_memory_latency(&mut self, cycles: u32)
+    {
+        self.cpu.set_memory_latency(cycles);
+    }
+
+    // Cycles executed so far, four per instruction plus the stalls.
+    pub fn cycles(&self) -> u64
+    {
+        self.cpu.cycles()
+    }
+
+    /*
+        The stack starts at the top of the data region, aligned down to 8, and grows down
+        towards the heap. A new computer starts with $sp there.
+     */
+    fn stack_top(&self) -> u32
+    {
+        self.data.end.min(self.bus.ram.len() as u32) & !0b111
+    }
+
+    // Points $sp to the given address, aligned down to 8, for a stack somewhere else.
+    pub fn set_stack_top(&mut self, address: u32)
+    {
+        const STACK_POINTER_REG: u8 = 29;
+        self.cpu.set_register(STACK_POINTER_REG, address & !0b111);
+    }
+
+    /*
+        Writes the arguments at the top of the data region like a C startup expects them:
+        the strings, below them the NULL terminated argv array, 8-byte aligned, with $sp pointing to it.
+        $a0 holds argc and $a1 argv.
+     */
+    pub fn setup_args(&mut self, args: &[&str]) -> Result<(), String>
+    {
+        const A0: u8 = 4;
+        const A1: u8 = 5;
+
+        let top = self.stack_top();
+        let strings_size: usize = args.iter().map(|arg| arg.len() + 1).sum();
+        let argv_size = (args.len() + 1) * 4;
+
+        let strings_start = (top as usize).checked_sub(strings_size)
+            .ok_or("Arguments don't fit in the data region")?;
+        let argv = strings_start.checked_sub(argv_size)
+            .map(|argv| argv & !0b111)
+            .filter(|&argv| argv >= self.data.start as usize)
+            .ok_or("Arguments don't fit in the data region")? as u32;
+
+        let mut string_address = strings_start as u32;
+        for (index, arg) in args.iter().enumerate()
+        {
+            self.bus.ram.write_data(argv + 4 * index as u32, string_address, 4);
+            for &byte in arg.as_bytes().iter().chain(&[0])
+            {
+                self.bus.ram.write_data(string_address, byte as u32, 1);
+                string_address += 1;
+            }
+        }
+        self.bus.ram.write_data(argv + 4 * args.len() as u32, 0, 4); // argv[argc] = NULL
+
+        self.cpu.set_register(A0, args.len() as u32);
+        self.cpu.set_register(A1, argv);
+        self.set_stack_top(argv);
+        Ok(())
+    }
+
+    pub fn set_trace(&mut self, enabled: bool)
+    {
+        self.cpu.set_call_tracking(enabled);
+    }
+
+    /*
+        Returns the current PC followed by the return addresses of the active calls, innermost first.
+        With tracing enabled the shadow call stack is exact, otherwise the frames are guessed
+        from $ra and the program addresses found on the stack.
+     */
+    pub fn backtrace(&self) -> Vec<u32>
+    {
+        let mut frames = vec![self.cpu.pc()];
+
+        if let Some(call_stack) = self.cpu.call_stack()
+        {
+            frames.extend(call_stack.iter().rev());
+            return frames;
+        }
+
+        const RETURN_ADDRESS_REG: u8 = 31;
+        const STACK_POINTER_REG: u8 = 29;
+        const STACK_SCAN_LIMIT: usize = 256; // words
+
+        let is_return_address = |address: u32| address.is_multiple_of(4) && self.program.contains(&address);
+
+        let return_address = self.cpu.register(RETURN_ADDRESS_REG);
+        if is_return_address(return_address)
+        {
+            frames.push(return_address);
+        }
+
+        let stack_pointer = self.cpu.register(STACK_POINTER_REG) & !0b11;
+        let stack_end = self.data.end.min(self.bus.ram.len() as u32);
+        let stack_words = (stack_pointer..stack_end).step_by(4).take(STACK_SCAN_LIMIT);
+        for address in stack_words
+        {
+            let word = self.bus.ram.read_data(address, 4);
+            if is_return_address(word) && frames.last() != Some(&word)
+            {
+                frames.push(word);
+            }
+        }
+
+        frames
+    }
+
+    // The backtrace with every frame as symbol+0xoffset where a symbol covers it.
+    pub fn symbolized_backtrace(&self) -> Vec<String>
+    {
+        self.backtrace().into_iter()
+            .map(|address| self.symbols.symbolize(address))
+            .collect()
+    }
+
+    pub fn add_breakpoint(&mut self, address: u32)
+    {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn add_breakpoint_symbol(&mut self, name: &str) -> Result<(), String>
+    {
+        let address = self.symbols.address_of(name)
+            .ok_or(format!("Unknown symbol: {}", name))?;
+        self.add_breakpoint(address);
+        Ok(())
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u32)
+    {
+        self.breakpoints.remove(&address);
+    }
+
+    // The breakpoint addresses in ascending order.
+    pub fn breakpoints(&self) -> Vec<u32>
+    {
+        self.breakpoints.iter().copied().collect()
+    }
+
+    pub fn clear_all_breakpoints(&mut self)
+    {
+        self.breakpoints.clear();
+    }
+
+    // Makes run and run_to stop after any instruction that changes the given GPR.
+    pub fn watch_register(&mut self, register: u8)
+    {
+        assert!(register < 32, "There is no register {}.", register);
+        self.watched_registers.insert(register);
+    }
+
+    pub fn unwatch_register(&mut self, register: u8)
+    {
+        self.watched_registers.remove(&register);
+    }
+
+    // The watched GPR numbers in ascending order, memory isn't watched.
+    pub fn watchpoints(&self) -> Vec<u8>
+    {
+        self.watched_registers.iter().copied().collect()
+    }
+
+    pub fn clear_all_watchpoints(&mut self)
+    {
+        self.watched_registers.clear();
+    }
+}
+
+// The CPU fetches whole words, so code can only start at word addresses.
+fn check_word_aligned(what: &str, address: u32) -> Result<(), String>
+{
+    match address.is_multiple_of(4)
+    {
+        true => Ok(()),
+        false => Err(format!("{} 0x{:08X} isn't word aligned", what, address)),
+    }
+}
+
+#[cfg(test)]
+fn test_layout() -> MemoryLayout
+{
+    MemoryLayout
+    {
+        program: 0..0x1000,
+        video_ram: 0x1000..0x1400,
+        back_buffer: None,
+        data: 0x1400..0x4000,
+    }
+}
+
+// A headless computer with the instruction words loaded from address 0.
+#[cfg(test)]
+fn computer_with(program: &[u32]) -> Computer
+{
+    let mut computer = Computer::new_headless(0x4000, 8, 8, test_layout()).unwrap();
+    for (i, &word) in program.iter().enumerate()
+    {
+        computer.bus.ram.write_data(4 * i as u32, word, 4);
+    }
+    computer
+}
+
+// A headless computer with the assembled source at the start of the program region.
+#[cfg(test)]
+fn test_computer(source: &str) -> Computer
+{
+    let mut computer = Computer::new_headless(0x4000, 8, 8, test_layout()).unwrap();
+    computer.load_assembly(source).unwrap();
+    computer
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "gui")]
+    fn closing_the_window_stops_the_run()
+    {
+        let mut computer = test_computer("
+            loop:
+            b loop");
+
+        // A window that the user closes after the tenth frame.
+        let window_open = Rc::new(Cell::new(true));
+        let closer = window_open.clone();
+        let mut frames = 0;
+        computer.set_frame_callback(Box::new(move |_, _, _|
+        {
+            frames += 1;
+            closer.set(frames < 10);
+        }));
+        computer.window_open = Some(window_open);
+
+        assert_eq!(computer.run(), HaltReason::WindowClosed);
+        assert_eq!(computer.cycles(), 40); // ten instructions of four phases
+    }
+
+    #[test]
+    fn symbolized_breakpoint_stops_at_the_function()
+    {
+        let mut computer = computer_with(&[
+            0x0C00_0004, // jal func
+            0x0000_0000, // nop
+            0x0800_0002, // loop: j loop
+            0x0000_0000, // nop
+            0x2402_0001, // func: addiu $v0, $zero, 1
+            0x03E0_0008, // jr $ra
+        ]);
+        computer.add_symbol(16, "func");
+
+        assert!(computer.add_breakpoint_symbol("missing").is_err());
+        computer.add_breakpoint_symbol("func").unwrap();
+        assert_eq!(computer.run(), HaltReason::Breakpoint(16));
+        assert_eq!(computer.symbolize(20), "func+0x4");
+        assert_eq!(disassemble(0x0C00_0004, 0, computer.disasm_options()), "jal func");
+    }
+
+    #[test]
+    fn backtrace_lists_the_nested_calls()
+    {
+        let program = [
+            0x241D_3FFC, // addiu $sp, $zero, 0x3FFC
+            0x0C00_0003, // jal outer
+            0x0800_0002, // loop: j loop
+            0x0C00_0005, // outer: jal inner
+            0x03E0_0008, // jr $ra
+            0x0000_0000, // inner: nop
+            0x03E0_0008, // jr $ra
+        ];
+
+        // The shadow stack of the trace, and the guess from $ra and the saved $ra on the stack.
+        for tracing in [true, false]
+        {
+            let mut computer = computer_with(&program);
+            computer.bus.ram.write_data(0x3FFC, 8, 4); // the $ra of outer, as its prologue saves it
+            computer.set_trace(tracing);
+            computer.add_breakpoint(20);
+            assert_eq!(computer.run(), HaltReason::Breakpoint(20));
+            assert_eq!(computer.backtrace(), vec![20, 16, 8]);
+
+            computer.add_symbol(12, "outer");
+            assert_eq!(computer.symbolized_backtrace()[1], "outer+0x4");
+        }
+    }
+
+    #[test]
+    fn full_word_loads_are_not_extended()
+    {
+        let mut computer = computer_with(&[
+            0x2409_2000, // addiu $t1, $zero, 0x2000
+            0x8D2A_0000, // lw $t2, 0($t1)
+        ]);
+        computer.bus.ram.write_data(0x2000, 0x8000_FFFF, 4);
+
+        computer.add_breakpoint(8);
+        assert_eq!(computer.run(), HaltReason::Breakpoint(8));
+        assert_eq!(computer.cpu.register(10), 0x8000_FFFF);
+    }
+
+    #[test]
+    fn vram_past_the_ram_is_an_error()
+    {
+        let past_the_ram = MemoryLayout { video_ram: 0x3C00..0x4400, ..test_layout() };
+        assert!(Computer::new_headless(0x4000, 8, 8, past_the_ram).is_err());
+    }
+
+    #[test]
+    fn back_buffer_past_the_ram_is_an_error()
+    {
+        let past_the_ram = MemoryLayout { back_buffer: Some(0x3C00..0x4400), ..test_layout() };
+        assert!(Computer::new_headless(0x4000, 8, 8, past_the_ram).is_err());
+    }
+
+    #[test]
+    fn display_scale_must_be_a_supported_power_of_two()
+    {
+        let mut computer = computer_with(&[]);
+
+        assert!(computer.set_display_scale(3).is_err());
+        assert!(computer.set_display_scale(64).is_err());
+        assert!(computer.set_display_scale(0).is_err());
+        assert_eq!(computer.display_scale(), 1);
+
+        computer.set_display_scale(4).unwrap();
+        assert_eq!(computer.display_scale(), 4);
+    }
+
+    #[test]
+    fn functional_and_phased_modes_agree()
+    {
+        let program = [
+            0x2408_000A, // addiu $t0, $zero, 10
+            0x0108_0018, // loop: mult $t0, $t0
+            0x0000_5012, // mflo $t2
+            0x022A_8821, // addu $s1, $s1, $t2
+            0x2108_FFFF, // addi $t0, $t0, -1
+            0x1D00_FFFB, // bgtz $t0, loop
+            0x8C09_2000, // lw $t1, 0x2000($zero)
+            0x800B_2003, // lb $t3, 0x2003($zero)
+            0x4491_1000, // mtc1 $s1, $f2
+            0x4680_1120, // cvt.s.w $f4, $f2
+            0x4604_2180, // add.s $f6, $f4, $f4
+            0x4412_3000, // mfc1 $s2, $f6
+            0x0800_000C, // end: j end
+        ];
+
+        let run = |mode|
+        {
+            let mut computer = computer_with(&program);
+            computer.bus.ram.write_data(0x2000, 0x1234_5678, 4);
+            computer.set_execution_mode(mode);
+            computer.add_breakpoint(48);
+            assert_eq!(computer.run(), HaltReason::Breakpoint(48));
+            computer.cpu.registers()
+        };
+        let functional = run(ExecutionMode::Functional);
+        let phased = run(ExecutionMode::Phased);
+
+        assert_eq!(functional[17], 385); // the sum of the squares of 1 to 10
+        assert_eq!(functional[18], 770.0f32.to_bits());
+        assert_eq!(functional[11], 0x78);
+        assert_eq!(functional, phased);
+    }
+
+    #[test]
+    fn sc_stores_after_ll()
+    {
+        for mode in [ExecutionMode::Functional, ExecutionMode::Phased]
+        {
+            let mut computer = computer_with(&[
+                0x2410_2000, // addiu $s0, $zero, 0x2000
+                0xC208_0000, // ll $t0, 0($s0)
+                0x2409_0005, // addiu $t1, $zero, 5
+                0xE209_0000, // sc $t1, 0($s0)
+            ]);
+            computer.set_execution_mode(mode);
+            computer.add_breakpoint(16);
+
+            assert_eq!(computer.run(), HaltReason::Breakpoint(16));
+            assert_eq!(computer.cpu.register(9), 1);
+            assert_eq!(computer.bus.ram.read_data(0x2000, 4), 5);
+        }
+    }
+
+    #[test]
+    fn loaded_asciiz_reads_back_byte_by_byte()
+    {
+        let mut computer = computer_with(&[]);
+        let labels = computer.load_data(&["number: .word 7", "msg: .asciiz \"hi!\""]).unwrap();
+
+        let msg = labels["msg"];
+        assert_eq!(msg, computer.data.start + 4);
+        assert_eq!(computer.bus.ram.read_data(labels["number"], 4), 7);
+        let bytes: Vec<u32> = (msg..msg + 4).map(|address| computer.bus.ram.read_data(address, 1)).collect();
+        assert_eq!(bytes, [b'h' as u32, b'i' as u32, b'!' as u32, 0]);
+        assert_eq!(computer.symbolize(msg + 1), "msg+0x1");
+    }
+
+    #[test]
+    fn memory_latency_stalls_loads_and_stores()
+    {
+        let program = [
+            0x2408_2000, // addiu $t0, $zero, 0x2000
+            0xAD08_0000, // sw $t0, 0($t0)
+            0x8D09_0000, // lw $t1, 0($t0)
+            0x252A_0001, // addiu $t2, $t1, 1
+        ];
+
+        for mode in [ExecutionMode::Phased, ExecutionMode::Functional]
+        {
+            let mut computer = computer_with(&program);
+            computer.set_execution_mode(mode);
+            computer.set_memory_latency(10);
+            computer.add_breakpoint(16);
+
+            assert_eq!(computer.run(), HaltReason::Breakpoint(16));
+            assert_eq!(computer.cpu.register(10), 0x2001);
+            match mode
+            {
+                ExecutionMode::Phased =>
+                {
+                    assert_eq!(computer.stall_cycles(), 2 * 10); // the store and the load
+                    assert_eq!(computer.cycles(), 4 * 4 + 2 * 10);
+                },
+                // The functional mode doesn't model the latency.
+                ExecutionMode::Functional =>
+                {
+                    assert_eq!(computer.stall_cycles(), 0);
+                    assert_eq!(computer.cycles(), 4 * 4);
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn loaded_assembly_runs_to_its_labels()
+    {
+        let mut computer = test_computer("
+            li $t0, 0x12345678
+            move $t1, $t0
+            done:
+            b done");
+        computer.add_breakpoint_symbol("done").unwrap();
+
+        assert_eq!(computer.run(), HaltReason::Breakpoint(12));
+        assert_eq!(computer.cpu.register(9), 0x1234_5678);
+        assert!(computer.load_assembly(&"nop\n".repeat(0x401)).is_err()); // past the program region
+    }
+
+    #[test]
+    fn sub_word_loads_extend_by_signedness()
+    {
+        let mut computer = test_computer("
+            li $s0, 0x2000
+            li $t0, 0x8000
+            sh $t0, 0($s0)
+            li $t1, 0x80
+            sb $t1, 4($s0)
+            lh $t2, 0($s0)
+            lhu $t3, 0($s0)
+            lb $t4, 4($s0)
+            lbu $t5, 4($s0)
+            lb $t6, 0($s0)
+            lbu $t7, 1($s0)
+            done:
+            b done");
+        computer.add_breakpoint_symbol("done").unwrap();
+        computer.run();
+
+        let registers = computer.cpu.registers();
+        assert_eq!(registers[10], 0xFFFF_8000); // lh
+        assert_eq!(registers[11], 0x0000_8000); // lhu
+        assert_eq!(registers[12], 0xFFFF_FF80); // lb
+        assert_eq!(registers[13], 0x0000_0080); // lbu
+        assert_eq!(registers[14], 0xFFFF_FF80); // the high byte of the big endian halfword
+        assert_eq!(registers[15], 0);
+    }
+
+    #[test]
+    fn program_sees_argc_and_argv()
+    {
+        let mut computer = test_computer("
+            move $s0, $a0
+            lw $t0, 4($a1)
+            lbu $s1, 0($t0)
+            lw $t1, 8($a1)
+            lbu $s2, 1($t1)
+            lw $s3, 12($a1)
+            done:
+            b done");
+        computer.setup_args(&["prog", "alpha", "beta"]).unwrap();
+        computer.add_breakpoint_symbol("done").unwrap();
+
+        assert_eq!(computer.run(), HaltReason::Breakpoint(24));
+        let registers = computer.cpu.registers();
+        assert_eq!(registers[16], 3);
+        assert_eq!(registers[17], b'a' as u32);
+        assert_eq!(registers[18], b'e' as u32);
+        assert_eq!(registers[19], 0); // argv[argc]
+        assert_eq!(registers[29], registers[5]); // $sp at argv
+        assert_eq!(registers[29] % 8, 0);
+    }
+
+    #[test]
+    fn stores_are_visible_through_the_memory_slice()
+    {
+        let mut computer = test_computer("
+            li $s0, 0x2000
+            lui $t0, 0x1122
+            ori $t0, $t0, 0x3344
+            sw $t0, 0($s0)
+            sb $t0, 5($s0)
+            lw $t1, 8($s0)
+            done:
+            b done");
+        computer.memory_mut()[0x2008..0x200C].copy_from_slice(&[0xCA, 0xFE, 0xBA, 0xBE]);
+        computer.add_breakpoint_symbol("done").unwrap();
+
+        assert_eq!(computer.run(), HaltReason::Breakpoint(24));
+        let memory = computer.memory();
+        assert_eq!(memory.len(), 0x4000);
+        assert_eq!(memory[0x2000..0x2006], [0x11, 0x22, 0x33, 0x44, 0, 0x44]); // big endian
+        assert_eq!(computer.cpu.register(9), 0xCAFE_BABE);
+    }
+
+    #[test]
+    fn pixels_stored_by_the_guest_show_in_the_framebuffer()
+    {
+        // The VRAM of the test layout starts at 0x1000, the pixels after the header.
+        let mut computer = test_computer("
+            li $s0, 0x1000
+            lui $t0, 0xFF
+            sw $t0, 0x20($s0)
+            ori $t1, $zero, 0xFF00
+            sw $t1, 0x24($s0)
+            ori $t2, $zero, 0xFF
+            sw $t2, 0x3C($s0)
+            done:
+            b done");
+        computer.add_breakpoint_symbol("done").unwrap();
+        computer.run();
+
+        let pixels = computer.framebuffer();
+        assert_eq!(computer.framebuffer_size(), (8, 8));
+        assert_eq!(pixels[..3], [0x00FF_0000, 0x0000_FF00, 0]);
+        assert_eq!(pixels[7], 0x0000_00FF); // the end of the first row
+        assert!(pixels[8..].iter().all(|&pixel| pixel == 0));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn screenshot_is_a_ppm_of_the_framebuffer()
+    {
+        let mut computer = computer_with(&[]);
+        computer.bus.write_data(0x1020, 0x0012_3456, 4);
+
+        let path = std::env::temp_dir().join(format!("supersim-screenshot-{}.ppm", std::process::id()));
+        computer.save_screenshot(path.to_str().unwrap()).unwrap();
+        let image = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let header = b"P6\n8 8\n255\n";
+        assert_eq!(image[..header.len()], header[..]);
+        assert_eq!(image[header.len()..header.len() + 6], [0x12, 0x34, 0x56, 0, 0, 0]);
+        assert_eq!(image.len(), header.len() + 8 * 8 * 3);
+    }
+
+    #[test]
+    fn little_endian_image_runs_after_swapping()
+    {
+        let assembly = assemble("
+            li $t0, 40
+            addiu $t1, $t0, 2
+            sll $t2, $t1, 4", 0).unwrap();
+        let little_endian: Vec<u8> = assembly.words.iter().flat_map(|word| word.to_le_bytes()).collect();
+
+        let mut computer = test_computer("nop");
+        assert!(computer.load_program(&little_endian[..5], true).is_err());
+        computer.load_program(&little_endian, true).unwrap();
+        assert_eq!(computer.bus.ram.read_data(4, 4), assembly.words[1]);
+
+        computer.add_breakpoint(12);
+        assert_eq!(computer.run(), HaltReason::Breakpoint(12));
+        assert_eq!(computer.cpu.register(9), 42);
+        assert_eq!(computer.cpu.register(10), 42 << 4);
+    }
+
+    #[test]
+    fn run_to_stops_at_the_target_or_the_step_limit()
+    {
+        let source = "
+            addiu $t0, $zero, 1
+            addiu $t0, $t0, 1
+            addiu $t0, $t0, 1
+            addiu $t0, $t0, 1";
+
+        let mut computer = test_computer(source);
+        assert_eq!(computer.run_to(12, 10), HaltReason::Reached(12));
+        assert_eq!(computer.cpu.pc(), 12);
+        assert_eq!(computer.cpu.register(8), 3); // the target itself hasn't run
+
+        // Already there, nothing runs.
+        assert_eq!(computer.run_to(12, 10), HaltReason::Reached(12));
+        assert_eq!(computer.cpu.register(8), 3);
+
+        let mut computer = test_computer(source);
+        assert_eq!(computer.run_to(12, 2), HaltReason::StepLimit);
+        assert_eq!(computer.cpu.pc(), 8);
+    }
+
+    #[test]
+    fn delay_slots_run_before_the_jump_target()
+    {
+        let mut computer = test_computer("
+            beq $zero, $zero, target
+            addiu $t0, $zero, 1
+            addiu $t1, $zero, 2
+            target:
+            jal func
+            addiu $t2, $zero, 3
+            done:
+            b done
+            nop
+            func:
+            jr $ra
+            addiu $t3, $zero, 4");
+        computer.set_delay_slots(true);
+        computer.add_breakpoint_symbol("done").unwrap();
+
+        assert_eq!(computer.run(), HaltReason::Breakpoint(20));
+        let registers = computer.cpu.registers();
+        assert_eq!(registers[8..12], [1, 0, 3, 4]); // the slots ran, the skipped instruction didn't
+        assert_eq!(registers[31], 20); // jal returns past its delay slot
+    }
+
+    #[test]
+    fn watched_register_stops_at_the_instruction_writing_it()
+    {
+        let mut computer = test_computer("
+            li $t1, 5
+            li $t0, 7
+            li $t2, 1");
+        computer.watch_register(8);
+
+        let reason = computer.run();
+        assert_eq!(reason, HaltReason::RegisterChanged { register: 8, old: 0, new: 7, pc: 4 });
+        assert_eq!(computer.cpu.register(10), 0); // stopped before the next instruction
+    }
+
+    #[test]
+    fn image_round_trips_into_the_memory()
+    {
+        let program = assemble("
+            li $t0, 0x2000
+            lw $t1, 4($t0)", 0x100).unwrap();
+        let image = Image
+        {
+            program_base: 0x100,
+            program: program.to_bytes(),
+            data_base: 0x2000,
+            data: vec![0, 0, 0, 1, 0xCA, 0xFE, 0xBA, 0xBE],
+            entry: 0x100,
+        };
+        let bytes = image.to_bytes();
+        assert_eq!(Image::parse(&bytes), Ok(image));
+
+        let mut computer = test_computer("");
+        computer.load_image_bytes(&bytes).unwrap();
+        assert_eq!(computer.cpu.pc(), 0x100);
+
+        computer.add_breakpoint(0x108);
+        assert_eq!(computer.run(), HaltReason::Breakpoint(0x108));
+        assert_eq!(computer.cpu.register(9), 0xCAFE_BABE);
+    }
+
+    #[test]
+    fn call_saves_ra_on_the_default_stack()
+    {
+        let mut computer = test_computer("
+            jal func
+            li $s0, 1
+            j end
+            func:
+            addi $sp, $sp, -8
+            sw $ra, 4($sp)
+            li $ra, 0
+            lw $ra, 4($sp)
+            addi $sp, $sp, 8
+            jr $ra
+            end:
+            b end");
+        let stack_top = computer.cpu.register(29);
+        assert_eq!(stack_top, computer.data.end & !0b111);
+        computer.add_breakpoint_symbol("end").unwrap();
+
+        assert_eq!(computer.run(), HaltReason::Breakpoint(0x24));
+        assert_eq!(computer.cpu.register(16), 1); // returned to the caller
+        assert_eq!(computer.cpu.register(29), stack_top);
+        assert_eq!(computer.bus.read_data(stack_top - 4, 4), 4); // the saved $ra
+    }
+
+    #[test]
+    fn interrupt_priority_must_list_every_line_once()
+    {
+        let mut computer = computer_with(&[]);
+
+        assert!(computer.set_interrupt_priority([0, 1, 2, 3, 4, 5, 6, 6]).is_err());
+        assert!(computer.set_interrupt_priority([0, 1, 2, 3, 4, 5, 6, 8]).is_err());
+        assert!(computer.set_interrupt_priority([0, 1, 2, 3, 4, 5, 6, 7]).is_ok());
+    }
+
+    #[test]
+    fn host_syscall_handler_counts_the_syscalls()
+    {
+        use alloc::rc::Rc;
+        use core::cell::Cell;
+
+        let count = Rc::new(Cell::new(0));
+        let counter = count.clone();
+        let mut computer = test_computer("
+            syscall
+            syscall
+            li $t0, 1
+            syscall");
+        computer.on_exception(8, Box::new(move |computer|
+        {
+            counter.set(counter.get() + 1);
+            computer.cpu.set_register(2, counter.get()); // seen by the guest in $v0
+        })).unwrap();
+
+        computer.run_for(4);
+        assert_eq!(count.get(), 3);
+        assert_eq!(computer.registers()[2], 3);
+        assert_eq!(computer.registers()[8], 1); // the guest went on after each syscall
+        assert_eq!(computer.pc(), 16); // the guest vector was never entered
+    }
+
+    #[test]
+    fn exception_codes_past_31_are_rejected()
+    {
+        let mut computer = computer_with(&[]);
+        assert!(computer.on_exception(32, Box::new(|_| {})).is_err());
+        assert!(computer.set_register(32, 1).is_err());
+        computer.remove_exception_handler(40); // nothing to remove
+    }
+
+    #[test]
+    fn in_memory_program_runs_until_it_halts()
+    {
+        // No file is involved, the way a fuzz target would feed the machine.
+        let program = assemble("
+            li $t0, 42
+            lui $t1, 2
+            sw $t0, 0($t1)
+            li $t0, 1
+            done: b done", 0).unwrap();
+        let layout = MemoryLayout::standard(256 * 1024, 16, 16).unwrap();
+        let mut computer = Computer::with_program(&program.to_bytes(), 256 * 1024, 16, 16, layout).unwrap();
+        computer.add_breakpoint(16);
+
+        assert_eq!(computer.pc(), 0);
+        assert_eq!(computer.run_for(100), HaltReason::Breakpoint(16));
+        assert_eq!(computer.bus.read_data(0x20000, 4), 42);
+        assert_eq!(computer.registers()[8], 1);
+    }
+
+    #[test]
+    fn unaligned_program_base_is_an_error()
+    {
+        let mut layout = MemoryLayout::standard(256 * 1024, 16, 16).unwrap();
+        layout.program.start = 2;
+
+        assert!(Computer::new_headless(256 * 1024, 16, 16, layout.clone()).is_err());
+        assert!(Computer::with_program(&[], 256 * 1024, 16, 16, layout).is_err());
+
+        let mut computer = computer_with(&[]);
+        assert!(computer.set_pc(6).is_err());
+        assert_eq!(computer.pc(), 0);
+        computer.set_pc(8).unwrap();
+        assert_eq!(computer.pc(), 8);
+    }
+
+    #[test]
+    fn injected_words_run_one_at_a_time()
+    {
+        let mut computer = test_computer("");
+        computer.set_register(9, 40).unwrap();
+        computer.set_register(10, 2).unwrap();
+
+        assert_eq!(computer.execute_instruction(0x012A4020), None); // add $t0, $t1, $t2
+        assert_eq!(computer.registers()[8], 42);
+        assert_eq!(computer.pc(), 4);
+
+        assert_eq!(computer.execute_instruction(0x012A5822), None); // sub $t3, $t1, $t2
+        assert_eq!(computer.registers()[11], 38);
+        assert_eq!(computer.pc(), 8);
+    }
+
+    #[test]
+    fn hilo_holds_the_full_signed_product()
+    {
+        let mut computer = test_computer("
+            li $t0, -123456
+            li $t1, 987654
+            mult $t0, $t1");
+        computer.run_for(5);
+        assert_eq!(computer.hilo(), (-123456_i64 * 987654) as u64);
+
+        computer.set_hilo(0x0123_4567_89AB_CDEF);
+        assert_eq!(computer.hilo(), 0x0123_4567_89AB_CDEF);
+    }
+
+    #[test]
+    fn elf_runs_from_the_biased_entry()
+    {
+        // Position independent: the branch is relative, so the code runs wherever it is placed.
+        let code = assemble("
+            li $t0, 7
+            b skip
+            li $t0, 1
+        skip:
+            li $t1, 9", 0x100).unwrap().to_bytes();
+        let elf = crate::computer::elf::test_elf(&code, 0x100, 0x104);
+
+        let mut computer = test_computer("");
+        assert_eq!(computer.load_elf(&elf, 0x2000), Ok(0x2104));
+        assert_eq!(computer.bus.read_data(0x100, 4), 0); // nothing at the unbiased address
+
+        assert_eq!(computer.run_for(2), HaltReason::StepLimit);
+        assert_eq!(computer.cpu.instruction_address(), 0x210C);
+        assert_eq!(computer.registers()[8], 0); // the entry skips the first instruction
+        assert_eq!(computer.registers()[9], 9);
+    }
+
+    #[test]
+    fn breakpoints_and_watchpoints_are_listed_and_cleared()
+    {
+        let mut computer = test_computer("nop");
+        for address in [0x40, 0x8, 0x20, 0x8]
+        {
+            computer.add_breakpoint(address);
+        }
+        computer.watch_register(9);
+        computer.watch_register(2);
+
+        assert_eq!(computer.breakpoints(), vec![0x8, 0x20, 0x40]); // sorted, without duplicates
+        assert_eq!(computer.watchpoints(), vec![2, 9]);
+
+        computer.clear_all_breakpoints();
+        computer.clear_all_watchpoints();
+        assert!(computer.breakpoints().is_empty());
+        assert!(computer.watchpoints().is_empty());
+    }
+
+    #[test]
+    fn exceptions_in_a_row_past_the_limit_are_an_exception_storm()
+    {
+        // Without a handler in memory each run takes a single exception.
+        for (limit, storm) in [(Some(0), true), (Some(1), false), (None, false)]
+        {
+            let mut computer = test_computer("
+                nop
+                teq $zero, $zero");
+            computer.set_exception_storm_limit(limit);
+
+            assert_eq!(computer.run() == HaltReason::ExceptionStorm, storm, "{:?}", limit);
+            assert_eq!(computer.cpu.exceptions_taken(), 1);
+        }
+    }
+
+    #[test]
+    fn return_through_an_unset_ra_trips_the_jump_to_zero_guard()
+    {
+        let source = "
+            li $t0, 1
+            jr $ra";
+
+        // Off by default, the program just starts over.
+        let mut computer = test_computer(source);
+        assert_eq!(computer.run_for(4), HaltReason::StepLimit);
+
+        let mut computer = test_computer(source);
+        computer.set_jump_to_zero_guard(true);
+        match computer.run_for(4)
+        {
+            HaltReason::Fault(fault) =>
+            {
+                assert_eq!(fault.kind, FaultKind::JumpToZero);
+                assert_eq!(fault.pc, 0x4); // the jr, not the address it went to
+            },
+            reason => panic!("jr $ra to 0 wasn't caught: {:?}", reason),
+        }
+    }
+
+    #[test]
+    fn tick_once_shows_the_memory_request_of_a_load()
+    {
+        let mut computer = test_computer("
+            li $t0, 0x2000
+            lw $t1, 4($t0)");
+        computer.bus.write_data(0x2004, 0xCAFEF00D, 4);
+        for _ in 0..4
+        {
+            computer.tick_once();
+        }
+
+        let load = |address, register| Some(MemoryRequest { address, data: 0, size: 4, store: false, register });
+        let requests: Vec<_> = (0..4).map(|_| computer.tick_once().request).collect();
+        assert_eq!(requests, [
+            load(0x4, 0), // the instruction word
+            load(0x2004, 9), // the data word, into $t1
+            None,
+            None,
+        ]);
+        assert_eq!(computer.registers()[9], 0xCAFEF00D); // the four ticks retired the lw
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn loaded_handler_runs_on_syscall_and_returns()
+    {
+        let handler = assemble("
+            addiu $s0, $s0, 1
+            mfc0 $k0, $14
+            addiu $k0, $k0, 4
+            mtc0 $k0, $14
+            eret", 0x3000).unwrap();
+        let path = std::env::temp_dir().join(format!("supersim-handler-{}.bin", std::process::id()));
+        fs::write(&path, handler.to_bytes()).unwrap();
+
+        let mut computer = test_computer("
+            syscall
+            addiu $s1, $s1, 1");
+        let loaded = computer.load_handler(path.to_str().unwrap(), 0x3000);
+        fs::remove_file(&path).unwrap();
+        loaded.unwrap();
+
+        assert_eq!(computer.run_for(7), HaltReason::StepLimit);
+        assert_eq!(computer.registers()[16], 1); // the handler ran once
+        assert_eq!(computer.registers()[17], 1); // and returned past the syscall
+        assert_eq!(computer.pc(), 8);
+    }
+
+    #[test]
+    fn handler_base_must_be_word_aligned_and_in_memory()
+    {
+        let mut computer = test_computer("nop");
+        assert!(computer.load_handler_bytes(&[0; 4], 0x3002).is_err());
+        assert!(computer.load_handler_bytes(&[0; 8], 0x4000 - 4).is_err());
+        assert!(computer.set_exception_vector(0x3001).is_err());
+    }
+
+    #[test]
+    fn handler_that_faults_again_is_an_exception_storm()
+    {
+        // The handler traps as its first instruction, so no instruction ever retires normally.
+        let handler = assemble("teq $zero, $zero", 0x3000).unwrap();
+        let mut computer = test_computer("teq $zero, $zero");
+        computer.load_handler_bytes(&handler.to_bytes(), 0x3000).unwrap();
+        computer.set_exception_storm_limit(Some(5));
+
+        assert_eq!(computer.run(), HaltReason::ExceptionStorm);
+        assert_eq!(computer.cpu.exceptions_taken(), 6);
+    }
 }
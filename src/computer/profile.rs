@@ -0,0 +1,95 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::computer::Computer;
+use crate::disassembler::disassemble;
+
+// One line of the hot spot report.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfileEntry
+{
+    pub address: u32,
+    pub count: u64, // times the instruction was run
+    pub text: String, // its disassembly
+}
+
+impl Computer
+{
+    // Starts counting the runs of every instruction address from zero, or stops it.
+    pub fn set_profiling(&mut self, enabled: bool)
+    {
+        self.profile = match enabled
+        {
+            true => Some(BTreeMap::new()),
+            false => None,
+        };
+    }
+
+    pub(super) fn profile_instruction(&mut self)
+    {
+        if let Some(profile) = &mut self.profile
+        {
+            *profile.entry(self.cpu.instruction_address()).or_insert(0) += 1;
+        }
+    }
+
+    // The top_n most run instructions, most run first, empty without profiling.
+    pub fn profile_report(&self, top_n: usize) -> Vec<ProfileEntry>
+    {
+        let Some(profile) = &self.profile else
+        {
+            return Vec::new();
+        };
+
+        let mut hot_spots: Vec<(u32, u64)> = profile.iter()
+            .map(|(&address, &count)| (address, count))
+            .collect();
+        // The sort is stable, equal counts stay in address order.
+        hot_spots.sort_by(|(_, count), (_, other)| other.cmp(count));
+        hot_spots.truncate(top_n);
+
+        hot_spots.into_iter()
+            .map(|(address, count)|
+            {
+                let instruction = match self.bus.contains(address, 4)
+                {
+                    true => self.bus.read_data(address, 4),
+                    false => 0,
+                };
+                ProfileEntry
+                {
+                    address,
+                    count,
+                    text: disassemble(instruction, address, self.symbols.disasm_options()),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use crate::computer::test_computer;
+
+    #[test]
+    fn loop_body_tops_the_report()
+    {
+        let mut computer = test_computer("
+            li $t0, 10
+        loop:
+            addi $t0, $t0, -1
+            bne $t0, $zero, loop
+            nop");
+        computer.set_profiling(true);
+        computer.run_for(22);
+
+        let report = computer.profile_report(3);
+        let hot_spots: Vec<(u32, u64)> = report.iter().map(|entry| (entry.address, entry.count)).collect();
+        assert_eq!(hot_spots, vec![(4, 10), (8, 10), (0, 1)]);
+        assert!(report[0].text.starts_with("addi"), "{}", report[0].text);
+        assert!(report[1].text.starts_with("bne"), "{}", report[1].text);
+    }
+}
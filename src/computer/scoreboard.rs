@@ -0,0 +1,254 @@
+use alloc::vec::Vec;
+use crate::computer::Computer;
+
+/*
+    An observational scoreboard of a classic five stage pipeline with forwarding, for teaching.
+    Execution is unchanged, the scoreboard only replays the retired instructions and reports
+    where such a pipeline would stall:
+        a loaded register can't be used by the next instruction (load-use, one cycle),
+        HI and LO can't be read until the multiply/divide unit is done (see set_hilo_latency),
+        the multiply/divide unit takes one operation at a time.
+ */
+const HI_LO: u8 = 32; // HI and LO are tracked as one register
+const LOAD_USE_CYCLES: u64 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hazard
+{
+    Data(u8), // waiting for the register, 32 for HI/LO
+    Structural, // the multiply/divide unit is busy
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stall
+{
+    pub pc: u32, // the instruction that waited
+    pub hazard: Hazard,
+    pub cycles: u64,
+}
+
+// A register whose new value isn't available yet.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InFlight
+{
+    pub register: u8, // 32 for HI/LO
+    pub producer: u32, // address of the instruction writing it
+    pub cycles_left: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scoreboard
+{
+    pub cycle: u64, // issue cycle of the next instruction
+    pub stalls: Vec<Stall>, // every stall since the scoreboard was enabled
+    ready_at: [u64; 33], // cycle each register can be read in
+    producers: [u32; 33],
+    unit_free_at: u64, // cycle the multiply/divide unit takes a new operation in
+}
+
+// The registers an instruction reads and writes, as far as the scoreboard cares.
+#[derive(Default)]
+struct RegisterUse
+{
+    sources: [Option<u8>; 2],
+    destination: Option<u8>,
+    load: bool,
+    multiply_divide: bool,
+}
+
+impl Scoreboard
+{
+    fn new() -> Scoreboard
+    {
+        Scoreboard
+        {
+            cycle: 0,
+            stalls: Vec::new(),
+            ready_at: [0; 33],
+            producers: [0; 33],
+            unit_free_at: 0,
+        }
+    }
+
+    // The registers still being produced when the next instruction issues.
+    pub fn in_flight(&self) -> Vec<InFlight>
+    {
+        (0..33u8)
+            .filter(|&register| self.ready_at[register as usize] > self.cycle)
+            .map(|register| InFlight
+            {
+                register,
+                producer: self.producers[register as usize],
+                cycles_left: self.ready_at[register as usize] - self.cycle,
+            })
+            .collect()
+    }
+
+    fn issue(&mut self, pc: u32, instruction: u32, hilo_latency: u64)
+    {
+        let usage = register_use(instruction);
+
+        let data_stall = usage.sources.iter().flatten()
+            .filter(|&&register| register != 0)
+            .map(|&register| (register, self.ready_at[register as usize].saturating_sub(self.cycle)))
+            .max_by_key(|&(_, cycles)| cycles)
+            .filter(|&(_, cycles)| cycles > 0);
+        if let Some((register, cycles)) = data_stall
+        {
+            self.stalls.push(Stall { pc, hazard: Hazard::Data(register), cycles });
+            self.cycle += cycles;
+        }
+
+        if usage.multiply_divide && self.unit_free_at > self.cycle
+        {
+            let cycles = self.unit_free_at - self.cycle;
+            self.stalls.push(Stall { pc, hazard: Hazard::Structural, cycles });
+            self.cycle += cycles;
+        }
+
+        if let Some(register) = usage.destination.filter(|&register| register != 0)
+        {
+            let latency = match (usage.load, register)
+            {
+                (true, _) => LOAD_USE_CYCLES,
+                (false, HI_LO) => hilo_latency,
+                (false, _) => 0, // forwarded
+            };
+            self.ready_at[register as usize] = self.cycle + 1 + latency;
+            self.producers[register as usize] = pc;
+        }
+        if usage.multiply_divide
+        {
+            self.unit_free_at = self.cycle + 1 + hilo_latency;
+        }
+
+        self.cycle += 1;
+    }
+}
+
+fn register_use(instruction: u32) -> RegisterUse
+{
+    let opcode = instruction >> 26;
+    let rs = ((instruction >> 21) & 0b11111) as u8;
+    let rt = ((instruction >> 16) & 0b11111) as u8;
+    let rd = ((instruction >> 11) & 0b11111) as u8;
+    let funct = instruction & 0b111111;
+
+    let uses = |sources: [Option<u8>; 2], destination: Option<u8>| RegisterUse
+    {
+        sources,
+        destination,
+        ..RegisterUse::default()
+    };
+
+    match (opcode, funct)
+    {
+        (0, 0 | 2 | 3) => uses([Some(rt), None], Some(rd)),
+        (0, 8) => uses([Some(rs), None], None), // jr
+        (0, 9) => uses([Some(rs), None], Some(rd)), // jalr
+        (0, 16 | 18) => uses([Some(HI_LO), None], Some(rd)), // mfhi, mflo
+        (0, 17 | 19) => uses([Some(rs), None], Some(HI_LO)), // mthi, mtlo
+        (0, 24..=27) => RegisterUse
+        {
+            multiply_divide: true,
+            ..uses([Some(rs), Some(rt)], Some(HI_LO))
+        },
+        (0, 4 | 6 | 7 | 32..=43) => uses([Some(rs), Some(rt)], Some(rd)),
+        (0, 0x30..=0x36) => uses([Some(rs), Some(rt)], None), // traps
+        (1, _) | (6 | 7, _) => uses([Some(rs), None], None),
+        (3, _) => uses([None, None], Some(31)), // jal
+        (4 | 5, _) => uses([Some(rs), Some(rt)], None),
+        (8..=14, _) => uses([Some(rs), None], Some(rt)),
+        (15, _) => uses([None, None], Some(rt)), // lui
+        (0x10 | 0x11, _) if rs == 0 => uses([None, None], Some(rt)), // mfc0, mfc1
+        (0x10 | 0x11, _) if rs == 4 => uses([Some(rt), None], None), // mtc0, mtc1
+        (0x1C, 0x02) => uses([Some(rs), Some(rt)], Some(rd)), // mul
+        (0x1C, 0x20 | 0x21) => uses([Some(rs), None], Some(rd)), // clz, clo
+        (32 | 33 | 35 | 36 | 37 | 0x30, _) => RegisterUse
+        {
+            load: true,
+            ..uses([Some(rs), None], Some(rt))
+        },
+        (34 | 38, _) => RegisterUse // lwl, lwr merge into rt
+        {
+            load: true,
+            ..uses([Some(rs), Some(rt)], Some(rt))
+        },
+        (40 | 41 | 43, _) => uses([Some(rs), Some(rt)], None),
+        (0x38, _) => uses([Some(rs), Some(rt)], Some(rt)), // sc
+        (0x31 | 0x35 | 0x39 | 0x3D, _) => uses([Some(rs), None], None), // FP loads and stores
+        _ => RegisterUse::default(),
+    }
+}
+
+impl Computer
+{
+    // Starts the scoreboard from an empty pipeline, or stops it.
+    pub fn set_scoreboard(&mut self, enabled: bool)
+    {
+        self.scoreboard = match enabled
+        {
+            true => Some(Scoreboard::new()),
+            false => None,
+        };
+    }
+
+    // None unless set_scoreboard enabled it.
+    pub fn scoreboard(&self) -> Option<&Scoreboard>
+    {
+        self.scoreboard.as_ref()
+    }
+
+    pub(super) fn score_instruction(&mut self)
+    {
+        let Some(scoreboard) = &mut self.scoreboard else
+        {
+            return;
+        };
+
+        let pc = self.cpu.instruction_address();
+        if self.bus.contains(pc, 4)
+        {
+            scoreboard.issue(pc, self.bus.read_data(pc, 4), self.cpu.hilo_latency() as u64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use alloc::vec;
+    use crate::computer::test_computer;
+    use super::{Hazard, InFlight, Stall};
+
+    #[test]
+    fn dependent_instructions_stall_the_scoreboard()
+    {
+        let mut computer = test_computer("
+            li $t0, 0x2000
+            lw $t1, 0($t0)
+            addu $t2, $t1, $t1
+            mult $t2, $t2
+            mult $t0, $t0
+            mfhi $t3");
+        computer.set_hilo_latency(3);
+        computer.set_scoreboard(true);
+
+        computer.run_for(2);
+        let scoreboard = computer.scoreboard().unwrap();
+        assert_eq!(scoreboard.in_flight(), vec![InFlight { register: 9, producer: 0x4, cycles_left: 1 }]);
+
+        computer.run_for(4);
+        let scoreboard = computer.scoreboard().unwrap();
+        assert_eq!(scoreboard.stalls, vec![
+            Stall { pc: 0x8, hazard: Hazard::Data(9), cycles: 1 }, // load-use on $t1
+            Stall { pc: 0x10, hazard: Hazard::Structural, cycles: 3 }, // the first mult is running
+            Stall { pc: 0x14, hazard: Hazard::Data(32), cycles: 3 }, // HI waits for the second
+        ]);
+        assert_eq!(scoreboard.cycle, 13); // six instructions and seven stall cycles
+
+        // Execution itself is unchanged, HI:LO holds 0x2000 squared.
+        assert_eq!(computer.registers()[11], 0);
+        assert_eq!(computer.cpu.hilo(), 0x400_0000);
+    }
+}
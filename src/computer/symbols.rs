@@ -0,0 +1,109 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use crate::computer::elf::ElfReader;
+use crate::disassembler::{symbol_at, DisasmOptions};
+
+const SHT_SYMTAB: u32 = 2;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+pub(super) struct SymbolTable
+{
+    symbols: BTreeMap<u32, String>,
+}
+
+impl SymbolTable
+{
+    pub(super) fn new() -> SymbolTable
+    {
+        SymbolTable
+        {
+            symbols: BTreeMap::new(),
+        }
+    }
+
+    /*
+        Reads the function and object symbols from the .symtab section of an ELF32 file.
+        https://refspecs.linuxfoundation.org/elf/gabi4+/ch4.symtab.html
+     */
+    pub(super) fn from_elf(elf: &[u8]) -> Result<SymbolTable, String>
+    {
+        let reader = ElfReader::new(elf)?;
+
+        let section_headers_offset = reader.word(0x20)? as usize;
+        let section_header_size = reader.halfword(0x2E)? as usize;
+        let sections_count = reader.halfword(0x30)? as usize;
+
+        let section_header = |index: usize| section_headers_offset + index * section_header_size;
+
+        let symtab = (0..sections_count)
+            .map(section_header)
+            .find(|&header| reader.word(header + 0x04) == Ok(SHT_SYMTAB))
+            .ok_or("No .symtab section")?;
+
+        let symtab_offset = reader.word(symtab + 0x10)? as usize;
+        let symtab_size = reader.word(symtab + 0x14)? as usize;
+        let strtab_index = reader.word(symtab + 0x18)? as usize;
+        let entry_size = reader.word(symtab + 0x24)? as usize;
+        if entry_size == 0
+        {
+            return Err("Bad .symtab entry size".to_string());
+        }
+
+        let strtab_offset = reader.word(section_header(strtab_index) + 0x10)? as usize;
+
+        let mut table = SymbolTable::new();
+        for entry in (symtab_offset..symtab_offset + symtab_size).step_by(entry_size)
+        {
+            let name_offset = reader.word(entry)? as usize;
+            let value = reader.word(entry + 0x04)?;
+            let symbol_type = reader.byte(entry + 0x0C)? & 0xF;
+
+            if symbol_type != STT_FUNC && symbol_type != STT_OBJECT
+            {
+                continue;
+            }
+
+            let name = reader.string(strtab_offset + name_offset)?;
+            if !name.is_empty()
+            {
+                table.insert(value, name);
+            }
+        }
+
+        Ok(table)
+    }
+
+    pub(super) fn insert(&mut self, address: u32, name: String)
+    {
+        self.symbols.insert(address, name);
+    }
+
+    pub(super) fn address_of(&self, name: &str) -> Option<u32>
+    {
+        self.symbols.iter()
+            .find(|(_, symbol)| symbol.as_str() == name)
+            .map(|(&address, _)| address)
+    }
+
+    pub(super) fn symbol(&self, address: u32) -> Option<String>
+    {
+        symbol_at(&self.symbols, address)
+    }
+
+    // Formats the address as "symbol+0xoffset", falling back to the bare address.
+    pub(super) fn symbolize(&self, address: u32) -> String
+    {
+        self.symbol(address).unwrap_or_else(|| format!("0x{:08X}", address))
+    }
+
+    pub(super) fn disasm_options(&self) -> DisasmOptions<'_>
+    {
+        DisasmOptions
+        {
+            symbols: Some(&self.symbols),
+            ..DisasmOptions::default()
+        }
+    }
+}
@@ -0,0 +1,680 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use crate::computer::Computer;
+use crate::computer::fault::FaultKind;
+
+/*
+    Host implementation of the MARS syscalls.
+    https://courses.missouristate.edu/kenvollmar/mars/help/syscallhelp.html
+ */
+const V0: u8 = 2;
+const A0: u8 = 4;
+const A1: u8 = 5;
+const A2: u8 = 6;
+
+const PRINT_INT: u32 = 1;
+const PRINT_STRING: u32 = 4;
+const READ_INT: u32 = 5;
+const READ_STRING: u32 = 8;
+const SBRK: u32 = 9;
+const EXIT: u32 = 10;
+const PRINT_CHAR: u32 = 11;
+const READ_CHAR: u32 = 12;
+const OPEN_FILE: u32 = 13;
+const READ_FILE: u32 = 14;
+const WRITE_FILE: u32 = 15;
+const CLOSE_FILE: u32 = 16;
+const SET_SEED: u32 = 40; // the generator id in $a0 is ignored, there is one generator
+const RANDOM_INT: u32 = 41;
+const RANDOM_INT_RANGE: u32 = 42; // below the bound in $a1
+const EXIT2: u32 = 17; // exit with the code in $a0
+
+const STDIN: u32 = 0;
+const STDOUT: u32 = 1;
+const STDERR: u32 = 2;
+const FIRST_FILE_DESCRIPTOR: u32 = 3;
+
+// open flags
+const READ_ONLY: u32 = 0;
+const WRITE_ONLY: u32 = 1;
+const WRITE_APPEND: u32 = 9;
+
+const DEFAULT_STACK_SIZE: u32 = 64 * 1024; // kept free for the stack at the top of the data region
+
+pub(super) struct SyscallService
+{
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    files: HashMap<u32, File>,
+    next_file_descriptor: u32,
+    sandbox: Option<PathBuf>,
+    heap_pointer: u32, // the heap grows up from the start of the data region, past loaded data
+    heap_limit: u32, // where the stack may grow down to, see Computer::set_stack_limit
+    random: u64, // state of the generator behind the random syscalls, see Computer::set_seed
+    exit_code: Option<i32>, // set once the guest exits
+}
+
+impl SyscallService
+{
+    pub(super) fn new(data: Range<u32>) -> SyscallService
+    {
+        SyscallService
+        {
+            input: Box::new(BufReader::new(io::stdin())),
+            output: Box::new(io::stdout()),
+            files: HashMap::new(),
+            next_file_descriptor: FIRST_FILE_DESCRIPTOR,
+            sandbox: None,
+            heap_pointer: data.start,
+            heap_limit: data.end.saturating_sub(DEFAULT_STACK_SIZE).max(data.start),
+            random: 0,
+            exit_code: None,
+        }
+    }
+
+    // Moves the heap past data placed at the start of the data region, keeping it word aligned.
+    pub(super) fn reserve_heap(&mut self, end: u32)
+    {
+        let end = end.next_multiple_of(4);
+        self.heap_pointer = self.heap_pointer.max(end);
+    }
+
+    pub(super) fn set_heap_limit(&mut self, limit: u32)
+    {
+        self.heap_limit = limit;
+    }
+
+    pub(super) fn seed_random(&mut self, seed: u64)
+    {
+        self.random = seed;
+    }
+
+    // The next value of a splitmix64 generator, https://prng.di.unimi.it/splitmix64.c
+    fn random(&mut self) -> u32
+    {
+        self.random = self.random.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.random;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        ((z ^ (z >> 31)) >> 32) as u32
+    }
+
+    pub(super) fn take_exit_code(&mut self) -> Option<i32>
+    {
+        self.exit_code.take()
+    }
+
+    // Returns the start of the newly allocated block, or -1 if the heap would run into the stack.
+    fn sbrk(&mut self, size: i32) -> i32
+    {
+        let Ok(size) = u32::try_from(size) else
+        {
+            return -1;
+        };
+
+        let block = self.heap_pointer;
+        let new_pointer = block.checked_add(size)
+            .and_then(|end| end.checked_next_multiple_of(4)); // keep the next block word aligned
+
+        match new_pointer
+        {
+            Some(end) if end <= self.heap_limit =>
+            {
+                self.heap_pointer = end;
+                block as i32
+            },
+            _ => -1,
+        }
+    }
+
+    pub(super) fn set_input(&mut self, input: Box<dyn BufRead>)
+    {
+        self.input = input;
+    }
+
+    pub(super) fn set_output(&mut self, output: Box<dyn Write>)
+    {
+        self.output = output;
+    }
+
+    pub(super) fn set_sandbox(&mut self, root: Option<PathBuf>)
+    {
+        self.sandbox = root;
+    }
+
+    // With a sandbox only relative paths that stay inside its directory are allowed.
+    fn resolve_path(&self, path: &str) -> Option<PathBuf>
+    {
+        let Some(root) = &self.sandbox else
+        {
+            return Some(PathBuf::from(path));
+        };
+
+        let path = Path::new(path);
+        let escapes = path.components()
+            .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir));
+
+        match escapes
+        {
+            true => None,
+            false => Some(root.join(path)),
+        }
+    }
+
+    fn open_file(&mut self, path: &str, flags: u32) -> i32
+    {
+        let Some(path) = self.resolve_path(path) else
+        {
+            return -1;
+        };
+
+        let mut options = OpenOptions::new();
+        match flags
+        {
+            READ_ONLY => options.read(true),
+            WRITE_ONLY => options.write(true).create(true).truncate(true),
+            WRITE_APPEND => options.append(true).create(true),
+            _ => return -1,
+        };
+
+        match options.open(path)
+        {
+            Ok(file) =>
+            {
+                let file_descriptor = self.next_file_descriptor;
+                self.next_file_descriptor += 1;
+                self.files.insert(file_descriptor, file);
+                file_descriptor as i32
+            },
+            Err(_) => -1,
+        }
+    }
+
+    fn read_file(&mut self, file_descriptor: u32, length: usize) -> Result<Vec<u8>, ()>
+    {
+        let mut buffer = vec![0; length];
+        let count = match file_descriptor
+        {
+            STDIN => self.input.read(&mut buffer),
+            _ => self.files.get_mut(&file_descriptor).ok_or(())?.read(&mut buffer),
+        }.map_err(|_| ())?;
+
+        buffer.truncate(count);
+        Ok(buffer)
+    }
+
+    fn write_file(&mut self, file_descriptor: u32, bytes: &[u8]) -> i32
+    {
+        let result = match file_descriptor
+        {
+            STDOUT | STDERR => self.output.write_all(bytes).and_then(|_| self.output.flush()),
+            _ => match self.files.get_mut(&file_descriptor)
+            {
+                Some(file) => file.write_all(bytes),
+                None => return -1,
+            },
+        };
+
+        match result
+        {
+            Ok(()) => bytes.len() as i32,
+            Err(_) => -1,
+        }
+    }
+
+    fn close_file(&mut self, file_descriptor: u32)
+    {
+        self.files.remove(&file_descriptor);
+    }
+
+    pub(super) fn print(&mut self, bytes: &[u8])
+    {
+        // The guest can't do anything about a failing host output, so errors are dropped.
+        let _ = self.output.write_all(bytes).and_then(|_| self.output.flush());
+    }
+
+    fn read_line(&mut self) -> String
+    {
+        let mut line = String::new();
+        self.input.read_line(&mut line).unwrap_or(0);
+        line
+    }
+
+    fn read_int(&mut self) -> i32
+    {
+        self.read_line().trim().parse().unwrap_or(0)
+    }
+
+    fn read_char(&mut self) -> u8
+    {
+        let byte = match self.input.fill_buf()
+        {
+            Ok(buffer) if !buffer.is_empty() => buffer[0],
+            _ => return 0,
+        };
+        self.input.consume(1);
+        byte
+    }
+}
+
+impl Computer
+{
+    // A buffer of read_string that doesn't fit in the RAM faults like a bad store.
+    pub(super) fn service_syscall(&mut self) -> Result<(), FaultKind>
+    {
+        // A code in the instruction selects the service, $v0 does otherwise.
+        let code = match self.cpu.syscall_code()
+        {
+            0 => self.cpu.register(V0),
+            code => code,
+        };
+        let a0 = self.cpu.register(A0);
+        let a1 = self.cpu.register(A1);
+        let a2 = self.cpu.register(A2);
+
+        match code
+        {
+            PRINT_INT => self.syscalls.print((a0 as i32).to_string().as_bytes()),
+            PRINT_STRING =>
+            {
+                let string = self.read_guest_string(a0);
+                self.syscalls.print(&string);
+            },
+            PRINT_CHAR => self.syscalls.print(&[a0 as u8]),
+            READ_INT =>
+            {
+                let value = self.syscalls.read_int();
+                self.cpu.set_register(V0, value as u32);
+            },
+            READ_STRING => self.read_string(a0, a1)?,
+            SBRK =>
+            {
+                let block = self.syscalls.sbrk(a0 as i32);
+                self.cpu.set_register(V0, block as u32);
+            },
+            READ_CHAR =>
+            {
+                let value = self.syscalls.read_char();
+                self.cpu.set_register(V0, value as u32);
+            },
+            OPEN_FILE =>
+            {
+                let path = String::from_utf8_lossy(&self.read_guest_string(a0)).into_owned();
+                let file_descriptor = self.syscalls.open_file(&path, a1);
+                self.cpu.set_register(V0, file_descriptor as u32);
+            },
+            READ_FILE =>
+            {
+                let result = match self.fits_in_ram(a1, a2)
+                {
+                    true => match self.syscalls.read_file(a0, a2 as usize)
+                    {
+                        Ok(bytes) =>
+                        {
+                            for (address, &byte) in (a1..).zip(bytes.iter())
+                            {
+                                self.bus.ram.write_data(address, byte as u32, 1);
+                            }
+                            bytes.len() as i32
+                        },
+                        Err(()) => -1,
+                    },
+                    false => -1, // The buffer runs past the RAM.
+                };
+                self.cpu.set_register(V0, result as u32);
+            },
+            WRITE_FILE =>
+            {
+                let result = match self.fits_in_ram(a1, a2)
+                {
+                    true =>
+                    {
+                        let bytes: Vec<u8> = (a1..a1 + a2)
+                            .map(|address| self.bus.ram.read_data(address, 1) as u8)
+                            .collect();
+                        self.syscalls.write_file(a0, &bytes)
+                    },
+                    false => -1,
+                };
+                self.cpu.set_register(V0, result as u32);
+            },
+            CLOSE_FILE => self.syscalls.close_file(a0),
+            SET_SEED => self.set_seed(a1 as u64),
+            RANDOM_INT =>
+            {
+                let value = self.syscalls.random();
+                self.cpu.set_register(A0, value);
+            },
+            RANDOM_INT_RANGE if a1 == 0 => self.cpu.raise_syscall_exception(),
+            RANDOM_INT_RANGE =>
+            {
+                let value = (self.syscalls.random() as u64 * a1 as u64) >> 32; // scaled into 0..bound
+                self.cpu.set_register(A0, value as u32);
+            },
+            EXIT => self.syscalls.exit_code = Some(0),
+            EXIT2 => self.syscalls.exit_code = Some(a0 as i32),
+            _ => self.cpu.raise_syscall_exception(),
+        }
+        Ok(())
+    }
+
+    fn fits_in_ram(&self, buffer: u32, length: u32) -> bool
+    {
+        buffer.checked_add(length).is_some_and(|end| end as usize <= self.bus.ram.len())
+    }
+
+    fn read_guest_string(&self, address: u32) -> Vec<u8>
+    {
+        (address as usize..self.bus.ram.len())
+            .map(|address| self.bus.ram.read_data(address as u32, 1) as u8)
+            .take_while(|&byte| byte != 0)
+            .collect()
+    }
+
+    // Reads at most length - 1 characters into the buffer and terminates them with a null.
+    fn read_string(&mut self, buffer: u32, length: u32) -> Result<(), FaultKind>
+    {
+        if length == 0
+        {
+            return Ok(());
+        }
+
+        if !self.fits_in_ram(buffer, length)
+        {
+            return Err(FaultKind::BadAddress(buffer));
+        }
+
+        let line = self.syscalls.read_line();
+        let bytes = line.as_bytes().iter()
+            .take(length as usize - 1)
+            .chain(std::iter::once(&0));
+
+        for (address, &byte) in (buffer..).zip(bytes)
+        {
+            self.bus.ram.write_data(address, byte as u32, 1);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::cell::RefCell;
+    use std::io::{self, Cursor, Write};
+    use std::rc::Rc;
+    use crate::computer::{computer_with, Computer, HaltReason};
+    use crate::computer::fault::FaultKind;
+    use super::SyscallService;
+
+    // An output the test keeps reading after the computer took its copy.
+    #[derive(Clone, Default)]
+    struct Captured(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Captured
+    {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize>
+        {
+            self.0.borrow_mut().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()>
+        {
+            Ok(())
+        }
+    }
+
+    // A computer running the words with host syscalls, the given input and a captured output,
+    // stopped after the last word.
+    fn host_computer(program: &[u32], input: &str) -> (Computer, Captured)
+    {
+        let output = Captured::default();
+        let mut computer = computer_with(program);
+        computer.set_host_syscalls(true);
+        computer.set_input(Box::new(Cursor::new(input.as_bytes().to_vec())));
+        computer.set_output(Box::new(output.clone()));
+        computer.add_breakpoint(4 * program.len() as u32);
+        (computer, output)
+    }
+
+    #[test]
+    fn read_int_and_char_return_in_v0()
+    {
+        let (mut computer, _) = host_computer(&[
+            0x2402_0005, // addiu $v0, $zero, 5
+            0x0000_000C, // syscall
+            0x0040_8021, // addu $s0, $v0, $zero
+            0x2402_000C, // addiu $v0, $zero, 12
+            0x0000_000C, // syscall
+        ], "-1234\nx");
+
+        assert_eq!(computer.run(), HaltReason::Breakpoint(20));
+        assert_eq!(computer.cpu.register(16), -1234i32 as u32);
+        assert_eq!(computer.cpu.register(2), b'x' as u32);
+    }
+
+    #[test]
+    fn read_string_truncates_and_terminates_the_line()
+    {
+        let (mut computer, _) = host_computer(&[
+            0x2404_2000, // addiu $a0, $zero, 0x2000
+            0x2405_0004, // addiu $a1, $zero, 4
+            0x2402_0008, // addiu $v0, $zero, 8
+            0x0000_000C, // syscall
+        ], "hello\n");
+
+        assert_eq!(computer.run(), HaltReason::Breakpoint(16));
+        assert_eq!(computer.bus.ram.read_data(0x2000, 4), u32::from_be_bytes(*b"hel\0"));
+    }
+
+    #[test]
+    fn print_integer_goes_to_the_output()
+    {
+        let (mut computer, output) = host_computer(&[
+            0x2404_002A, // addiu $a0, $zero, 42
+            0x2402_0001, // addiu $v0, $zero, 1
+            0x0000_000C, // syscall
+            0x2404_000A, // addiu $a0, $zero, 10
+            0x2402_000B, // addiu $v0, $zero, 11
+            0x0000_000C, // syscall
+        ], "");
+
+        assert_eq!(computer.run(), HaltReason::Breakpoint(24));
+        assert_eq!(*output.0.borrow(), b"42\n");
+    }
+
+    #[test]
+    fn file_written_by_the_guest_reads_back()
+    {
+        let sandbox = std::env::temp_dir().join(format!("supersim-files-{}", std::process::id()));
+        std::fs::create_dir_all(&sandbox).unwrap();
+
+        let (mut computer, _) = host_computer(&[
+            0x2404_2000, // addiu $a0, $zero, path
+            0x2405_0001, // addiu $a1, $zero, 1
+            0x2402_000D, // addiu $v0, $zero, 13
+            0x0000_000C, // syscall
+            0x0040_8021, // addu $s0, $v0, $zero
+            0x0200_2021, // addu $a0, $s0, $zero
+            0x2405_2010, // addiu $a1, $zero, message
+            0x2406_0005, // addiu $a2, $zero, 5
+            0x2402_000F, // addiu $v0, $zero, 15
+            0x0000_000C, // syscall
+            0x0040_8821, // addu $s1, $v0, $zero
+            0x0200_2021, // addu $a0, $s0, $zero
+            0x2402_0010, // addiu $v0, $zero, 16
+            0x0000_000C, // syscall
+            0x2404_2000, // addiu $a0, $zero, path
+            0x2405_0000, // addiu $a1, $zero, 0
+            0x2402_000D, // addiu $v0, $zero, 13
+            0x0000_000C, // syscall
+            0x0040_2021, // addu $a0, $v0, $zero
+            0x2405_2020, // addiu $a1, $zero, buffer
+            0x2406_0010, // addiu $a2, $zero, 16
+            0x2402_000E, // addiu $v0, $zero, 14
+            0x0000_000C, // syscall
+            0x0040_9021, // addu $s2, $v0, $zero
+        ], "");
+        computer.set_file_sandbox(Some(sandbox.clone()));
+        for (address, &byte) in (0x2000..).zip(b"guest.txt\0".iter()).chain((0x2010..).zip(b"hello".iter()))
+        {
+            computer.bus.ram.write_data(address, byte as u32, 1);
+        }
+
+        let reason = computer.run();
+        let written = std::fs::read(sandbox.join("guest.txt"));
+        std::fs::remove_dir_all(&sandbox).unwrap();
+
+        assert_eq!(reason, HaltReason::Breakpoint(96));
+        assert_eq!(written.unwrap(), b"hello");
+        assert!(computer.cpu.register(16) >= 3); // a descriptor past the standard streams
+        assert_eq!(computer.cpu.register(17), 5); // bytes written
+        assert_eq!(computer.cpu.register(18), 5); // bytes read back
+        assert_eq!(computer.bus.ram.read_data(0x2020, 4), u32::from_be_bytes(*b"hell"));
+        assert_eq!(computer.bus.ram.read_data(0x2024, 2), u32::from(u16::from_be_bytes(*b"o\0")));
+    }
+
+    #[test]
+    fn read_file_past_the_memory_is_an_error()
+    {
+        let (mut computer, _) = host_computer(&[
+            0x2404_0000, // addiu $a0, $zero, 0 (stdin)
+            0x2405_3FFC, // addiu $a1, $zero, 0x3FFC
+            0x2406_0010, // addiu $a2, $zero, 16
+            0x2402_000E, // addiu $v0, $zero, 14
+            0x0000_000C, // syscall
+        ], "too long for the buffer");
+
+        assert_eq!(computer.run(), HaltReason::Breakpoint(20));
+        assert_eq!(computer.cpu.register(2), -1i32 as u32);
+        assert_eq!(computer.bus.ram.read_data(0x3FFC, 4), 0); // nothing is written
+    }
+
+    #[test]
+    fn sbrk_advances_word_aligned_and_spares_the_stack()
+    {
+        let mut syscalls = SyscallService::new(0x1000..0x2_0000);
+
+        assert_eq!(syscalls.sbrk(5), 0x1000);
+        assert_eq!(syscalls.sbrk(8), 0x1008); // 5 bytes rounded up to a word
+        assert_eq!(syscalls.sbrk(0x2_0000), -1); // 128 KiB more would run into the stack
+        assert_eq!(syscalls.sbrk(-4), -1);
+
+        syscalls.set_heap_limit(0x2_0000);
+        assert_eq!(syscalls.sbrk(0x1_8000), 0x1010); // past the default limit
+    }
+
+    #[test]
+    fn read_string_past_the_memory_is_a_fault()
+    {
+        let (mut computer, _) = host_computer(&[
+            0x2404_3FFC, // addiu $a0, $zero, 0x3FFC
+            0x2405_0008, // addiu $a1, $zero, 8
+            0x2402_0008, // addiu $v0, $zero, 8
+            0x0000_000C, // syscall
+        ], "too long\n");
+
+        match computer.run()
+        {
+            HaltReason::Fault(fault) =>
+            {
+                assert_eq!(fault.kind, FaultKind::BadAddress(0x3FFC));
+                assert_eq!(fault.pc, 12);
+            },
+            reason => panic!("Expected a fault, got {:?}", reason),
+        }
+    }
+
+    #[test]
+    fn sbrk_starts_past_the_loaded_data()
+    {
+        let mut syscalls = SyscallService::new(0x1000..0x2_0000);
+        syscalls.reserve_heap(0x1006);
+
+        assert_eq!(syscalls.sbrk(4), 0x1008); // 6 bytes of data rounded up to a word
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_run()
+    {
+        let program = [
+            0x2402_0029, // addiu $v0, $zero, 41
+            0x0000_000C, // syscall
+            0x0080_8021, // addu $s0, $a0, $zero
+            0x2405_000A, // addiu $a1, $zero, 10
+            0x2402_002A, // addiu $v0, $zero, 42
+            0x0000_000C, // syscall
+            0x0080_8821, // addu $s1, $a0, $zero
+        ];
+        let run = |seed|
+        {
+            let (mut computer, _) = host_computer(&program, "");
+            let hash = computer.run_deterministic(seed, b"", 7);
+            (hash, computer.cpu.registers())
+        };
+
+        let (hash, registers) = run(7);
+        assert_eq!(run(7), (hash, registers));
+        assert!(registers[17] < 10);
+
+        let (other_hash, other_registers) = run(8);
+        assert_ne!(other_hash, hash);
+        assert_ne!(other_registers[16], registers[16]);
+
+        // Syscall 40 seeds the same generator.
+        let (mut computer, _) = host_computer(&[
+            0x2405_0007, // addiu $a1, $zero, 7
+            0x2402_0028, // addiu $v0, $zero, 40
+            0x0000_000C, // syscall
+            0x2402_0029, // addiu $v0, $zero, 41
+            0x0000_000C, // syscall
+        ], "");
+        assert_eq!(computer.run(), HaltReason::Breakpoint(20));
+        assert_eq!(computer.cpu.register(4), registers[16]);
+    }
+
+    #[test]
+    fn guest_exit_code_ends_the_run()
+    {
+        let (mut computer, _) = host_computer(&[
+            0x2404_0003, // addiu $a0, $zero, 3
+            0x2402_0011, // addiu $v0, $zero, 17
+            0x0000_000C, // syscall
+            0x2410_0001, // addiu $s0, $zero, 1
+        ], "");
+        assert_eq!(computer.run(), HaltReason::Exited(3));
+        assert_eq!(computer.cpu.register(16), 0);
+
+        let (mut computer, _) = host_computer(&[
+            0x2402_000A, // addiu $v0, $zero, 10
+            0x0000_000C, // syscall
+        ], "");
+        assert_eq!(computer.run(), HaltReason::Exited(0));
+    }
+
+    #[test]
+    fn monitor_reports_an_unhandled_syscall_and_halts()
+    {
+        let output = Captured::default();
+        let mut computer = computer_with(&[
+            0x2402_0001, // addiu $v0, $zero, 1
+            0x0000_000C, // syscall
+            0x2408_0001, // addiu $t0, $zero, 1
+        ]);
+        computer.set_output(Box::new(output.clone()));
+        computer.set_monitor(true);
+
+        match computer.run()
+        {
+            HaltReason::Fault(fault) => assert_eq!(fault.kind, FaultKind::UnhandledException(8)),
+            reason => panic!("Expected a fault, got {:?}", reason),
+        }
+        let text = String::from_utf8(output.0.borrow().clone()).unwrap();
+        assert!(text.starts_with("Unhandled exception: Sys (8)\nPC: 0x00000004\n"), "{}", text);
+        assert_eq!(computer.cpu.register(8), 0);
+    }
+}
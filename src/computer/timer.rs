@@ -0,0 +1,127 @@
+/*
+    Programmable interval timer, mapped at its base address:
+        word 0: control, bit 0 enable, bit 1 interrupt enable, bit 2 expired (write 0 to acknowledge)
+        word 1: reload value
+        word 2: counter
+    The counter decrements every step. When it runs out the timer reloads, sets the expired
+    flag and, with interrupts enabled, requests its interrupt line until acknowledged,
+    so a reload value of N expires every N steps.
+ */
+const CONTROL_ENABLE: u32 = 1 << 0;
+const CONTROL_INTERRUPT_ENABLE: u32 = 1 << 1;
+const CONTROL_EXPIRED: u32 = 1 << 2;
+
+pub(super) const TIMER_SIZE: u32 = 12;
+
+pub(super) struct IntervalTimer
+{
+    base: u32,
+    interrupt_line: u8,
+    control: u32,
+    reload: u32,
+    counter: u32,
+}
+
+impl IntervalTimer
+{
+    pub(super) fn new(base: u32, interrupt_line: u8) -> IntervalTimer
+    {
+        if interrupt_line > 7
+        {
+            panic!("Bad interrupt line: {}", interrupt_line);
+        }
+
+        IntervalTimer
+        {
+            base,
+            interrupt_line,
+            control: 0,
+            reload: 0,
+            counter: 0,
+        }
+    }
+
+    pub(super) fn contains(&self, address: u32) -> bool
+    {
+        address.wrapping_sub(self.base) < TIMER_SIZE
+    }
+
+    pub(super) fn read(&self, address: u32) -> u32
+    {
+        match (address - self.base) / 4
+        {
+            0 => self.control,
+            1 => self.reload,
+            _ => self.counter,
+        }
+    }
+
+    pub(super) fn write(&mut self, address: u32, data: u32)
+    {
+        match (address - self.base) / 4
+        {
+            0 => self.control = data & (CONTROL_ENABLE | CONTROL_INTERRUPT_ENABLE | CONTROL_EXPIRED),
+            1 => self.reload = data,
+            _ => self.counter = data,
+        }
+    }
+
+    pub(super) fn step(&mut self)
+    {
+        if self.control & CONTROL_ENABLE == 0
+        {
+            return;
+        }
+
+        if self.counter <= 1
+        {
+            self.counter = self.reload;
+            self.control |= CONTROL_EXPIRED;
+        }
+        else
+        {
+            self.counter -= 1;
+        }
+    }
+
+    pub(super) fn interrupt_requests(&self) -> u8
+    {
+        let requesting = self.control & CONTROL_INTERRUPT_ENABLE != 0 &&
+            self.control & CONTROL_EXPIRED != 0;
+
+        (requesting as u8) << self.interrupt_line
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use alloc::vec::Vec;
+    use super::*;
+
+    #[test]
+    fn reload_of_n_interrupts_every_n_steps()
+    {
+        const BASE: u32 = 0x1000;
+
+        let mut timer = IntervalTimer::new(BASE, 5);
+        timer.write(BASE + 4, 4);
+        timer.write(BASE + 8, 4);
+        timer.write(BASE, CONTROL_ENABLE | CONTROL_INTERRUPT_ENABLE);
+
+        let mut interrupts = Vec::new();
+        for step in 1..=12
+        {
+            timer.step();
+            let requests = timer.interrupt_requests();
+            if requests != 0
+            {
+                assert_eq!(requests, 1 << 5);
+                interrupts.push(step);
+                timer.write(BASE, CONTROL_ENABLE | CONTROL_INTERRUPT_ENABLE); // acknowledge
+            }
+        }
+
+        assert_eq!(interrupts, [4, 8, 12]);
+    }
+}
@@ -0,0 +1,255 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use crate::computer::Computer;
+use crate::computer::fault::FaultKind;
+use crate::data_section::strip_comment;
+use crate::disassembler::REGISTER_NAMES;
+
+// One retired instruction of a reference run: its address and the registers after it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceStep
+{
+    pub pc: u32,
+    pub registers: [u32; 32],
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mismatch
+{
+    Pc(u32), // address of the instruction this machine retired instead
+    Register { register: u8, expected: u32, actual: u32 },
+    Fault(FaultKind),
+}
+
+// One instruction run with the instruction trace on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEntry
+{
+    pub pc: u32,
+    pub instruction: u32,
+    pub branch: Option<u32>, // address of the jump or branch if this is its delay slot
+}
+
+impl TraceEntry
+{
+    pub fn in_delay_slot(&self) -> bool
+    {
+        self.branch.is_some()
+    }
+}
+
+// The first step where the machine and the reference went apart.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence
+{
+    pub step: usize,
+    pub pc: u32, // as in the reference
+    pub symbol: Option<String>, // the pc as symbol+0xoffset, if a symbol covers it
+    pub mismatch: Mismatch,
+}
+
+impl fmt::Display for Divergence
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "Step {}, PC 0x{:08X}", self.step, self.pc)?;
+        if let Some(symbol) = &self.symbol
+        {
+            write!(f, " ({})", symbol)?;
+        }
+        write!(f, ": ")?;
+        match &self.mismatch
+        {
+            Mismatch::Pc(actual) => write!(f, "the instruction at 0x{:08X} was run instead", actual),
+            Mismatch::Register { register, expected, actual } =>
+                write!(f, "{} is 0x{:08X}, expected 0x{:08X}", REGISTER_NAMES[*register as usize], actual, expected),
+            Mismatch::Fault(kind) => write!(f, "the machine faulted: {:?}", kind),
+        }
+    }
+}
+
+/*
+    Reads a reference trace, one retired instruction per line: the PC followed by
+    the 32 registers, all in hex and separated by whitespace. # starts a comment.
+ */
+pub fn parse_trace(text: &str) -> Result<Vec<TraceStep>, String>
+{
+    let mut steps = Vec::new();
+    for (line_number, line) in text.lines().enumerate()
+    {
+        let line = strip_comment(line).trim();
+        if line.is_empty()
+        {
+            continue;
+        }
+
+        let words = line.split_whitespace()
+            .map(|word| u32::from_str_radix(word.trim_start_matches("0x"), 16))
+            .collect::<Result<Vec<u32>, _>>()
+            .map_err(|_| format!("Line {}: not a hex word", line_number + 1))?;
+        let [pc, registers @ ..] = words.as_slice() else
+        {
+            unreachable!(); // the line isn't empty
+        };
+        let registers = registers.try_into()
+            .map_err(|_| format!("Line {}: expected the PC and 32 registers", line_number + 1))?;
+
+        steps.push(TraceStep { pc: *pc, registers });
+    }
+    Ok(steps)
+}
+
+impl Computer
+{
+    // Starts recording every instruction run from an empty trace, or stops it.
+    pub fn set_instruction_trace(&mut self, enabled: bool)
+    {
+        self.instruction_trace = match enabled
+        {
+            true => Some(Vec::new()),
+            false => None,
+        };
+    }
+
+    // The instructions run so far, oldest first, empty without the trace.
+    pub fn instruction_trace(&self) -> &[TraceEntry]
+    {
+        self.instruction_trace.as_deref().unwrap_or(&[])
+    }
+
+    pub(super) fn trace_instruction(&mut self)
+    {
+        let Some(trace) = &mut self.instruction_trace else
+        {
+            return;
+        };
+
+        let pc = self.cpu.instruction_address();
+        let instruction = match self.bus.contains(pc, 4)
+        {
+            true => self.bus.read_data(pc, 4),
+            false => 0,
+        };
+        trace.push(TraceEntry { pc, instruction, branch: self.cpu.delay_slot_branch() });
+    }
+
+    /*
+        Runs one instruction per reference step and compares the address of the retired
+        instruction and the registers after it, stopping at the first divergence.
+     */
+    pub fn run_with_trace_check(&mut self, reference: &[TraceStep]) -> Result<(), Divergence>
+    {
+        for (step, expected) in reference.iter().enumerate()
+        {
+            self.check_trace_step(expected).map_err(|mismatch| Divergence
+            {
+                step,
+                pc: expected.pc,
+                symbol: self.symbols.symbol(expected.pc),
+                mismatch,
+            })?;
+        }
+
+        Ok(())
+    }
+
+    fn check_trace_step(&mut self, expected: &TraceStep) -> Result<(), Mismatch>
+    {
+        self.step().map_err(Mismatch::Fault)?;
+
+        let pc = self.cpu.instruction_address();
+        if pc != expected.pc
+        {
+            return Err(Mismatch::Pc(pc));
+        }
+
+        let registers = self.cpu.registers();
+        let differing = (0..32).find(|&register| registers[register] != expected.registers[register]);
+        if let Some(register) = differing
+        {
+            return Err(Mismatch::Register
+            {
+                register: register as u8,
+                expected: expected.registers[register],
+                actual: registers[register],
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use alloc::{format, vec};
+    use alloc::string::{String, ToString};
+    use alloc::vec::Vec;
+    use crate::computer::test_computer;
+    use super::{parse_trace, Mismatch};
+
+    const COUNTDOWN_SUM: &str = include_str!("../../testdata/countdown_sum.trace");
+
+    fn countdown_sum(step: i32) -> String
+    {
+        format!("
+            li $t0, 3
+            li $t1, 0
+        loop:
+            addu $t1, $t1, $t0
+            addi $t0, $t0, {}
+            bne $t0, $zero, loop", step)
+    }
+
+    #[test]
+    fn run_matches_the_reference_trace()
+    {
+        let reference = parse_trace(COUNTDOWN_SUM).unwrap();
+        assert_eq!(reference.len(), 11);
+
+        let mut computer = test_computer(&countdown_sum(-1));
+        assert_eq!(computer.run_with_trace_check(&reference), Ok(()));
+        assert_eq!(computer.cpu.registers()[9], 6);
+    }
+
+    #[test]
+    fn first_divergence_from_the_reference_is_reported()
+    {
+        let reference = parse_trace(COUNTDOWN_SUM).unwrap();
+        let mut computer = test_computer(&countdown_sum(-2));
+        computer.add_symbol(8, "loop");
+
+        let divergence = computer.run_with_trace_check(&reference).unwrap_err();
+        assert_eq!(divergence.step, 3);
+        assert_eq!(divergence.mismatch, Mismatch::Register { register: 8, expected: 2, actual: 1 });
+        assert_eq!(divergence.to_string(), "Step 3, PC 0x0000000C (loop+0x4): $t0 is 0x00000001, expected 0x00000002");
+    }
+
+    #[test]
+    fn the_delay_slot_of_a_taken_branch_is_flagged()
+    {
+        let mut computer = test_computer("
+            beq $zero, $zero, target
+            addiu $t0, $zero, 1
+            addiu $t1, $zero, 2
+            target:
+            addiu $t2, $zero, 3");
+        computer.set_delay_slots(true);
+        computer.set_instruction_trace(true);
+        for _ in 0..3
+        {
+            computer.step().unwrap();
+        }
+
+        let trace = computer.instruction_trace();
+        let addresses: Vec<u32> = trace.iter().map(|entry| entry.pc).collect();
+        assert_eq!(addresses, vec![0, 4, 12]);
+        assert!(!trace[0].in_delay_slot());
+        assert_eq!(trace[1].branch, Some(0));
+        assert_eq!(trace[1].instruction, 0x2408_0001); // addiu $t0, $zero, 1
+        assert!(!trace[2].in_delay_slot());
+        assert_eq!(computer.cpu.registers()[8..11], [1, 0, 3]);
+    }
+}
@@ -1,40 +1,49 @@
-use minifb::{Window, WindowOptions};
-use crate::computer::memory::Memory;
+use minifb::{Scale, Window, WindowOptions};
 
 pub(super) struct Video
 {
-    vram_start: u32,
-    vram_size: u32,
     window: Window,
 }
 
 impl Video
 {
-    pub(super) fn new(width: usize, height: usize, vram_start: u32) -> Video
+    pub(super) fn new(width: usize, height: usize, scale: usize) -> Result<Video, String>
     {
-        Video
+        // The window is scaled up with nearest neighbour, the framebuffer keeps its size.
+        let scale = match scale
         {
-            vram_start,
-            vram_size: (width * height * 4) as u32,
-            window: Window::new("super emulator kurwo", width, height, WindowOptions::default())
-                .unwrap(),
-        }
-    }
+            1 => Scale::X1,
+            2 => Scale::X2,
+            4 => Scale::X4,
+            8 => Scale::X8,
+            16 => Scale::X16,
+            32 => Scale::X32,
+            _ => return Err(format!("Unsupported display scale: {}", scale)),
+        };
+        let options = WindowOptions
+        {
+            scale,
+            ..WindowOptions::default()
+        };
 
-    pub(super) fn display(&mut self, memory: &Memory)
-    {
-        let start = self.vram_start;
-        let end = self.vram_start + self.vram_size;
-        let mut buffer = Vec::new();
+        let window = Window::new("super emulator kurwo", width, height, options)
+            .map_err(|error| error.to_string())?;
 
-        for addr in (start..end).step_by(4)
+        Ok(Video
         {
-            let pixel = memory.read_data(addr as u32, 4);
-            buffer.push(pixel);
-        }
+            window,
+        })
+    }
 
-        let (width, height) = self.window.get_size();
-        self.window.update_with_buffer(&buffer, width, height)
-            .unwrap();
+    pub(super) fn is_open(&self) -> bool
+    {
+        self.window.is_open()
     }
-}
\ No newline at end of file
+
+    // Fails when the window can no longer be updated, e.g. after it was closed.
+    pub(super) fn display(&mut self, pixels: &[u32], width: usize, height: usize) -> Result<(), String>
+    {
+        self.window.update_with_buffer(pixels, width, height)
+            .map_err(|error| error.to_string())
+    }
+}
@@ -0,0 +1,205 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+// Bytes of a data section together with the addresses of its labels.
+pub struct DataImage
+{
+    pub base: u32,
+    pub bytes: Vec<u8>,
+    pub labels: BTreeMap<String, u32>,
+}
+
+/*
+    Assembles lines of data directives placed at the base address, e.g.
+        array: .word 1, 2, 0x30
+        msg: .asciiz "hi\n"
+    Supported directives: .byte, .half, .word, .space, .align, .ascii and .asciiz.
+    Values are stored big endian, .half and .word are aligned to their size like in MARS.
+ */
+pub fn assemble_data(lines: &[&str], base: u32) -> Result<DataImage, String>
+{
+    let mut image = DataImage
+    {
+        base,
+        bytes: Vec::new(),
+        labels: BTreeMap::new(),
+    };
+
+    for (number, line) in lines.iter().enumerate()
+    {
+        image.assemble_line(line)
+            .map_err(|error| format!("Line {}: {}", number + 1, error))?;
+    }
+
+    Ok(image)
+}
+
+impl DataImage
+{
+    fn address(&self) -> u32
+    {
+        self.base.wrapping_add(self.bytes.len() as u32)
+    }
+
+    fn align(&mut self, alignment: usize)
+    {
+        while !self.address().is_multiple_of(alignment as u32)
+        {
+            self.bytes.push(0);
+        }
+    }
+
+    fn assemble_line(&mut self, line: &str) -> Result<(), String>
+    {
+        let mut line = strip_comment(line).trim();
+
+        if let Some((label, rest)) = line.split_once(':')
+        {
+            let label = label.trim();
+            let is_label = !label.is_empty() &&
+                label.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+            if is_label
+            {
+                if self.labels.contains_key(label)
+                {
+                    return Err(format!("Duplicate label: {}", label));
+                }
+                self.labels.insert(label.to_string(), self.address());
+                line = rest.trim();
+            }
+        }
+
+        if line.is_empty()
+        {
+            return Ok(());
+        }
+
+        let (directive, arguments) = line.split_once(char::is_whitespace)
+            .unwrap_or((line, ""));
+        let arguments = arguments.trim();
+
+        match directive
+        {
+            ".byte" => self.values(arguments, 1),
+            ".half" => self.values(arguments, 2),
+            ".word" => self.values(arguments, 4),
+            ".space" =>
+            {
+                let size = parse_number(arguments)?;
+                self.bytes.resize(self.bytes.len() + size as usize, 0);
+                Ok(())
+            },
+            ".align" =>
+            {
+                let power = parse_number(arguments)?;
+                if power > 16
+                {
+                    return Err(format!("Bad alignment: {}", power));
+                }
+                self.align(1 << power);
+                Ok(())
+            },
+            ".ascii" =>
+            {
+                let string = parse_string(arguments)?;
+                self.bytes.extend(string);
+                Ok(())
+            },
+            ".asciiz" =>
+            {
+                let string = parse_string(arguments)?;
+                self.bytes.extend(string);
+                self.bytes.push(0);
+                Ok(())
+            },
+            _ => Err(format!("Unknown directive: {}", directive)),
+        }
+    }
+
+    fn values(&mut self, arguments: &str, size: usize) -> Result<(), String>
+    {
+        self.align(size);
+        for argument in arguments.split(',')
+        {
+            let value = parse_number(argument)?;
+            let bytes = value.to_be_bytes();
+            self.bytes.extend(&bytes[4 - size..]);
+        }
+
+        Ok(())
+    }
+}
+
+pub(crate) fn strip_comment(line: &str) -> &str
+{
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, c) in line.char_indices()
+    {
+        match c
+        {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..index],
+            _ => {},
+        }
+    }
+
+    line
+}
+
+pub(crate) fn parse_number(text: &str) -> Result<u32, String>
+{
+    let text = text.trim();
+    let (negative, digits) = match text.strip_prefix('-')
+    {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+
+    let value = match digits.strip_prefix("0x").or(digits.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => digits.parse::<u32>(),
+    }.map_err(|_| format!("Bad number: {}", text))?;
+
+    Ok(match negative
+    {
+        true => value.wrapping_neg(),
+        false => value,
+    })
+}
+
+fn parse_string(text: &str) -> Result<Vec<u8>, String>
+{
+    let inner = text.strip_prefix('"')
+        .and_then(|text| text.strip_suffix('"'))
+        .ok_or(format!("Bad string: {}", text))?;
+
+    let mut bytes = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next()
+    {
+        let c = match c
+        {
+            '\\' => match chars.next()
+            {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('0') => '\0',
+                Some('\\') => '\\',
+                Some('"') => '"',
+                _ => return Err(format!("Bad escape sequence in {}", text)),
+            },
+            c => c,
+        };
+
+        let mut buffer = [0; 4];
+        bytes.extend(c.encode_utf8(&mut buffer).as_bytes());
+    }
+
+    Ok(bytes)
+}
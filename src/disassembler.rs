@@ -0,0 +1,492 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+
+pub const REGISTER_NAMES: [&str; 32] =
+[
+    "$zero", "$at", "$v0", "$v1", "$a0", "$a1", "$a2", "$a3",
+    "$t0", "$t1", "$t2", "$t3", "$t4", "$t5", "$t6", "$t7",
+    "$s0", "$s1", "$s2", "$s3", "$s4", "$s5", "$s6", "$s7",
+    "$t8", "$t9", "$k0", "$k1", "$gp", "$sp", "$fp", "$ra",
+];
+
+const REGISTER_NUMBERS: [&str; 32] =
+[
+    "$0", "$1", "$2", "$3", "$4", "$5", "$6", "$7",
+    "$8", "$9", "$10", "$11", "$12", "$13", "$14", "$15",
+    "$16", "$17", "$18", "$19", "$20", "$21", "$22", "$23",
+    "$24", "$25", "$26", "$27", "$28", "$29", "$30", "$31",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisasmOptions<'a>
+{
+    pub abi_names: bool, // $t0, $sp, $ra rather than $8, $29, $31
+    pub symbols: Option<&'a BTreeMap<u32, String>>, // branch and jump targets as symbol+0xoffset
+}
+
+impl Default for DisasmOptions<'_>
+{
+    fn default() -> Self
+    {
+        DisasmOptions
+        {
+            abi_names: true,
+            symbols: None,
+        }
+    }
+}
+
+// The nearest symbol at or below the address, as "symbol" or "symbol+0xoffset".
+pub fn symbol_at(symbols: &BTreeMap<u32, String>, address: u32) -> Option<String>
+{
+    match symbols.range(..=address).next_back()
+    {
+        Some((&start, name)) if start == address => Some(name.clone()),
+        Some((&start, name)) => Some(format!("{}+0x{:X}", name, address - start)),
+        None => None,
+    }
+}
+
+fn target(address: u32, options: DisasmOptions) -> String
+{
+    options.symbols
+        .and_then(|symbols| symbol_at(symbols, address))
+        .unwrap_or_else(|| format!("0x{:08X}", address))
+}
+
+fn reg(num: u32, options: DisasmOptions) -> &'static str
+{
+    let names = match options.abi_names
+    {
+        true => &REGISTER_NAMES,
+        false => &REGISTER_NUMBERS,
+    };
+    names[num as usize & 0b11111]
+}
+
+fn branch_target(address: u32, imm: u32) -> u32
+{
+    let offset = (imm as u16 as i16 as i32) * 4;
+    (address.wrapping_add(4) as i32).wrapping_add(offset) as u32
+}
+
+/*
+    Translates an instruction located at the given address to assembly.
+    The address is needed to show absolute branch and jump targets, named after
+    the nearest symbol when the options carry a symbol table.
+    Encodings the CPU doesn't decode are shown as .word directives.
+ */
+pub fn disassemble(instruction: u32, address: u32, options: DisasmOptions) -> String
+{
+    let opcode = instruction >> 26;
+    let rs = (instruction >> 21) & 0b11111;
+    let rt = (instruction >> 16) & 0b11111;
+    let rd = (instruction >> 11) & 0b11111;
+    let shamt = (instruction >> 6) & 0b11111;
+    let funct = instruction & 0b111111;
+    let imm = instruction & 0xFFFF;
+    let simm = imm as u16 as i16;
+
+    let (rs, rt, rd) = (reg(rs, options), reg(rt, options), reg(rd, options));
+
+    match opcode
+    {
+        0 => match funct
+        {
+            0 if instruction == 0 => String::from("nop"),
+            0 => format!("sll {}, {}, {}", rd, rt, shamt),
+            1 if (instruction >> 16) & 0b11 == 0 => format!("movf {}, {}, {}", rd, rs, (instruction >> 18) & 0b111),
+            1 if (instruction >> 16) & 0b11 == 1 => format!("movt {}, {}, {}", rd, rs, (instruction >> 18) & 0b111),
+            2 => format!("srl {}, {}, {}", rd, rt, shamt),
+            3 => format!("sra {}, {}, {}", rd, rt, shamt),
+            4 => format!("sllv {}, {}, {}", rd, rt, rs),
+            6 => format!("srlv {}, {}, {}", rd, rt, rs),
+            7 => format!("srav {}, {}, {}", rd, rt, rs),
+            8 => format!("jr {}", rs),
+            9 => format!("jalr {}, {}", rd, rs),
+            12 if instruction >> 6 == 0 => String::from("syscall"),
+            12 => format!("syscall 0x{:X}", (instruction >> 6) & 0xF_FFFF),
+            16 => format!("mfhi {}", rd),
+            17 => format!("mthi {}", rs),
+            18 => format!("mflo {}", rd),
+            19 => format!("mtlo {}", rs),
+            24 => format!("mult {}, {}", rs, rt),
+            25 => format!("multu {}, {}", rs, rt),
+            26 => format!("div {}, {}", rs, rt),
+            27 => format!("divu {}, {}", rs, rt),
+            32 => format!("add {}, {}, {}", rd, rs, rt),
+            33 => format!("addu {}, {}, {}", rd, rs, rt),
+            34 => format!("sub {}, {}, {}", rd, rs, rt),
+            35 => format!("subu {}, {}, {}", rd, rs, rt),
+            36 => format!("and {}, {}, {}", rd, rs, rt),
+            37 => format!("or {}, {}, {}", rd, rs, rt),
+            38 => format!("xor {}, {}, {}", rd, rs, rt),
+            39 => format!("nor {}, {}, {}", rd, rs, rt),
+            42 => format!("slt {}, {}, {}", rd, rs, rt),
+            43 => format!("sltu {}, {}, {}", rd, rs, rt),
+            0x30..=0x34 | 0x36 =>
+            {
+                let mnemonic = ["tge", "tgeu", "tlt", "tltu", "teq", "", "tne"][funct as usize - 0x30];
+                match (instruction >> 6) & 0x3FF
+                {
+                    0 => format!("{} {}, {}", mnemonic, rs, rt),
+                    code => format!("{} {}, {}, 0x{:X}", mnemonic, rs, rt, code),
+                }
+            },
+            _ => word(instruction),
+        },
+        1 => match (instruction >> 16) & 0b11111
+        {
+            8 => format!("tgei {}, {}", rs, simm),
+            9 => format!("tgeiu {}, {}", rs, simm),
+            0xA => format!("tlti {}, {}", rs, simm),
+            0xB => format!("tltiu {}, {}", rs, simm),
+            0xC => format!("teqi {}, {}", rs, simm),
+            0xE => format!("tnei {}, {}", rs, simm),
+            _ => word(instruction),
+        },
+        2 | 3 =>
+        {
+            let destination = (address.wrapping_add(4) & 0xF000_0000) | ((instruction & 0x3FF_FFFF) << 2);
+            let mnemonic = if opcode == 2 {"j"} else {"jal"};
+            format!("{} {}", mnemonic, target(destination, options))
+        },
+        4 => format!("beq {}, {}, {}", rs, rt, target(branch_target(address, imm), options)),
+        5 => format!("bne {}, {}, {}", rs, rt, target(branch_target(address, imm), options)),
+        6 => format!("blez {}, {}", rs, target(branch_target(address, imm), options)),
+        7 => format!("bgtz {}, {}", rs, target(branch_target(address, imm), options)),
+        8 => format!("addi {}, {}, {}", rt, rs, simm),
+        9 => format!("addiu {}, {}, {}", rt, rs, simm),
+        10 => format!("slti {}, {}, {}", rt, rs, simm),
+        11 => format!("sltiu {}, {}, {}", rt, rs, simm),
+        12 => format!("andi {}, {}, 0x{:X}", rt, rs, imm),
+        13 => format!("ori {}, {}, 0x{:X}", rt, rs, imm),
+        14 => format!("xori {}, {}, 0x{:X}", rt, rs, imm),
+        15 => format!("lui {}, 0x{:X}", rt, imm),
+        0x10 => disassemble_cp0(instruction, options),
+        0x11 => disassemble_cp1(instruction, options),
+        0x12 => match (instruction >> 21) & 0b11111
+        {
+            0 => format!("mfc2 {}, ${}", rt, (instruction >> 11) & 0b11111),
+            2 => format!("cfc2 {}, ${}", rt, (instruction >> 11) & 0b11111),
+            4 => format!("mtc2 {}, ${}", rt, (instruction >> 11) & 0b11111),
+            6 => format!("ctc2 {}, ${}", rt, (instruction >> 11) & 0b11111),
+            _ => word(instruction),
+        },
+        0x1C => match funct
+        {
+            0x02 => format!("mul {}, {}, {}", rd, rs, rt),
+            0x20 => format!("clz {}, {}", rd, rs),
+            0x21 => format!("clo {}, {}", rd, rs),
+            _ => word(instruction),
+        },
+        32 => format!("lb {}, {}({})", rt, simm, rs),
+        33 => format!("lh {}, {}({})", rt, simm, rs),
+        34 => format!("lwl {}, {}({})", rt, simm, rs),
+        35 => format!("lw {}, {}({})", rt, simm, rs),
+        36 => format!("lbu {}, {}({})", rt, simm, rs),
+        37 => format!("lhu {}, {}({})", rt, simm, rs),
+        38 => format!("lwr {}, {}({})", rt, simm, rs),
+        40 => format!("sb {}, {}({})", rt, simm, rs),
+        41 => format!("sh {}, {}({})", rt, simm, rs),
+        43 => format!("sw {}, {}({})", rt, simm, rs),
+        0x30 => format!("ll {}, {}({})", rt, simm, rs),
+        0x38 => format!("sc {}, {}({})", rt, simm, rs),
+        0x2F => format!("cache {}, {}({})", (instruction >> 16) & 0b11111, simm, rs),
+        0x33 => format!("pref {}, {}({})", (instruction >> 16) & 0b11111, simm, rs),
+        0x31 => format!("lwc1 $f{}, {}({})", (instruction >> 16) & 0b11111, simm, rs),
+        0x39 => format!("swc1 $f{}, {}({})", (instruction >> 16) & 0b11111, simm, rs),
+        0x35 => format!("ldc1 $f{}, {}({})", (instruction >> 16) & 0b11111, simm, rs),
+        0x3D => format!("sdc1 $f{}, {}({})", (instruction >> 16) & 0b11111, simm, rs),
+        _ => word(instruction),
+    }
+}
+
+// The name of the instruction as disassemble shows it (nop for the zero word), .word if unknown.
+pub fn mnemonic(instruction: u32) -> String
+{
+    let text = disassemble(instruction, 0, DisasmOptions::default());
+    String::from(text.split_whitespace().next().unwrap_or_default())
+}
+
+fn word(instruction: u32) -> String
+{
+    format!(".word 0x{:08X}", instruction)
+}
+
+fn disassemble_cp0(instruction: u32, options: DisasmOptions) -> String
+{
+    let rs = (instruction >> 21) & 0b11111;
+    let rt = reg((instruction >> 16) & 0b11111, options);
+    let rd = (instruction >> 11) & 0b11111;
+    let funct = instruction & 0b111111;
+    let select = instruction & 0b111;
+    let moves = instruction & 0x7F8 == 0;
+
+    match (rs & 0x10 != 0, rs, funct) // CO bit, see Cpu::decode_cp0
+    {
+        (true, _, 0x10) => String::from("rfe"),
+        (true, _, 0x18) => String::from("eret"),
+        (true, _, 0x20) => String::from("wait"),
+        (false, 0, 0) => format!("mfc0 {}, ${}", rt, rd),
+        (false, 4, 0) => format!("mtc0 {}, ${}", rt, rd),
+        (false, 0, _) if moves => format!("mfc0 {}, ${}, {}", rt, rd, select),
+        (false, 4, _) if moves => format!("mtc0 {}, ${}, {}", rt, rd, select),
+        _ => word(instruction),
+    }
+}
+
+fn disassemble_cp1(instruction: u32, options: DisasmOptions) -> String
+{
+    let format_field = (instruction >> 21) & 0b11111;
+    let ft = (instruction >> 16) & 0b11111;
+    let fs = (instruction >> 11) & 0b11111;
+    let fd = (instruction >> 6) & 0b11111;
+    let funct = instruction & 0b111111;
+    let cc = (instruction >> 18) & 0b111;
+    let compare_cc = (instruction >> 8) & 0b111;
+
+    if fd == 0 && funct == 0
+    {
+        match format_field
+        {
+            0 => return format!("mfc1 {}, $f{}", reg(ft, options), fs),
+            4 => return format!("mtc1 {}, $f{}", reg(ft, options), fs),
+            _ => {},
+        }
+    }
+
+    let suffix = match format_field
+    {
+        0x10 => "s",
+        0x11 => "d",
+        0x14 => "w",
+        _ => return word(instruction),
+    };
+
+    match (format_field, funct)
+    {
+        (0x10 | 0x11, 0) => format!("add.{} $f{}, $f{}, $f{}", suffix, fd, fs, ft),
+        (0x10 | 0x11, 1) => format!("sub.{} $f{}, $f{}, $f{}", suffix, fd, fs, ft),
+        (0x10 | 0x11, 2) => format!("mul.{} $f{}, $f{}, $f{}", suffix, fd, fs, ft),
+        (0x10 | 0x11, 3) => format!("div.{} $f{}, $f{}, $f{}", suffix, fd, fs, ft),
+        (0x10 | 0x11, 4) if ft == 0 => format!("sqrt.{} $f{}, $f{}", suffix, fd, fs),
+        (0x10 | 0x11, 5) if ft == 0 => format!("abs.{} $f{}, $f{}", suffix, fd, fs),
+        (0x10 | 0x11, 6) if ft == 0 => format!("mov.{} $f{}, $f{}", suffix, fd, fs),
+        (0x10 | 0x11, 7) if ft == 0 => format!("neg.{} $f{}, $f{}", suffix, fd, fs),
+        (0x10 | 0x11, 0xC) if ft == 0 => format!("round.w.{} $f{}, $f{}", suffix, fd, fs),
+        (0x10 | 0x11, 0xD) if ft == 0 => format!("trunc.w.{} $f{}, $f{}", suffix, fd, fs),
+        (0x10 | 0x11, 0xE) if ft == 0 => format!("ceil.w.{} $f{}, $f{}", suffix, fd, fs),
+        (0x10 | 0x11, 0xF) if ft == 0 => format!("floor.w.{} $f{}, $f{}", suffix, fd, fs),
+        (0x10 | 0x11, 0x11) if ft & 0b11 == 0 => format!("movf.{} $f{}, $f{}, {}", suffix, fd, fs, cc),
+        (0x10 | 0x11, 0x11) if ft & 0b11 == 1 => format!("movt.{} $f{}, $f{}, {}", suffix, fd, fs, cc),
+        (0x10 | 0x11, 0x12) => format!("movz.{} $f{}, $f{}, {}", suffix, fd, fs, reg(ft, options)),
+        (0x10 | 0x11, 0x13) => format!("movn.{} $f{}, $f{}, {}", suffix, fd, fs, reg(ft, options)),
+        (0x11 | 0x14, 0x20) if ft == 0 => format!("cvt.s.{} $f{}, $f{}", suffix, fd, fs),
+        (0x10 | 0x14, 0x21) if ft == 0 => format!("cvt.d.{} $f{}, $f{}", suffix, fd, fs),
+        (0x10 | 0x11, 0x24) if ft == 0 => format!("cvt.w.{} $f{}, $f{}", suffix, fd, fs),
+        (0x10 | 0x11, 0x32) if fd & 0b11 == 0 => format!("c.eq.{} {}, $f{}, $f{}", suffix, compare_cc, fs, ft),
+        (0x10 | 0x11, 0x3C) if fd & 0b11 == 0 => format!("c.lt.{} {}, $f{}, $f{}", suffix, compare_cc, fs, ft),
+        (0x10 | 0x11, 0x3E) if fd & 0b11 == 0 => format!("c.le.{} {}, $f{}, $f{}", suffix, compare_cc, fs, ft),
+        _ => word(instruction),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use crate::assembler::assemble;
+    use super::*;
+
+    // One of every instruction the assembler encodes in a single word, in its canonical form.
+    const EVERY_INSTRUCTION: &str = "
+        nop
+        sll $t0, $t1, 3
+        srl $t0, $t1, 31
+        sra $t0, $t1, 1
+        sllv $t0, $t1, $t2
+        srlv $t0, $t1, $t2
+        srav $t0, $t1, $t2
+        movf $t0, $t1, 3
+        movt $t0, $t1, 7
+        jr $ra
+        jalr $t9
+        jalr $t0, $t9
+        syscall
+        syscall 0x1234
+        mfhi $t0
+        mthi $t0
+        mflo $t0
+        mtlo $t0
+        mult $t0, $t1
+        multu $t0, $t1
+        div $t0, $t1
+        divu $t0, $t1
+        add $t0, $t1, $t2
+        addu $t0, $t1, $t2
+        sub $t0, $t1, $t2
+        subu $t0, $t1, $t2
+        and $t0, $t1, $t2
+        or $t0, $t1, $t2
+        xor $t0, $t1, $t2
+        nor $t0, $t1, $t2
+        slt $t0, $t1, $t2
+        sltu $t0, $t1, $t2
+        tge $t0, $t1
+        tgeu $t0, $t1, 7
+        tlt $t0, $t1
+        tltu $t0, $t1
+        teq $t0, $t1
+        tne $t0, $t1
+        tgei $t0, -5
+        tgeiu $t0, 5
+        tlti $t0, 5
+        tltiu $t0, 5
+        teqi $t0, 5
+        tnei $t0, 5
+        j 0x00400000
+        jal 0x00000800
+        beq $t0, $t1, 0x00000400
+        bne $t0, $t1, 0x00000380
+        blez $t0, 0x00010000
+        bgtz $t0, 0x00000404
+        addi $t0, $t1, -1
+        addiu $sp, $sp, -32
+        slti $t0, $t1, 100
+        sltiu $t0, $t1, 100
+        andi $t0, $t1, 0xFFFF
+        ori $t0, $t1, 0x8000
+        xori $t0, $t1, 0x1234
+        lui $t0, 0x1001
+        lb $t0, -1($t1)
+        lh $t0, 2($t1)
+        lwl $t0, 3($t1)
+        lw $ra, 4($sp)
+        lbu $t0, 0($t1)
+        lhu $t0, 6($t1)
+        lwr $t0, 0($t1)
+        sb $t0, 1($t1)
+        sh $t0, 2($t1)
+        sw $ra, 28($sp)
+        ll $t0, 0($t1)
+        sc $t0, 0($t1)
+        cache 1, 8($t0)
+        pref 4, 0($t0)
+        lwc1 $f2, 4($t0)
+        swc1 $f2, 4($t0)
+        ldc1 $f2, 8($t0)
+        sdc1 $f2, 8($t0)
+        mul $t0, $t1, $t2
+        clz $t0, $t1
+        clo $t0, $t1
+        mfc0 $t0, $12
+        mfc0 $t0, $16, 1
+        mtc0 $t0, $14
+        rfe
+        eret
+        wait
+        mfc2 $t0, $5
+        cfc2 $t0, $5
+        mtc2 $t0, $5
+        ctc2 $t0, $5
+        mfc1 $t0, $f1
+        mtc1 $t0, $f1
+        add.s $f0, $f1, $f2
+        sub.d $f0, $f2, $f4
+        mul.s $f0, $f1, $f2
+        div.d $f0, $f2, $f4
+        sqrt.s $f0, $f1
+        abs.d $f0, $f2
+        mov.s $f0, $f1
+        neg.d $f0, $f2
+        round.w.s $f0, $f1
+        trunc.w.d $f0, $f2
+        ceil.w.s $f0, $f1
+        floor.w.d $f0, $f2
+        movf.s $f0, $f1, 2
+        movt.d $f0, $f2, 0
+        movz.s $f0, $f1, $t0
+        movn.d $f0, $f2, $t0
+        cvt.s.d $f0, $f2
+        cvt.s.w $f0, $f1
+        cvt.d.s $f0, $f1
+        cvt.d.w $f0, $f1
+        cvt.w.s $f0, $f1
+        c.eq.s $f0, $f1
+        c.lt.d 3, $f0, $f2
+        c.le.s 7, $f0, $f1";
+
+    // Disassembles a word and assembles the text again, at the same address.
+    fn round_trip(word: u32, address: u32, options: DisasmOptions) -> Result<u32, String>
+    {
+        let text = disassemble(word, address, options);
+        let words = assemble(&text, address).map_err(|error| format!("{}: {}", text, error))?.words;
+        match words[..]
+        {
+            [again] => Ok(again),
+            _ => Err(format!("{}: assembled to {} words", text, words.len())),
+        }
+    }
+
+    #[test]
+    fn registers_show_as_abi_names_or_numbers()
+    {
+        let numbers = DisasmOptions { abi_names: false, ..DisasmOptions::default() };
+        let add = 0x012A4020; // add $t0, $t1, $t2
+        let load = 0x8FBF0004; // lw $ra, 4($sp)
+
+        assert_eq!(disassemble(add, 0, DisasmOptions::default()), "add $t0, $t1, $t2");
+        assert_eq!(disassemble(add, 0, numbers), "add $8, $9, $10");
+        assert_eq!(disassemble(load, 0, DisasmOptions::default()), "lw $ra, 4($sp)");
+        assert_eq!(disassemble(load, 0, numbers), "lw $31, 4($29)");
+    }
+
+    #[test]
+    fn every_instruction_round_trips_through_the_assembler()
+    {
+        let numbers = DisasmOptions { abi_names: false, ..DisasmOptions::default() };
+        let words = assemble(EVERY_INSTRUCTION, 0x400).unwrap().words;
+        assert_eq!(words.len(), EVERY_INSTRUCTION.lines().count() - 1); // single words only
+
+        for (index, &word) in words.iter().enumerate()
+        {
+            let address = 0x400 + index as u32 * 4;
+            for options in [DisasmOptions::default(), numbers]
+            {
+                assert_eq!(round_trip(word, address, options), Ok(word), "0x{:08X}", word);
+            }
+        }
+    }
+
+    #[test]
+    fn random_words_round_trip_to_their_canonical_form()
+    {
+        // xorshift32, the same sequence every run
+        let mut state = 0x2545F491u32;
+        let mut random = ||
+        {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        };
+
+        let mut known = 0;
+        for _ in 0..200_000
+        {
+            let (word, address) = (random(), random() & !0b11);
+            let text = disassemble(word, address, DisasmOptions::default());
+            if text.starts_with(".word")
+            {
+                continue;
+            }
+            known += 1;
+
+            // Bits the CPU ignores may be dropped, but the instruction has to stay the same.
+            let again = round_trip(word, address, DisasmOptions::default()).unwrap();
+            assert_eq!(disassemble(again, address, DisasmOptions::default()), text, "0x{:08X}", word);
+        }
+        assert!(known > 10_000);
+    }
+}
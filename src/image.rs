@@ -0,0 +1,79 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/*
+    A program and its data in one file. Every header field is a big-endian word:
+
+    offset  field
+    0       magic "SSIM"
+    4       program base
+    8       program size in bytes
+    12      data base
+    16      data size in bytes
+    20      entry point
+    24      program bytes, followed by the data bytes
+ */
+const MAGIC: [u8; 4] = *b"SSIM";
+const HEADER_SIZE: usize = 24;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image
+{
+    pub program_base: u32,
+    pub program: Vec<u8>,
+    pub data_base: u32,
+    pub data: Vec<u8>,
+    pub entry: u32,
+}
+
+impl Image
+{
+    pub fn parse(bytes: &[u8]) -> Result<Image, String>
+    {
+        if bytes.len() < HEADER_SIZE || bytes[0..4] != MAGIC
+        {
+            return Err("Not a program image".to_string());
+        }
+
+        let field = |offset: usize|
+            u32::from_be_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]);
+        let program_size = field(8) as usize;
+        let data_size = field(16) as usize;
+
+        let program_end = HEADER_SIZE.checked_add(program_size);
+        let data_end = program_end.and_then(|end| end.checked_add(data_size));
+        let (Some(program_end), Some(data_end)) = (program_end, data_end) else
+        {
+            return Err("Image sections are too big".to_string());
+        };
+        if data_end != bytes.len()
+        {
+            return Err(format!("Image sections take {} bytes, the image has {}",
+                data_end - HEADER_SIZE, bytes.len() - HEADER_SIZE));
+        }
+
+        Ok(Image
+        {
+            program_base: field(4),
+            program: bytes[HEADER_SIZE..program_end].to_vec(),
+            data_base: field(12),
+            data: bytes[program_end..data_end].to_vec(),
+            entry: field(20),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8>
+    {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + self.program.len() + self.data.len());
+        bytes.extend(MAGIC);
+        bytes.extend(self.program_base.to_be_bytes());
+        bytes.extend((self.program.len() as u32).to_be_bytes());
+        bytes.extend(self.data_base.to_be_bytes());
+        bytes.extend((self.data.len() as u32).to_be_bytes());
+        bytes.extend(self.entry.to_be_bytes());
+        bytes.extend(&self.program);
+        bytes.extend(&self.data);
+        bytes
+    }
+}
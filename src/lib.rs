@@ -0,0 +1,10 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod assembler;
+pub mod computer;
+pub mod data_section;
+pub mod disassembler;
+pub mod image;
+pub mod memory_layout;
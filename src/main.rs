@@ -1,23 +1,199 @@
-use crate::computer::Computer;
-use crate::memory_layout::MemoryLayout;
+use std::fs;
+use std::process;
+use supersim::computer::{Computer, HaltReason};
+use supersim::disassembler::REGISTER_NAMES;
+use supersim::memory_layout::MemoryLayout;
 
-mod computer;
-mod memory_layout;
+/*
+    supersim [--program FILE] [--steps N [--dump-regs] [--dump-mem START:LENGTH]]
+
+    With --steps the program runs headless for at most N instructions and the final state
+    is printed as one JSON object, for scripts.
+ */
+#[derive(Default)]
+struct Options
+{
+    program: Option<String>,
+    steps: Option<u64>,
+    dump_registers: bool,
+    dump_memory: Option<(u32, u32)>,
+}
+
+fn parse_number(text: &str) -> Result<u64, String>
+{
+    let parsed = match text.strip_prefix("0x")
+    {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| format!("Not a number: {}", text))
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<Options, String>
+{
+    let mut options = Options::default();
+    while let Some(arg) = args.next()
+    {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+        match arg.as_str()
+        {
+            "--program" => options.program = Some(value()?),
+            "--steps" => options.steps = Some(parse_number(&value()?)?),
+            "--dump-regs" => options.dump_registers = true,
+            "--dump-mem" =>
+            {
+                let range = value()?;
+                let (start, length) = range.split_once(':')
+                    .ok_or(format!("Expected START:LENGTH, got {}", range))?;
+                options.dump_memory = Some((parse_number(start)? as u32, parse_number(length)? as u32));
+            },
+            _ => return Err(format!("Unknown option: {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+fn halt_json(reason: &HaltReason) -> String
+{
+    match reason
+    {
+        HaltReason::WindowClosed => String::from("{\"reason\": \"window_closed\"}"),
+        HaltReason::Breakpoint(address) => format!("{{\"reason\": \"breakpoint\", \"address\": {}}}", address),
+        HaltReason::Fault(fault) => format!("{{\"reason\": \"fault\", \"pc\": {}}}", fault.pc),
+        HaltReason::Exited(code) => format!("{{\"reason\": \"exited\", \"code\": {}}}", code),
+        HaltReason::Reached(address) => format!("{{\"reason\": \"reached\", \"address\": {}}}", address),
+        HaltReason::StepLimit => String::from("{\"reason\": \"step_limit\"}"),
+        HaltReason::RegisterChanged { register, .. } =>
+            format!("{{\"reason\": \"register_changed\", \"register\": {}}}", register),
+        HaltReason::ExceptionStorm => String::from("{\"reason\": \"exception_storm\"}"),
+    }
+}
+
+fn state_json(computer: &Computer, reason: &HaltReason, options: &Options) -> String
+{
+    let mut fields = vec![
+        format!("\"halt\": {}", halt_json(reason)),
+        format!("\"pc\": {}", computer.pc()),
+        format!("\"cycles\": {}", computer.cycles()),
+    ];
+
+    if options.dump_registers
+    {
+        let registers: Vec<String> = REGISTER_NAMES.iter().zip(computer.registers())
+            .map(|(name, value)| format!("\"{}\": {}", name, value))
+            .collect();
+        fields.push(format!("\"registers\": {{{}}}", registers.join(", ")));
+    }
+
+    if let Some((start, length)) = options.dump_memory
+    {
+        let memory = computer.memory();
+        let end = start.saturating_add(length).min(memory.len() as u32);
+        let bytes: String = memory[start.min(end) as usize..end as usize].iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        fields.push(format!("\"memory\": {{\"start\": {}, \"bytes\": \"{}\"}}", start, bytes));
+    }
+
+    format!("{{{}}}", fields.join(", "))
+}
 
 fn main()
 {
     const MEMORY_SIZE: u32 = 3 * 1024 * 1024;
     const SCREEN_WIDTH: u32 = 800;
     const SCREEN_HEIGHT: u32 = 600;
+    const SCREEN_SCALE: usize = 1;
+
+    let options = parse_options(std::env::args().skip(1)).unwrap_or_else(|error|
+    {
+        eprintln!("{}", error);
+        process::exit(2);
+    });
+
+    let memory_layout = MemoryLayout::standard(MEMORY_SIZE, SCREEN_WIDTH, SCREEN_HEIGHT)
+        .unwrap_or_else(|error|
+        {
+            eprintln!("{}", error);
+            process::exit(2);
+        });
+
+    let computer = match options.steps
+    {
+        Some(_) => Computer::new_headless(MEMORY_SIZE as usize, SCREEN_WIDTH as usize,
+                                          SCREEN_HEIGHT as usize, memory_layout),
+        None => Computer::new(MEMORY_SIZE as usize, SCREEN_WIDTH as usize,
+                              SCREEN_HEIGHT as usize, SCREEN_SCALE, memory_layout),
+    };
+    let mut computer = computer.unwrap_or_else(|error|
+    {
+        eprintln!("{}", error);
+        process::exit(2);
+    });
+    computer.set_host_syscalls(true);
 
-    let memory_layout = MemoryLayout
+    if let Some(path) = &options.program
     {
-        program: 0..4,
-        video_ram: 4..(4 * SCREEN_WIDTH * SCREEN_HEIGHT),
-        data: 4 * SCREEN_WIDTH * SCREEN_HEIGHT..MEMORY_SIZE,
+        let loaded = fs::read(path).map_err(|error| error.to_string())
+            .and_then(|image| computer.load_program(&image, false));
+        if let Err(error) = loaded
+        {
+            eprintln!("{}: {}", path, error);
+            process::exit(2);
+        }
+    }
+
+    let reason = match options.steps
+    {
+        Some(steps) =>
+        {
+            let reason = computer.run_for(steps);
+            println!("{}", state_json(&computer, &reason, &options));
+            reason
+        },
+        None => computer.run(),
     };
 
-    let mut computer = Computer::new(1024 * 1024 * 32, 800,
-                                     600, memory_layout);
-    computer.run();
+    match reason
+    {
+        HaltReason::Exited(code) => process::exit(code),
+        HaltReason::Fault(fault) =>
+        {
+            eprintln!("{}", fault);
+            process::exit(1);
+        },
+        _ => {},
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn batch_mode_prints_the_final_state_as_json()
+    {
+        let args = ["--steps", "10", "--dump-regs", "--dump-mem", "0x2000:4"];
+        let options = parse_options(args.iter().map(|arg| arg.to_string())).unwrap();
+
+        let layout = MemoryLayout
+        {
+            program: 0..0x1000,
+            video_ram: 0x1000..0x1400,
+            back_buffer: None,
+            data: 0x1400..0x4000,
+        };
+        let mut computer = Computer::new_headless(0x4000, 8, 8, layout).unwrap();
+        computer.load_assembly("
+            li $t0, 7
+            li $t1, 0x2000
+            sw $t0, 0($t1)").unwrap();
+        let reason = computer.run_for(options.steps.unwrap());
+
+        let json = state_json(&computer, &reason, &options);
+        assert!(json.starts_with("{\"halt\": {\"reason\": \"step_limit\"}, \"pc\": 40, "), "{}", json);
+        assert!(json.contains("\"$t0\": 7, \"$t1\": 8192,"), "{}", json);
+        assert!(json.ends_with("\"memory\": {\"start\": 8192, \"bytes\": \"00000007\"}}"), "{}", json);
+    }
 }
@@ -1,8 +1,73 @@
-use std::ops::Range;
+use alloc::format;
+use alloc::string::String;
+use core::ops::Range;
 
-pub(crate) struct MemoryLayout
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryLayout
 {
-    pub(crate) program: Range<u32>,
-    pub(crate) video_ram: Range<u32>,
-    pub(crate) data: Range<u32>,
-}
\ No newline at end of file
+    pub program: Range<u32>,
+    pub video_ram: Range<u32>,
+    pub back_buffer: Option<Range<u32>>, // second page for double buffering
+    pub data: Range<u32>,
+}
+
+const PROGRAM_SIZE: u32 = 64 * 1024;
+const VRAM_HEADER_SIZE: u32 = 32; // the display header, see computer::framebuffer
+
+impl MemoryLayout
+{
+    /*
+        The program at address 0 with 64 KiB for it, then the VRAM sized for a direct color
+        screen of the given size, and the data region in the rest of the memory.
+     */
+    pub fn standard(memory_size: u32, screen_width: u32, screen_height: u32) -> Result<MemoryLayout, String>
+    {
+        let too_small = || format!("{} bytes of memory can't hold a program and a {}x{} screen",
+            memory_size, screen_width, screen_height);
+
+        let vram_end = screen_width.checked_mul(screen_height)
+            .and_then(|pixels| pixels.checked_mul(4))
+            .and_then(|size| size.checked_add(PROGRAM_SIZE + VRAM_HEADER_SIZE))
+            .filter(|&end| end < memory_size)
+            .ok_or_else(too_small)?;
+
+        Ok(MemoryLayout
+        {
+            program: 0..PROGRAM_SIZE,
+            video_ram: PROGRAM_SIZE..vram_end,
+            back_buffer: None,
+            data: vram_end..memory_size,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn standard_layouts_are_contiguous_and_sized_for_the_screen()
+    {
+        for (memory_size, width, height) in [(256 * 1024, 16, 16), (3 * 1024 * 1024, 800, 600), (4 * 1024 * 1024, 1, 1)]
+        {
+            let layout = MemoryLayout::standard(memory_size, width, height).unwrap();
+
+            assert_eq!(layout.program.start, 0);
+            assert_eq!(layout.video_ram.start, layout.program.end);
+            assert_eq!(layout.data.start, layout.video_ram.end);
+            assert_eq!(layout.data.end, memory_size);
+            assert_eq!(layout.video_ram.len() as u32, VRAM_HEADER_SIZE + width * height * 4);
+            assert!(!layout.data.is_empty());
+        }
+    }
+
+    #[test]
+    fn screen_too_big_for_the_memory_is_an_error()
+    {
+        assert!(MemoryLayout::standard(256 * 1024, 800, 600).is_err());
+        assert!(MemoryLayout::standard(u32::MAX, 0x10000, 0x10000).is_err()); // the VRAM size overflows
+        // The memory must leave room for some data past the VRAM.
+        assert!(MemoryLayout::standard(PROGRAM_SIZE + VRAM_HEADER_SIZE + 4, 1, 1).is_err());
+    }
+}